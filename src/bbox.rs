@@ -24,6 +24,12 @@ impl fmt::Display for BBox {
     }
 }
 
+impl Default for BBox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BBox {
     pub fn new() -> BBox {
         BBox {
@@ -48,6 +54,52 @@ impl BBox {
             self.ury = self.ury.max(py);
         }
     }
+    // Adds a circular arc, running counterclockwise from the start angle
+    // to the end angle (in degrees), as the PostScript arc operator does.
+    // The extents are the end points, plus any of the four axis points
+    // the arc passes through.
+    pub fn addarc(&mut self, x: f32, y: f32, r: f32, start: f32, end: f32) {
+        let mut sweep = (end - start) % 360.0;
+        if sweep < 0.0 {
+            sweep += 360.0;
+        }
+        if sweep == 0.0 && end != start {
+            sweep = 360.0;
+        }
+        let point = |a: f32| {
+            let a = a.to_radians();
+            (x + r * a.cos(), y + r * a.sin())
+        };
+        let (px, py) = point(start);
+        self.addpoint(px, py);
+        let (px, py) = point(start + sweep);
+        self.addpoint(px, py);
+        // First multiple of 90 degrees at or after the start
+        let mut a = (start / 90.0).ceil() * 90.0;
+        while a < start + sweep {
+            let (px, py) = point(a);
+            self.addpoint(px, py);
+            a += 90.0;
+        }
+    }
+    // Adds a cubic Bezier curve, using the true extents of the curve
+    // (which can be much tighter than the control points).  The extremes
+    // are at the end points, or where the derivative in X or Y is zero.
+    #[allow(clippy::too_many_arguments)]
+    pub fn addcubic(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32) {
+        self.addpoint(x0, y0);
+        self.addpoint(x3, y3);
+        let eval = |t: f32, p0: f32, p1: f32, p2: f32, p3: f32| {
+            let u = 1.0 - t;
+            u * u * u * p0 + 3.0 * u * u * t * p1 + 3.0 * u * t * t * p2 + t * t * t * p3
+        };
+        for t in cubic_extrema(x0, x1, x2, x3)
+            .into_iter()
+            .chain(cubic_extrema(y0, y1, y2, y3))
+        {
+            self.addpoint(eval(t, x0, x1, x2, x3), eval(t, y0, y1, y2, y3));
+        }
+    }
     pub fn area(&self) -> f32 {
         (self.urx - self.llx) * (self.ury - self.lly)
     }
@@ -69,6 +121,21 @@ impl BBox {
         self.urx = self.urx.max(other.urx);
         self.ury = self.ury.max(other.ury);
     }
+    // The overlap of two regions; not valid if they don't overlap.
+    pub fn intersect(&self, other: &BBox) -> BBox {
+        let mut result = BBox::new();
+        if self.valid && other.valid {
+            let llx = self.llx.max(other.llx);
+            let lly = self.lly.max(other.lly);
+            let urx = self.urx.min(other.urx);
+            let ury = self.ury.min(other.ury);
+            if llx <= urx && lly <= ury {
+                result.addpoint(llx, lly);
+                result.addpoint(urx, ury);
+            }
+        }
+        result
+    }
     // Functions to split a region, giving two new regions --
     pub fn split_h(&self, bias: f32) -> (BBox, BBox) {
         let mut bottom = *self;
@@ -87,3 +154,27 @@ impl BBox {
         (left, right)
     }
 }
+
+// Parameter values in (0, 1) where one coordinate of a cubic Bezier
+// curve has zero derivative.  The derivative is a quadratic,
+// a t^2 + b t + c, with the coefficients below.
+fn cubic_extrema(p0: f32, p1: f32, p2: f32, p3: f32) -> Vec<f32> {
+    let a = -p0 + 3.0 * p1 - 3.0 * p2 + p3;
+    let b = 2.0 * (p0 - 2.0 * p1 + p2);
+    let c = p1 - p0;
+    let mut roots = Vec::new();
+    if a.abs() < 1e-9 {
+        if b.abs() > 1e-9 {
+            roots.push(-c / b);
+        }
+    } else {
+        let disc = b * b - 4.0 * a * c;
+        if disc >= 0.0 {
+            let sq = disc.sqrt();
+            roots.push((-b + sq) / (2.0 * a));
+            roots.push((-b - sq) / (2.0 * a));
+        }
+    }
+    roots.retain(|t| *t > 0.0 && *t < 1.0);
+    roots
+}
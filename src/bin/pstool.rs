@@ -3,15 +3,51 @@ use argh::FromArgs;
 /// A simple library for generating PostScript graphics.  The raw PostScript
 /// can then be converted to PDF using a tool like GhostScript.
 use pstools::*;
+use std::io::{IsTerminal, Read};
 #[derive(FromArgs)]
 /// PSTools_r Simplified PostScript generator
 struct PSArgs {
-    /// input file
+    /// input file, or - for standard input (the default when input is
+    /// piped in); JSON scenes are read from .json files, or from standard
+    /// input starting with {
     #[argh(option, short = 'i')]
     input: Option<String>,
     /// output file
     #[argh(option, short = 'o')]
     output: Option<String>,
+    /// SVG output file
+    #[argh(option, short = 's')]
+    svg: Option<String>,
+    /// JSON scene output file
+    #[argh(option, short = 'j')]
+    json: Option<String>,
+    /// interactive HTML viewer output file
+    #[argh(option, short = 'w')]
+    html: Option<String>,
+    /// output color mode: rgb (default), cmyk, or gray
+    #[argh(option, short = 'm')]
+    color_mode: Option<String>,
+    /// page size for the output: letter, a4, a3, or WIDTHxHEIGHT in
+    /// points (by default, an EPS file sized to the figure)
+    #[argh(option, short = 'p')]
+    page: Option<String>,
+    /// landscape orientation for the page
+    #[argh(switch, short = 'l')]
+    landscape: bool,
+    /// page margin in points (default 72)
+    #[argh(option)]
+    margin: Option<f32>,
+    /// largest EPS figure in points, scaled down beyond that (default
+    /// 10000, 0 for no limit)
+    #[argh(option)]
+    max_size: Option<f32>,
+    /// stop at the first error in the input file, and exit with status 1
+    /// without writing any output
+    #[argh(switch)]
+    strict: bool,
+    /// deterministic output, for files kept under version control
+    #[argh(switch, short = 'D')]
+    deterministic: bool,
     /// demo mode
     #[argh(switch, short = 'd')]
     demo: bool,
@@ -41,8 +77,83 @@ fn main() {
 
     let mut pst = PSTool::new();
 
-    if arguments.input.is_some() {
-        pst.parse(arguments.input.unwrap()).unwrap();
+    if let Some(mode) = arguments.color_mode {
+        match mode.parse::<ColorMode>() {
+            Ok(mode) => pst.set_color_mode(mode),
+            Err(err) => {
+                eprintln!("{}; use rgb, cmyk, or gray", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(size) = arguments.page {
+        let size = match size.parse::<PageSize>() {
+            Ok(size) => size,
+            Err(err) => {
+                eprintln!("{}; use letter, a4, a3, or WIDTHxHEIGHT", err);
+                std::process::exit(1);
+            }
+        };
+        let mut page = PageSetup::new(size);
+        if arguments.landscape {
+            page.orientation = PageOrientation::Landscape;
+        }
+        if let Some(margin) = arguments.margin {
+            page.margin = margin;
+        }
+        pst.set_page(page);
+    }
+    if let Some(max_size) = arguments.max_size {
+        pst.set_max_size(max_size);
+    }
+    pst.set_deterministic(arguments.deterministic);
+
+    // Commands come from standard input with "-i -", or with no input
+    // file when something is piped in (and no demo was asked for).
+    let input = match arguments.input {
+        Some(input) => Some(input),
+        None if !arguments.demo && !arguments.alt_demo && !std::io::stdin().is_terminal() => {
+            Some("-".to_string())
+        }
+        None => None,
+    };
+    if let Some(input) = input {
+        let mode = if arguments.strict {
+            ParseMode::Strict
+        } else {
+            ParseMode::Lenient
+        };
+        let result = if input == "-" {
+            let mut text = String::new();
+            if let Err(err) = std::io::stdin().read_to_string(&mut text) {
+                eprintln!("PSTools: <stdin>: {}", err);
+                std::process::exit(1);
+            }
+            if text.trim_start().starts_with('{') {
+                read_json(&mut pst, &text, "<stdin>")
+            } else {
+                pst.parse_reader(text.as_bytes(), "<stdin>", mode).map_err(messages)
+            }
+        } else if input.ends_with(".json") {
+            match std::fs::read_to_string(&input) {
+                Ok(text) => read_json(&mut pst, &text, &input),
+                Err(err) => {
+                    eprintln!("PSTools: {}: {}", input, err);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            pst.parse_with(&input, mode).map_err(messages)
+        };
+        if let Err(errors) = result {
+            for error in &errors {
+                eprintln!("PSTools: {}", error);
+            }
+            if arguments.strict {
+                std::process::exit(1);
+            }
+        }
     }
     // println!("PST has {} events", pst.len());
     if arguments.demo {
@@ -99,9 +210,31 @@ fn main() {
 
         pst.set_border(10.0);
     }
-    if pst.len() > 0 {
-        if arguments.output.is_some() {
-            pst.generate(arguments.output.unwrap()).unwrap();
+    if !pst.is_empty() {
+        if let Some(output) = arguments.output {
+            let scale = pst.output_scale();
+            if scale != 1.0 {
+                println!("Figure scaled by {}", scale);
+            }
+            if pst.uses_alpha() {
+                println!("Alpha below 1.0 is only rendered by Ghostscript 9.53 or later, run with -dALLOWPSTRANSPARENCY; otherwise it is drawn opaque");
+            }
+            pst.generate(output).unwrap();
+        }
+        if let Some(svg) = arguments.svg {
+            if let Err(err) = pst.generate_svg(svg.clone()) {
+                eprintln!("PSTools: {}: {}", svg, err);
+                std::process::exit(1);
+            }
+        }
+        if let Some(json) = arguments.json {
+            pst.generate_json(json).unwrap();
+        }
+        if let Some(html) = arguments.html {
+            if let Err(err) = pst.generate_html(html.clone()) {
+                eprintln!("PSTools: {}: {}", html, err);
+                std::process::exit(1);
+            }
         }
 
         // if arguments.output.is_some() {
@@ -117,12 +250,20 @@ fn main() {
         // } else {
         //     pst.generate("".to_string());
         // }
-        return;
     } else {
         println!("Use -h for information.");
     }
 }
 
+fn messages(errors: Vec<ParseError>) -> Vec<String> {
+    errors.iter().map(|e| e.to_string()).collect()
+}
+
+// A JSON scene stops at the first error, so there's at most one message.
+fn read_json(pst: &mut PSTool, text: &str, name: &str) -> Result<usize, Vec<String>> {
+    pst.parse_json(text).map_err(|e| vec![format!("{}: {}", name, e)])
+}
+
 fn detailed_help() {
     println!("More info and details");
 }
@@ -0,0 +1,438 @@
+// Read-only views of the recorded events, and editing them after they
+// have been added -- removal, replacement, truncation, and filtering.
+// Handles are given out lazily: the drawing calls just push events, and
+// an event without an entry in ids has the handle that would come next
+// (so the handles of the events past the end of ids run on from
+// next_id, in order).  Anything that removes or moves events brings ids
+// up to date first.
+use crate::path::PathBuilder;
+use crate::{FillPattern, Head, LineCap, LineJoin, Orientation, PSEvent, PSTag, PSTool};
+
+/// A handle to a recorded event.  Unlike an index, a handle keeps
+/// referring to the same event as events before it are removed, and is
+/// never reused within a PSTool.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct EventHandle(u64);
+
+/// A read-only view of a recorded event, with the arguments of the call
+/// that added it.  Arrows, dimensions, and charts are recorded as their
+/// parts (lines, curves, arrow tips, and text), and appear that way here.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event<'a> {
+    /// add_box
+    Box { llx: f32, lly: f32, urx: f32, ury: f32 },
+    /// add_line
+    Line { x1: f32, y1: f32, x2: f32, y2: f32 },
+    /// add_circle
+    Circle { x: f32, y: f32, radius: f32 },
+    /// add_arc
+    Arc { x: f32, y: f32, radius: f32, start: f32, end: f32 },
+    /// add_wedge
+    Wedge { x: f32, y: f32, radius: f32, start: f32, end: f32 },
+    /// add_ellipse
+    Ellipse { x: f32, y: f32, rx: f32, ry: f32, angle: f32 },
+    /// add_rounded_box
+    RoundedBox { llx: f32, lly: f32, urx: f32, ury: f32, radius: f32 },
+    /// add_curve
+    Curve { x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32 },
+    /// add_cubic (add_quadratic records a cubic)
+    Cubic { x0: f32, y0: f32, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32 },
+    /// add_path
+    Path { path: &'a PathBuilder, fill: bool, stroke: bool },
+    /// An arrow head, with its tip and back corners
+    ArrowTip { tip: (f32, f32), left: (f32, f32), right: (f32, f32), filled: bool },
+    /// set_color
+    Color { r: f32, g: f32, b: f32, a: f32 },
+    /// set_fill, or set_fill_pattern
+    Fill { fill: bool, pattern: FillPattern, spacing: f32, angle: f32 },
+    /// set_line_width
+    LineWidth { width: f32 },
+    /// set_dash
+    Dash { pattern: &'a [f32], offset: f32 },
+    /// set_line_cap
+    LineCap { cap: LineCap },
+    /// set_line_join
+    LineJoin { join: LineJoin },
+    /// set_miter_limit
+    MiterLimit { limit: f32 },
+    /// set_font
+    Font { size: f32, name: &'a str },
+    /// add_text, add_text_rotated, or add_text_centered
+    Text { x: f32, y: f32, angle: f32, text: &'a str, centered: bool },
+    /// add_comment
+    Comment { text: &'a str },
+    /// add_postscript
+    PostScript { text: &'a str },
+    /// add_gsave
+    GSave,
+    /// add_grestore
+    GRestore,
+    /// add_scale
+    Scale { factor: f32 },
+    /// add_translate
+    Translate { dx: f32, dy: f32 },
+    /// push_clip_path (push_clip_rect records a path)
+    Clip { path: &'a PathBuilder },
+    /// pop_clip
+    PopClip,
+    /// place_symbol, with the lower left corner at (x, y)
+    Place { symbol: &'a str, x: f32, y: f32, orientation: Orientation, scale: f32 },
+    /// begin_layer
+    BeginLayer { name: &'a str },
+    /// end_layer
+    EndLayer,
+    /// begin_object, with the object's ID and properties
+    BeginObject { id: &'a str, properties: &'a [(String, String)] },
+    /// end_object
+    EndObject,
+}
+
+impl PSTool {
+    fn handle_at(&self, index: usize) -> EventHandle {
+        match self.ids.get(index) {
+            Some(id) => EventHandle(*id),
+            None => EventHandle(self.next_id + (index - self.ids.len()) as u64),
+        }
+    }
+
+    fn sync_ids(&mut self) {
+        while self.ids.len() < self.events.len() {
+            self.ids.push(self.next_id);
+            self.next_id += 1;
+        }
+    }
+
+    fn view(&self, e: &PSEvent) -> Event<'_> {
+        unsafe {
+            match e.tag {
+                PSTag::B => {
+                    let b = e.event.line;
+                    Event::Box { llx: b.llx, lly: b.lly, urx: b.urx, ury: b.ury }
+                }
+                PSTag::L => {
+                    let b = e.event.line;
+                    Event::Line { x1: b.llx, y1: b.lly, x2: b.urx, y2: b.ury }
+                }
+                PSTag::R => {
+                    let b = e.event.line;
+                    Event::Circle { x: b.llx, y: b.lly, radius: b.urx }
+                }
+                PSTag::A | PSTag::WG => {
+                    let a = e.event.arc;
+                    if e.tag == PSTag::A {
+                        Event::Arc { x: a.x, y: a.y, radius: a.r, start: a.start, end: a.end }
+                    } else {
+                        Event::Wedge { x: a.x, y: a.y, radius: a.r, start: a.start, end: a.end }
+                    }
+                }
+                PSTag::E => {
+                    let el = e.event.ellipse;
+                    Event::Ellipse { x: el.x, y: el.y, rx: el.rx, ry: el.ry, angle: el.angle }
+                }
+                PSTag::RB => {
+                    let r = e.event.rbox;
+                    Event::RoundedBox { llx: r.llx, lly: r.lly, urx: r.urx, ury: r.ury, radius: r.radius }
+                }
+                PSTag::V => {
+                    let c = e.event.curve;
+                    if c.fillable {
+                        Event::Cubic { x0: c.x0, y0: c.y0, x1: c.x1, y1: c.y1, x2: c.x2, y2: c.y2, x3: c.x3, y3: c.y3 }
+                    } else {
+                        Event::Curve { x1: c.x1, y1: c.y1, x2: c.x2, y2: c.y2, x3: c.x3, y3: c.y3 }
+                    }
+                }
+                PSTag::PA => {
+                    let p = e.event.path;
+                    Event::Path { path: &self.paths[p.path], fill: p.fill, stroke: p.stroke }
+                }
+                PSTag::H => {
+                    let h = e.event.head;
+                    Event::ArrowTip {
+                        tip: (h.tip_x, h.tip_y),
+                        left: (h.lx, h.ly),
+                        right: (h.rx, h.ry),
+                        filled: h.filled,
+                    }
+                }
+                PSTag::C => {
+                    let c = e.event.color;
+                    Event::Color { r: c.r, g: c.g, b: c.b, a: c.a }
+                }
+                PSTag::F => {
+                    let f = e.event.fill;
+                    Event::Fill { fill: f.fill, pattern: f.pattern, spacing: f.spacing, angle: f.angle }
+                }
+                PSTag::W => Event::LineWidth { width: e.event.line_width },
+                PSTag::D => {
+                    let d = e.event.dash;
+                    Event::Dash { pattern: &self.dashes[d.pattern], offset: d.offset }
+                }
+                PSTag::LC => Event::LineCap { cap: e.event.line_cap },
+                PSTag::LJ => Event::LineJoin { join: e.event.line_join },
+                PSTag::ML => Event::MiterLimit { limit: e.event.miter_limit },
+                PSTag::FN => {
+                    let f = e.event.font;
+                    Event::Font { size: f.scale, name: &self.te[f.font_name] }
+                }
+                PSTag::T | PSTag::TC => {
+                    let t = e.event.text;
+                    Event::Text {
+                        x: t.x,
+                        y: t.y,
+                        angle: t.angle,
+                        text: &self.te[t.text],
+                        centered: e.tag == PSTag::TC,
+                    }
+                }
+                PSTag::N => Event::Comment { text: &self.te[e.event.text.text] },
+                PSTag::P => Event::PostScript { text: &self.te[e.event.text.text] },
+                PSTag::GS => Event::GSave,
+                PSTag::GR => Event::GRestore,
+                PSTag::S => Event::Scale { factor: e.event.scale.scale },
+                PSTag::X => {
+                    let t = e.event.translate;
+                    Event::Translate { dx: t.dx, dy: t.dy }
+                }
+                PSTag::CS => Event::Clip { path: &self.paths[e.event.path.path] },
+                PSTag::CR => Event::PopClip,
+                PSTag::I => {
+                    let i = e.event.instance;
+                    let (llx, lly) = self.placed_corner(i.symbol, i.orient, i.scale);
+                    Event::Place {
+                        symbol: &self.symbols[i.symbol].0,
+                        x: i.x + llx,
+                        y: i.y + lly,
+                        orientation: i.orient,
+                        scale: i.scale,
+                    }
+                }
+                PSTag::LB => Event::BeginLayer { name: &self.te[e.event.text.text] },
+                PSTag::LE => Event::EndLayer,
+                PSTag::OB => {
+                    let (id, properties) = &self.objects[e.event.object];
+                    Event::BeginObject { id, properties }
+                }
+                PSTag::OE => Event::EndObject,
+            }
+        }
+    }
+
+    /// Iterates over the recorded events, in order, with their handles.
+    /// ```
+    /// use pstools::{Event, PSTool};
+    /// let mut pst = PSTool::new();
+    /// pst.add_box(0.0, 0.0, 10.0, 5.0);
+    /// pst.add_text(2.0, 2.0, "INV_1".to_string());
+    /// let labels: Vec<&str> = pst
+    ///     .events()
+    ///     .filter_map(|(_, e)| match e {
+    ///         Event::Text { text, .. } => Some(text),
+    ///         _ => None,
+    ///     })
+    ///     .collect();
+    /// assert_eq!(labels, vec!["INV_1"]);
+    /// ```
+    pub fn events(&self) -> impl Iterator<Item = (EventHandle, Event<'_>)> + '_ {
+        self.events.iter().enumerate().map(|(i, e)| (self.handle_at(i), self.view(e)))
+    }
+
+    /// The event at an index (from 0, in the order added).
+    pub fn event(&self, index: usize) -> Option<Event<'_>> {
+        self.events.get(index).map(|e| self.view(e))
+    }
+
+    /// The handle of the event at an index.
+    pub fn handle(&self, index: usize) -> Option<EventHandle> {
+        if index < self.events.len() {
+            Some(self.handle_at(index))
+        } else {
+            None
+        }
+    }
+
+    /// The handle of the most recently added event -- call it just
+    /// after add_box (or any other call that records an event) to keep
+    /// track of the object.
+    pub fn last_handle(&self) -> Option<EventHandle> {
+        self.events.len().checked_sub(1).and_then(|i| self.handle(i))
+    }
+
+    /// The current index of the event with a handle, if it hasn't been
+    /// removed.
+    pub fn index_of(&self, handle: EventHandle) -> Option<usize> {
+        // Handles increase along the events, whether or not they've
+        // been given out yet.
+        if handle.0 >= self.next_id {
+            let index = self.ids.len() + (handle.0 - self.next_id) as usize;
+            return if index < self.events.len() { Some(index) } else { None };
+        }
+        self.ids.binary_search(&handle.0).ok()
+    }
+
+    /// Adds an event given as an Event, the same as the matching call
+    /// (such as add_box for Event::Box).  This can copy events from one
+    /// PSTool to another.  A Place names its symbol, which must be
+    /// defined here; if it isn't, nothing is added.
+    pub fn add_event(&mut self, event: Event) {
+        match event {
+            Event::Box { llx, lly, urx, ury } => self.add_box(llx, lly, urx, ury),
+            Event::Line { x1, y1, x2, y2 } => self.add_line(x1, y1, x2, y2),
+            Event::Circle { x, y, radius } => self.add_circle(x, y, radius),
+            Event::Arc { x, y, radius, start, end } => self.add_arc(x, y, radius, start, end),
+            Event::Wedge { x, y, radius, start, end } => self.add_wedge(x, y, radius, start, end),
+            Event::Ellipse { x, y, rx, ry, angle } => self.add_ellipse(x, y, rx, ry, angle),
+            Event::RoundedBox { llx, lly, urx, ury, radius } => self.add_rounded_box(llx, lly, urx, ury, radius),
+            Event::Curve { x1, y1, x2, y2, x3, y3 } => self.add_curve(x1, y1, x2, y2, x3, y3),
+            Event::Cubic { x0, y0, x1, y1, x2, y2, x3, y3 } => self.add_cubic(x0, y0, x1, y1, x2, y2, x3, y3),
+            Event::Path { path, fill, stroke } => self.add_path(path, fill, stroke),
+            Event::ArrowTip { tip, left, right, filled } => self.push_arrow_tip(Head {
+                tip_x: tip.0,
+                tip_y: tip.1,
+                lx: left.0,
+                ly: left.1,
+                rx: right.0,
+                ry: right.1,
+                filled,
+            }),
+            Event::Color { r, g, b, a } => self.set_color(r, g, b, a),
+            Event::Fill { fill, pattern, spacing, angle } => {
+                if fill && pattern != FillPattern::Solid {
+                    self.set_fill_pattern(pattern, spacing, angle);
+                } else {
+                    self.set_fill(fill);
+                }
+            }
+            Event::LineWidth { width } => self.set_line_width(width),
+            Event::Dash { pattern, offset } => self.set_dash(pattern.to_vec(), offset),
+            Event::LineCap { cap } => self.set_line_cap(cap),
+            Event::LineJoin { join } => self.set_line_join(join),
+            Event::MiterLimit { limit } => self.set_miter_limit(limit),
+            Event::Font { size, name } => self.set_font(size, name.to_string()),
+            Event::Text { x, y, angle, text, centered } => {
+                let tag = if centered { PSTag::TC } else { PSTag::T };
+                self.push_text(tag, x, y, angle, text.to_string());
+            }
+            Event::Comment { text } => self.add_comment(text.to_string()),
+            Event::PostScript { text } => self.add_postscript(text.to_string()),
+            Event::GSave => self.add_gsave(),
+            Event::GRestore => self.add_grestore(),
+            Event::Scale { factor } => self.add_scale(factor),
+            Event::Translate { dx, dy } => self.add_translate(dx, dy),
+            Event::Clip { path } => self.push_clip_path(path),
+            Event::PopClip => self.pop_clip(),
+            Event::Place { symbol, x, y, orientation, scale } => {
+                self.place_symbol(symbol, x, y, orientation, scale);
+            }
+            Event::BeginLayer { name } => self.begin_layer(name.to_string()),
+            Event::EndLayer => self.end_layer(),
+            Event::BeginObject { id, properties } => {
+                let properties: Vec<(&str, &str)> = properties.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+                self.begin_object(id.to_string(), &properties);
+            }
+            Event::EndObject => self.end_object(),
+        }
+    }
+
+    /// Removes the event at an index; the events after it move up one.
+    /// Returns false if there is no such event.  Removing one half of a
+    /// pair (a gsave or grestore, a clip, or a layer) leaves the other
+    /// unmatched.
+    pub fn remove_event(&mut self, index: usize) -> bool {
+        if index >= self.events.len() {
+            return false;
+        }
+        self.sync_ids();
+        self.events.remove(index);
+        self.ids.remove(index);
+        true
+    }
+
+    /// Removes the event with a handle, as remove_event.
+    pub fn remove_handle(&mut self, handle: EventHandle) -> bool {
+        match self.index_of(handle) {
+            Some(index) => self.remove_event(index),
+            None => false,
+        }
+    }
+
+    /// Replaces the event at an index with another, which keeps the
+    /// handle.  Returns false if there is no such event (or the
+    /// replacement places a symbol that isn't defined).
+    /// ```
+    /// use pstools::{Event, PSTool};
+    /// let mut pst = PSTool::new();
+    /// pst.add_box(0.0, 0.0, 10.0, 5.0);
+    /// let cell = pst.last_handle().unwrap();
+    /// pst.add_circle(20.0, 20.0, 2.0);
+    /// pst.replace_handle(cell, Event::Box { llx: 0.0, lly: 0.0, urx: 12.0, ury: 5.0 });
+    /// assert_eq!(pst.event(0), Some(Event::Box { llx: 0.0, lly: 0.0, urx: 12.0, ury: 5.0 }));
+    /// assert_eq!(pst.index_of(cell), Some(0));
+    /// ```
+    pub fn replace_event(&mut self, index: usize, event: Event) -> bool {
+        if index >= self.events.len() {
+            return false;
+        }
+        self.sync_ids();
+        let len = self.events.len();
+        self.add_event(event);
+        if self.events.len() != len + 1 {
+            return false;
+        }
+        let e = self.events.pop().unwrap();
+        self.events[index] = e;
+        true
+    }
+
+    /// Replaces the event with a handle, as replace_event.
+    pub fn replace_handle(&mut self, handle: EventHandle, event: Event) -> bool {
+        match self.index_of(handle) {
+            Some(index) => self.replace_event(index, event),
+            None => false,
+        }
+    }
+
+    /// Keeps only the first len events, dropping the rest -- take len()
+    /// as a mark before adding a group of objects, and truncate to it to
+    /// undo them.  Settings, notes, and symbols are not affected.
+    pub fn truncate(&mut self, len: usize) {
+        self.sync_ids();
+        self.events.truncate(len);
+        self.ids.truncate(len);
+    }
+
+    /// Keeps only the events for which the function returns true, such as
+    /// dropping all of the text before a final export:
+    /// ```
+    /// use pstools::{Event, PSTool};
+    /// let mut pst = PSTool::new();
+    /// pst.add_box(0.0, 0.0, 10.0, 5.0);
+    /// pst.add_text(2.0, 2.0, "INV_1".to_string());
+    /// pst.retain(|e| !matches!(e, Event::Text { .. }));
+    /// assert_eq!(pst.len(), 1);
+    /// ```
+    /// As with remove_event, dropping one half of a pair leaves the other
+    /// unmatched.
+    pub fn retain<F: FnMut(Event) -> bool>(&mut self, mut keep: F) {
+        self.sync_ids();
+        let kept: Vec<bool> = self.events.iter().map(|e| keep(self.view(e))).collect();
+        let mut i = 0;
+        self.events.retain(|_| {
+            i += 1;
+            kept[i - 1]
+        });
+        let mut i = 0;
+        self.ids.retain(|_| {
+            i += 1;
+            kept[i - 1]
+        });
+    }
+
+    /// A copy of this PSTool with only the events for which the function
+    /// returns true, as with retain.  Handles refer to the same events in
+    /// the copy.
+    pub fn filter<F: FnMut(Event) -> bool>(&self, keep: F) -> PSTool {
+        let mut copy = self.clone();
+        copy.retain(keep);
+        copy
+    }
+}
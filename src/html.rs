@@ -0,0 +1,197 @@
+// An interactive HTML viewer: the SVG output, embedded in a page with a
+// small script for zooming, panning, showing and hiding layers, object
+// tooltips, and searching for objects by ID.  Everything is in the one
+// file, so it can be mailed or archived with a report.
+use crate::svg::xml_escape;
+use crate::PSTool;
+use std::fs::File;
+use std::io::Result;
+use std::io::Write;
+
+const STYLE: &str = r#"html, body { margin: 0; height: 100%; font: 13px sans-serif; }
+body { display: flex; flex-direction: column; }
+#bar { display: flex; flex-wrap: wrap; align-items: center; gap: 6px 14px; padding: 6px 10px; background: #f4f4f4; border-bottom: 1px solid #ccc; }
+#bar h1 { font-size: 14px; margin: 0; }
+#layers:empty { display: none; }
+#layers label { margin-right: 8px; }
+#view { flex: 1; min-height: 0; background: #fff; }
+#view svg { display: block; width: 100%; height: 100%; cursor: grab; touch-action: none; user-select: none; }
+#view svg.dragging { cursor: grabbing; }
+#tip { position: fixed; z-index: 1; pointer-events: none; white-space: pre; background: #ffffe0; border: 1px solid #999; padding: 3px 6px; }
+"#;
+
+const SCRIPT: &str = r#"(function () {
+  const svg = document.querySelector('#view svg');
+  const view = svg.viewBox.baseVal;
+  const home = [view.x, view.y, view.width, view.height];
+  const tip = document.getElementById('tip');
+  const search = document.getElementById('search');
+  const found = document.getElementById('found');
+  const ns = 'http://www.w3.org/2000/svg';
+
+  // Objects are the groups with a title.  The titles become the
+  // tooltips, so that the browser doesn't show them as well.
+  const objects = [];
+  for (const title of svg.querySelectorAll('g > title')) {
+    const g = title.parentNode;
+    if (g.closest('defs')) continue;
+    g.pstTip = title.textContent;
+    g.removeChild(title);
+    objects.push(g);
+  }
+
+  // Points on the screen, in the coordinates of the view box
+  function point(x, y) {
+    return new DOMPoint(x, y).matrixTransform(svg.getScreenCTM().inverse());
+  }
+
+  function show(x, y, width, height) {
+    view.x = x;
+    view.y = y;
+    view.width = width;
+    view.height = height;
+  }
+
+  svg.addEventListener('wheel', function (ev) {
+    ev.preventDefault();
+    const p = point(ev.clientX, ev.clientY);
+    const k = Math.pow(1.002, ev.deltaMode ? ev.deltaY * 16 : ev.deltaY);
+    show(p.x - (p.x - view.x) * k, p.y - (p.y - view.y) * k, view.width * k, view.height * k);
+  }, { passive: false });
+
+  // Dragging keeps the point that was grabbed under the pointer
+  let grab = null;
+  svg.addEventListener('pointerdown', function (ev) {
+    grab = point(ev.clientX, ev.clientY);
+    svg.setPointerCapture(ev.pointerId);
+    svg.classList.add('dragging');
+    tip.hidden = true;
+  });
+  function release() {
+    grab = null;
+    svg.classList.remove('dragging');
+  }
+  svg.addEventListener('pointerup', release);
+  svg.addEventListener('pointercancel', release);
+  svg.addEventListener('pointermove', function (ev) {
+    if (grab) {
+      const p = point(ev.clientX, ev.clientY);
+      show(view.x - (p.x - grab.x), view.y - (p.y - grab.y), view.width, view.height);
+      return;
+    }
+    let g = ev.target;
+    while (g && g !== svg && !g.pstTip) g = g.parentNode;
+    if (g && g.pstTip) {
+      tip.textContent = g.pstTip;
+      tip.style.left = ev.clientX + 14 + 'px';
+      tip.style.top = ev.clientY + 14 + 'px';
+      tip.hidden = false;
+    } else {
+      tip.hidden = true;
+    }
+  });
+  svg.addEventListener('pointerleave', function () { tip.hidden = true; });
+  document.getElementById('fit').addEventListener('click', function () { show(...home); });
+
+  // Search: a box over each object with a matching ID, and Enter zooms
+  // to each match in turn
+  const marks = document.createElementNS(ns, 'g');
+  marks.setAttribute('pointer-events', 'none');
+  svg.appendChild(marks);
+  let hits = [];
+  let next = 0;
+  function highlight() {
+    marks.replaceChildren();
+    hits = [];
+    next = 0;
+    const text = search.value.trim().toLowerCase();
+    if (text) {
+      for (const g of objects) {
+        const r = g.getBoundingClientRect();
+        if (!g.dataset.id.toLowerCase().includes(text) || (r.width === 0 && r.height === 0)) continue;
+        const a = point(r.left, r.top);
+        const b = point(r.right, r.bottom);
+        const box = { x: Math.min(a.x, b.x), y: Math.min(a.y, b.y), width: Math.abs(b.x - a.x), height: Math.abs(b.y - a.y) };
+        const mark = document.createElementNS(ns, 'rect');
+        for (const key in box) mark.setAttribute(key, box[key]);
+        mark.setAttribute('fill', 'rgba(255, 200, 0, 0.3)');
+        mark.setAttribute('stroke', '#e60');
+        mark.setAttribute('stroke-width', '2');
+        mark.setAttribute('vector-effect', 'non-scaling-stroke');
+        marks.appendChild(mark);
+        hits.push(box);
+      }
+    }
+    found.textContent = text ? hits.length + (hits.length === 1 ? ' match' : ' matches') : '';
+  }
+  search.addEventListener('input', highlight);
+  search.addEventListener('keydown', function (ev) {
+    if (ev.key !== 'Enter' || hits.length === 0) return;
+    const box = hits[next];
+    next = (next + 1) % hits.length;
+    const pad = Math.max(box.width, box.height) * 0.5 + 1;
+    show(box.x - pad, box.y - pad, box.width + 2 * pad, box.height + 2 * pad);
+  });
+
+  // A check box for each layer name
+  const layers = new Map();
+  for (const g of svg.querySelectorAll('[data-layer]')) {
+    const name = g.getAttribute('data-layer');
+    if (!layers.has(name)) layers.set(name, []);
+    layers.get(name).push(g);
+  }
+  const panel = document.getElementById('layers');
+  for (const [name, groups] of layers) {
+    const label = document.createElement('label');
+    const box = document.createElement('input');
+    box.type = 'checkbox';
+    box.checked = true;
+    box.addEventListener('change', function () {
+      for (const g of groups) g.style.display = box.checked ? '' : 'none';
+      highlight();
+    });
+    label.append(box, ' ', name || '(unnamed)');
+    panel.appendChild(label);
+  }
+})();
+"#;
+
+impl PSTool {
+    /// Generates an interactive HTML page showing the events, for figures
+    /// too large to read as a static image.  The page embeds the SVG
+    /// output (see generate_svg), and needs nothing else to be viewed:
+    /// the mouse wheel zooms, dragging pans, and there is a check box to
+    /// show or hide each layer.  Hovering over an object shows its ID and
+    /// properties, and the search box highlights the objects with an ID
+    /// containing the text (Enter zooms to each in turn).  Returns the
+    /// number of events processed; an error creating or writing the file
+    /// is returned.
+    pub fn generate_html(&self, filepath: String) -> Result<usize> {
+        let mut f = File::create(filepath)?;
+
+        let title = xml_escape(self.title.as_deref().unwrap_or("PSTools"));
+        writeln!(&mut f, "<!DOCTYPE html>")?;
+        writeln!(&mut f, "<html>")?;
+        writeln!(&mut f, "<head>")?;
+        writeln!(&mut f, "<meta charset=\"utf-8\">")?;
+        writeln!(&mut f, "<title>{}</title>", title)?;
+        write!(&mut f, "<style>\n{}</style>\n", STYLE)?;
+        writeln!(&mut f, "</head>")?;
+        writeln!(&mut f, "<body>")?;
+        writeln!(&mut f, "<div id=\"bar\">")?;
+        writeln!(&mut f, "<h1>{}</h1>", title)?;
+        writeln!(&mut f, "<span><input id=\"search\" type=\"search\" placeholder=\"Find objects\"> <span id=\"found\"></span></span>")?;
+        writeln!(&mut f, "<button id=\"fit\" type=\"button\">Fit</button>")?;
+        writeln!(&mut f, "<span id=\"layers\"></span>")?;
+        writeln!(&mut f, "</div>")?;
+        writeln!(&mut f, "<div id=\"view\">")?;
+        self.write_svg(&mut f, "width=\"100%\" height=\"100%\"")?;
+        writeln!(&mut f, "</div>")?;
+        writeln!(&mut f, "<div id=\"tip\" hidden></div>")?;
+        write!(&mut f, "<script>\n{}</script>\n", SCRIPT)?;
+        writeln!(&mut f, "</body>")?;
+        writeln!(&mut f, "</html>")?;
+
+        Ok(self.events.len())
+    }
+}
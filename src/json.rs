@@ -0,0 +1,1220 @@
+// JSON scene descriptions, for tools that would rather write JSON than the
+// text command format.  A scene holds the settings, notes, symbols, and
+// events of a PSTool; each event is an object with a "type" and the
+// arguments of the matching PSTool call, and layers nest their events.
+// The JSON is read and written here directly (the crate has no JSON
+// dependency).  The schema is in doc/scene.schema.json.
+use crate::path::{FillRule, PathBuilder, PathSegment};
+use crate::{
+    check_dash, ArrowHead, ColorMode, FillPattern, Head, LineCap, LineJoin, Orientation, PSTag, PSTool, PageOrientation, PageSetup,
+    PageSize,
+};
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+
+/// An error in a JSON scene.  The location is the line and column (from
+/// one) for text that isn't valid JSON, or the path to the value, such as
+/// `scene.events[3].radius`, for JSON that doesn't fit the scene schema.
+#[derive(Clone, PartialEq, Debug)]
+pub struct JsonError {
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+type JsonResult<T> = std::result::Result<T, JsonError>;
+
+// The scene format version written, and the newest one read
+const VERSION: usize = 1;
+
+// Limit on nested arrays and objects, against running out of stack
+const MAX_NESTING: usize = 256;
+
+#[derive(Clone, Debug)]
+enum Json {
+    Null,
+    Bool(bool),
+    // Numbers are kept as text, so that an f32 written out reads back
+    // exactly.
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+// Reads JSON text, keeping track of the line and column for errors.
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Reader {
+    fn new(text: &str) -> Reader {
+        Reader {
+            chars: text.chars().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn error(&self, message: &str) -> JsonError {
+        JsonError {
+            location: format!("line {}, column {}", self.line, self.column),
+            message: message.to_string(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.bump();
+        }
+    }
+
+    fn expect(&mut self, c: char) -> JsonResult<()> {
+        self.skip_space();
+        if self.peek() == Some(c) {
+            self.bump();
+            Ok(())
+        } else {
+            Err(self.found(&format!("'{}'", c)))
+        }
+    }
+
+    // An error for something other than what was expected
+    fn found(&self, expected: &str) -> JsonError {
+        match self.peek() {
+            Some(c) => self.error(&format!("expected {}, found '{}'", expected, c)),
+            None => self.error(&format!("expected {}, found the end of the text", expected)),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> JsonResult<Json> {
+        if depth > MAX_NESTING {
+            return Err(self.error("arrays and objects are nested too deeply"));
+        }
+        self.skip_space();
+        match self.peek() {
+            Some('{') => {
+                self.bump();
+                let mut fields = Vec::new();
+                self.skip_space();
+                if self.peek() == Some('}') {
+                    self.bump();
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_space();
+                    if self.peek() != Some('"') {
+                        return Err(self.found("a field name"));
+                    }
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    let value = self.value(depth + 1)?;
+                    fields.push((key, value));
+                    self.skip_space();
+                    match self.peek() {
+                        Some(',') => {
+                            self.bump();
+                        }
+                        Some('}') => {
+                            self.bump();
+                            return Ok(Json::Object(fields));
+                        }
+                        _ => return Err(self.found("',' or '}'")),
+                    }
+                }
+            }
+            Some('[') => {
+                self.bump();
+                let mut items = Vec::new();
+                self.skip_space();
+                if self.peek() == Some(']') {
+                    self.bump();
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_space();
+                    match self.peek() {
+                        Some(',') => {
+                            self.bump();
+                        }
+                        Some(']') => {
+                            self.bump();
+                            return Ok(Json::Array(items));
+                        }
+                        _ => return Err(self.found("',' or ']'")),
+                    }
+                }
+            }
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            Some('-' | '0'..='9') => self.number(),
+            _ => Err(self.found("a value")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> JsonResult<Json> {
+        for c in word.chars() {
+            if self.peek() != Some(c) {
+                return Err(self.found(&format!("'{}'", word)));
+            }
+            self.bump();
+        }
+        Ok(value)
+    }
+
+    fn digits(&mut self, text: &mut String) -> JsonResult<()> {
+        if !matches!(self.peek(), Some('0'..='9')) {
+            return Err(self.found("a digit"));
+        }
+        while let Some(c @ '0'..='9') = self.peek() {
+            text.push(c);
+            self.bump();
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> JsonResult<Json> {
+        let mut text = String::new();
+        if self.peek() == Some('-') {
+            text.push('-');
+            self.bump();
+        }
+        if self.peek() == Some('0') {
+            text.push('0');
+            self.bump();
+        } else {
+            self.digits(&mut text)?;
+        }
+        if self.peek() == Some('.') {
+            text.push('.');
+            self.bump();
+            self.digits(&mut text)?;
+        }
+        if let Some(e @ ('e' | 'E')) = self.peek() {
+            text.push(e);
+            self.bump();
+            if let Some(sign @ ('+' | '-')) = self.peek() {
+                text.push(sign);
+                self.bump();
+            }
+            self.digits(&mut text)?;
+        }
+        Ok(Json::Number(text))
+    }
+
+    fn string(&mut self) -> JsonResult<String> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error("unterminated string")),
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.unicode_escape()?,
+                        _ => return Err(self.error("unknown escape in string")),
+                    };
+                    s.push(c);
+                }
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(self.error("control character in string (use an escape)"));
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> JsonResult<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.bump().and_then(|c| c.to_digit(16)) {
+                Some(d) => code = code * 16 + d,
+                None => return Err(self.error("expected four hex digits after \\u")),
+            }
+        }
+        Ok(code)
+    }
+
+    // The character for a \u escape; characters outside the Basic
+    // Multilingual Plane are written as a surrogate pair.
+    fn unicode_escape(&mut self) -> JsonResult<char> {
+        let high = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if self.bump() != Some('\\') || self.bump() != Some('u') {
+                return Err(self.error("unpaired surrogate in \\u escape"));
+            }
+            let low = self.hex4()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(self.error("unpaired surrogate in \\u escape"));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate in \\u escape"))
+    }
+}
+
+fn parse(text: &str) -> JsonResult<Json> {
+    let mut reader = Reader::new(text);
+    let value = reader.value(0)?;
+    reader.skip_space();
+    if reader.peek().is_some() {
+        return Err(reader.found("the end of the text"));
+    }
+    Ok(value)
+}
+
+fn quote(s: &str) -> String {
+    let mut q = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => q.push_str("\\\""),
+            '\\' => q.push_str("\\\\"),
+            '\n' => q.push_str("\\n"),
+            '\r' => q.push_str("\\r"),
+            '\t' => q.push_str("\\t"),
+            c if (c as u32) < 0x20 => q.push_str(&format!("\\u{:04x}", c as u32)),
+            c => q.push(c),
+        }
+    }
+    q.push('"');
+    q
+}
+
+// Whether a value is written over several lines -- arrays of objects (the
+// events, symbols, and layers) are, with one object per line.
+fn spread(value: &Json) -> bool {
+    match value {
+        Json::Array(items) => items.iter().any(|i| matches!(i, Json::Object(_)) || spread(i)),
+        Json::Object(fields) => fields.iter().any(|(_, v)| spread(v)),
+        _ => false,
+    }
+}
+
+fn compact(value: &Json) -> String {
+    match value {
+        Json::Null => "null".to_string(),
+        Json::Bool(b) => b.to_string(),
+        Json::Number(n) => n.clone(),
+        Json::String(s) => quote(s),
+        Json::Array(items) => format!("[{}]", items.iter().map(compact).collect::<Vec<_>>().join(", ")),
+        Json::Object(fields) => format!(
+            "{{{}}}",
+            fields
+                .iter()
+                .map(|(k, v)| format!("{}: {}", quote(k), compact(v)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn write_value(w: &mut dyn Write, value: &Json, indent: usize) -> std::io::Result<()> {
+    if !spread(value) {
+        return write!(w, "{}", compact(value));
+    }
+    let pad = " ".repeat(indent + 2);
+    match value {
+        Json::Array(items) => {
+            writeln!(w, "[")?;
+            for (i, item) in items.iter().enumerate() {
+                write!(w, "{}", pad)?;
+                write_value(w, item, indent + 2)?;
+                writeln!(w, "{}", if i + 1 < items.len() { "," } else { "" })?;
+            }
+            write!(w, "{}]", " ".repeat(indent))
+        }
+        Json::Object(fields) => {
+            writeln!(w, "{{")?;
+            for (i, (key, item)) in fields.iter().enumerate() {
+                write!(w, "{}{}: ", pad, quote(key))?;
+                write_value(w, item, indent + 2)?;
+                writeln!(w, "{}", if i + 1 < fields.len() { "," } else { "" })?;
+            }
+            write!(w, "{}}}", " ".repeat(indent))
+        }
+        _ => unreachable!(),
+    }
+}
+
+// Numbers that aren't finite have no JSON form, and are written as null
+// (which reads back as an error).
+fn num(x: f32) -> Json {
+    if x.is_finite() {
+        Json::Number(x.to_string())
+    } else {
+        Json::Null
+    }
+}
+
+fn string(s: &str) -> Json {
+    Json::String(s.to_string())
+}
+
+fn nums(values: &[f32]) -> Json {
+    Json::Array(values.iter().map(|v| num(*v)).collect())
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+fn event(kind: &str, mut fields: Vec<(&str, Json)>) -> Json {
+    fields.insert(0, ("type", string(kind)));
+    object(fields)
+}
+
+fn segments(path: &PathBuilder) -> Json {
+    Json::Array(
+        path.segments()
+            .iter()
+            .map(|seg| {
+                let (letter, values) = match *seg {
+                    PathSegment::MoveTo(x, y) => ("M", vec![x, y]),
+                    PathSegment::LineTo(x, y) => ("L", vec![x, y]),
+                    PathSegment::CurveTo(x1, y1, x2, y2, x3, y3) => ("C", vec![x1, y1, x2, y2, x3, y3]),
+                    PathSegment::Arc(x, y, r, start, end) => ("A", vec![x, y, r, start, end]),
+                    PathSegment::ClosePath => ("Z", vec![]),
+                };
+                let mut items = vec![string(letter)];
+                items.extend(values.into_iter().map(num));
+                Json::Array(items)
+            })
+            .collect(),
+    )
+}
+
+fn number(value: &Json, location: &str) -> JsonResult<f32> {
+    match value {
+        Json::Number(text) => text.parse::<f32>().map_err(|_| JsonError {
+            location: location.to_string(),
+            message: format!("bad number {}", text),
+        }),
+        _ => Err(JsonError {
+            location: location.to_string(),
+            message: "expected a number".to_string(),
+        }),
+    }
+}
+
+fn text(value: &Json, location: &str) -> JsonResult<String> {
+    match value {
+        Json::String(s) => Ok(s.clone()),
+        _ => Err(JsonError {
+            location: location.to_string(),
+            message: "expected a string".to_string(),
+        }),
+    }
+}
+
+// The fields of a JSON object, with the location for error messages.
+struct Fields<'a> {
+    fields: &'a [(String, Json)],
+    location: String,
+}
+
+impl<'a> Fields<'a> {
+    fn new(value: &'a Json, location: String) -> JsonResult<Fields<'a>> {
+        match value {
+            Json::Object(fields) => Ok(Fields { fields, location }),
+            _ => Err(JsonError {
+                location,
+                message: "expected an object".to_string(),
+            }),
+        }
+    }
+
+    fn at(&self, key: &str) -> String {
+        format!("{}.{}", self.location, key)
+    }
+
+    fn error(&self, key: &str, message: String) -> JsonError {
+        JsonError {
+            location: self.at(key),
+            message,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&'a Json> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn missing(&self, key: &str) -> JsonError {
+        JsonError {
+            location: self.location.clone(),
+            message: format!("missing {}", key),
+        }
+    }
+
+    fn required(&self, key: &str) -> JsonResult<&'a Json> {
+        self.get(key).ok_or_else(|| self.missing(key))
+    }
+
+    fn num(&self, key: &str) -> JsonResult<f32> {
+        number(self.required(key)?, &self.at(key))
+    }
+
+    fn num_or(&self, key: &str, default: f32) -> JsonResult<f32> {
+        match self.get(key) {
+            Some(value) => number(value, &self.at(key)),
+            None => Ok(default),
+        }
+    }
+
+    fn string(&self, key: &str) -> JsonResult<String> {
+        text(self.required(key)?, &self.at(key))
+    }
+
+    fn opt_string(&self, key: &str) -> JsonResult<Option<String>> {
+        match self.get(key) {
+            Some(value) => Ok(Some(text(value, &self.at(key))?)),
+            None => Ok(None),
+        }
+    }
+
+    fn bool_or(&self, key: &str, default: bool) -> JsonResult<bool> {
+        match self.get(key) {
+            Some(Json::Bool(b)) => Ok(*b),
+            Some(_) => Err(self.error(key, "expected true or false".to_string())),
+            None => Ok(default),
+        }
+    }
+
+    // A keyword, such as a line cap or an orientation
+    fn keyword<T: FromStr<Err = String>>(&self, key: &str, default: Option<T>) -> JsonResult<T> {
+        match self.opt_string(key)? {
+            Some(word) => word.parse::<T>().map_err(|err| self.error(key, err)),
+            None => default.ok_or_else(|| self.missing(key)),
+        }
+    }
+
+    fn array(&self, key: &str) -> JsonResult<&'a [Json]> {
+        match self.get(key) {
+            Some(Json::Array(items)) => Ok(items),
+            Some(_) => Err(self.error(key, "expected an array".to_string())),
+            None => Ok(&[]),
+        }
+    }
+
+    fn nums(&self, key: &str) -> JsonResult<Vec<f32>> {
+        self.array(key)?
+            .iter()
+            .enumerate()
+            .map(|(i, v)| number(v, &format!("{}[{}]", self.at(key), i)))
+            .collect()
+    }
+
+    fn point(&self, key: &str) -> JsonResult<(f32, f32)> {
+        self.required(key)?;
+        match self.nums(key)?[..] {
+            [x, y] => Ok((x, y)),
+            _ => Err(self.error(key, "expected [x, y]".to_string())),
+        }
+    }
+
+    fn path(&self) -> JsonResult<PathBuilder> {
+        let mut path = PathBuilder::new();
+        path.set_fill_rule(self.keyword("fill_rule", Some(FillRule::NonZero))?);
+        self.required("segments")?;
+        for (i, seg) in self.array("segments")?.iter().enumerate() {
+            let location = format!("{}[{}]", self.at("segments"), i);
+            let bad = |message: &str| JsonError {
+                location: location.clone(),
+                message: message.to_string(),
+            };
+            let items = match seg {
+                Json::Array(items) if !items.is_empty() => items,
+                _ => return Err(bad("expected a segment, such as [\"M\", x, y]")),
+            };
+            let letter = text(&items[0], &location)?;
+            let v = items[1..]
+                .iter()
+                .enumerate()
+                .map(|(j, item)| number(item, &format!("{}[{}]", location, j + 1)))
+                .collect::<JsonResult<Vec<f32>>>()?;
+            let count = match letter.as_str() {
+                "M" | "L" => 2,
+                "C" => 6,
+                "Q" => 4,
+                "A" => 5,
+                "Z" => 0,
+                _ => return Err(bad(&format!("unknown segment {} (expected M, L, C, Q, A, or Z)", letter))),
+            };
+            if v.len() != count {
+                return Err(bad(&format!("{} takes {} numbers", letter, count)));
+            }
+            match letter.as_str() {
+                "M" => path.move_to(v[0], v[1]),
+                "L" => path.line_to(v[0], v[1]),
+                "C" => path.curve_to(v[0], v[1], v[2], v[3], v[4], v[5]),
+                "Q" => path.quad_to(v[0], v[1], v[2], v[3]),
+                "A" => path.arc(v[0], v[1], v[2], v[3], v[4]),
+                _ => path.close_path(),
+            };
+        }
+        Ok(path)
+    }
+}
+
+impl PSTool {
+    /// Writes the scene -- settings, notes, symbols, and events -- as
+    /// JSON, in the format that parse_json reads (described in
+    /// doc/scene.schema.json).  Each event is an object with a "type",
+    /// and layers and objects hold their events in a nested list.  Arrows, dimensions,
+    /// and charts are written as their parts, as with write_commands.
+    pub fn write_json(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        let mut scene = vec![("format", string("pstools-scene")), ("version", Json::Number(VERSION.to_string()))];
+        scene.extend(self.json_scene());
+        write_value(w, &object(scene), 0)?;
+        writeln!(w)
+    }
+
+    /// Writes the scene as JSON to a file, as write_json.
+    pub fn generate_json(&self, filepath: String) -> std::io::Result<()> {
+        let mut f = File::create(filepath)?;
+        self.write_json(&mut f)
+    }
+
+    fn json_scene(&self) -> Vec<(&'static str, Json)> {
+        let mut scene = Vec::new();
+        let settings = self.json_settings();
+        if !settings.is_empty() {
+            scene.push(("settings", object(settings)));
+        }
+        if !self.notes.is_empty() {
+            scene.push(("notes", Json::Array(self.notes.iter().map(|n| string(n)).collect())));
+        }
+        if !self.symbols.is_empty() {
+            let symbols = self
+                .symbols
+                .iter()
+                .map(|(name, symbol)| object(vec![("name", string(name)), ("scene", object(symbol.json_scene()))]))
+                .collect();
+            scene.push(("symbols", Json::Array(symbols)));
+        }
+        scene.push(("events", Json::Array(self.json_events())));
+        scene
+    }
+
+    // The settings that differ from the defaults
+    fn json_settings(&self) -> Vec<(&'static str, Json)> {
+        let mut settings = Vec::new();
+        if let Some(title) = &self.title {
+            settings.push(("title", string(title)));
+        }
+        if let Some(creator) = &self.creator {
+            settings.push(("creator", string(creator)));
+        }
+        if let Some(user) = &self.user {
+            settings.push(("for", string(user)));
+        }
+        if self.deterministic {
+            settings.push(("deterministic", Json::Bool(true)));
+        }
+        if self.color_mode != ColorMode::Rgb {
+            settings.push(("color_mode", string(&self.color_mode.to_string())));
+        }
+        if let Some(page) = self.page {
+            settings.push((
+                "page",
+                object(vec![
+                    ("size", string(&page.size.to_string())),
+                    ("orientation", string(&page.orientation.to_string())),
+                    ("margin", num(page.margin)),
+                    ("center", Json::Bool(page.center)),
+                ]),
+            ));
+        }
+        if self.max_size != 10000.0 {
+            settings.push(("max_size", num(self.max_size)));
+        }
+        if self.border != 0.0 {
+            settings.push(("border", num(self.border)));
+        }
+        if self.bbox.valid {
+            let b = &self.bbox;
+            settings.push(("bounds", nums(&[b.llx, b.lly, b.urx, b.ury])));
+        }
+        settings
+    }
+
+    // The events, with the events of each layer gathered into the layer
+    // object.
+    fn json_events(&self) -> Vec<Json> {
+        // The enclosing lists of events, with the layer or object (its
+        // type and fields) that each open list belongs to
+        let mut open = Vec::new();
+        let mut events = Vec::new();
+        for e in &self.events {
+            let mut json = None;
+            unsafe {
+                if e.tag == PSTag::B || e.tag == PSTag::L {
+                    let b = e.event.line;
+                    json = Some(if e.tag == PSTag::B {
+                        event(
+                            "box",
+                            vec![("llx", num(b.llx)), ("lly", num(b.lly)), ("urx", num(b.urx)), ("ury", num(b.ury))],
+                        )
+                    } else {
+                        event(
+                            "line",
+                            vec![("x1", num(b.llx)), ("y1", num(b.lly)), ("x2", num(b.urx)), ("y2", num(b.ury))],
+                        )
+                    });
+                }
+                if e.tag == PSTag::R {
+                    let b = e.event.line;
+                    json = Some(event("circle", vec![("x", num(b.llx)), ("y", num(b.lly)), ("radius", num(b.urx))]));
+                }
+                if e.tag == PSTag::A || e.tag == PSTag::WG {
+                    let a = e.event.arc;
+                    json = Some(event(
+                        if e.tag == PSTag::A { "arc" } else { "wedge" },
+                        vec![
+                            ("x", num(a.x)),
+                            ("y", num(a.y)),
+                            ("radius", num(a.r)),
+                            ("start", num(a.start)),
+                            ("end", num(a.end)),
+                        ],
+                    ));
+                }
+                if e.tag == PSTag::E {
+                    let el = e.event.ellipse;
+                    json = Some(event(
+                        "ellipse",
+                        vec![
+                            ("x", num(el.x)),
+                            ("y", num(el.y)),
+                            ("rx", num(el.rx)),
+                            ("ry", num(el.ry)),
+                            ("angle", num(el.angle)),
+                        ],
+                    ));
+                }
+                if e.tag == PSTag::RB {
+                    let r = e.event.rbox;
+                    json = Some(event(
+                        "rounded_box",
+                        vec![
+                            ("llx", num(r.llx)),
+                            ("lly", num(r.lly)),
+                            ("urx", num(r.urx)),
+                            ("ury", num(r.ury)),
+                            ("radius", num(r.radius)),
+                        ],
+                    ));
+                }
+                if e.tag == PSTag::V {
+                    let c = e.event.curve;
+                    json = Some(if c.fillable {
+                        event(
+                            "cubic",
+                            vec![
+                                ("x0", num(c.x0)),
+                                ("y0", num(c.y0)),
+                                ("x1", num(c.x1)),
+                                ("y1", num(c.y1)),
+                                ("x2", num(c.x2)),
+                                ("y2", num(c.y2)),
+                                ("x3", num(c.x3)),
+                                ("y3", num(c.y3)),
+                            ],
+                        )
+                    } else {
+                        event(
+                            "curve",
+                            vec![
+                                ("x1", num(c.x1)),
+                                ("y1", num(c.y1)),
+                                ("x2", num(c.x2)),
+                                ("y2", num(c.y2)),
+                                ("x3", num(c.x3)),
+                                ("y3", num(c.y3)),
+                            ],
+                        )
+                    });
+                }
+                if e.tag == PSTag::H {
+                    let h = e.event.head;
+                    json = Some(event(
+                        "arrow_tip",
+                        vec![
+                            ("tip", nums(&[h.tip_x, h.tip_y])),
+                            ("left", nums(&[h.lx, h.ly])),
+                            ("right", nums(&[h.rx, h.ry])),
+                            ("filled", Json::Bool(h.filled)),
+                        ],
+                    ));
+                }
+                if e.tag == PSTag::PA {
+                    let p = e.event.path;
+                    let path = &self.paths[p.path];
+                    json = Some(event(
+                        "path",
+                        vec![
+                            ("fill", Json::Bool(p.fill)),
+                            ("stroke", Json::Bool(p.stroke)),
+                            ("fill_rule", string(&path.fill_rule.to_string())),
+                            ("segments", segments(path)),
+                        ],
+                    ));
+                }
+                if e.tag == PSTag::CS {
+                    let path = &self.paths[e.event.path.path];
+                    json = Some(event(
+                        "clip",
+                        vec![("fill_rule", string(&path.fill_rule.to_string())), ("segments", segments(path))],
+                    ));
+                }
+                if e.tag == PSTag::CR {
+                    json = Some(event("pop_clip", vec![]));
+                }
+                if e.tag == PSTag::I {
+                    let i = e.event.instance;
+                    // As with place_symbol, (x, y) is the lower left corner
+                    let (llx, lly) = self.placed_corner(i.symbol, i.orient, i.scale);
+                    json = Some(event(
+                        "place",
+                        vec![
+                            ("symbol", Json::Number(i.symbol.to_string())),
+                            ("x", num(i.x + llx)),
+                            ("y", num(i.y + lly)),
+                            ("orientation", string(&i.orient.to_string())),
+                            ("scale", num(i.scale)),
+                        ],
+                    ));
+                }
+                if e.tag == PSTag::C {
+                    let c = e.event.color;
+                    let mut fields = vec![("r", num(c.r)), ("g", num(c.g)), ("b", num(c.b))];
+                    if c.a != 1.0 {
+                        fields.push(("a", num(c.a)));
+                    }
+                    json = Some(event("color", fields));
+                }
+                if e.tag == PSTag::F {
+                    let fill = e.event.fill;
+                    let mut fields = vec![("fill", Json::Bool(fill.fill))];
+                    if fill.fill && fill.pattern != FillPattern::Solid {
+                        fields.push(("pattern", string(&fill.pattern.to_string())));
+                        fields.push(("spacing", num(fill.spacing)));
+                        fields.push(("angle", num(fill.angle)));
+                    }
+                    json = Some(event("fill", fields));
+                }
+                if e.tag == PSTag::W {
+                    json = Some(event("line_width", vec![("width", num(e.event.line_width))]));
+                }
+                if e.tag == PSTag::D {
+                    let d = e.event.dash;
+                    json = Some(event(
+                        "dash",
+                        vec![("pattern", nums(&self.dashes[d.pattern])), ("offset", num(d.offset))],
+                    ));
+                }
+                if e.tag == PSTag::LC {
+                    json = Some(event("line_cap", vec![("cap", string(&e.event.line_cap.to_string()))]));
+                }
+                if e.tag == PSTag::LJ {
+                    json = Some(event("line_join", vec![("join", string(&e.event.line_join.to_string()))]));
+                }
+                if e.tag == PSTag::ML {
+                    json = Some(event("miter_limit", vec![("limit", num(e.event.miter_limit))]));
+                }
+                if e.tag == PSTag::FN {
+                    let font = e.event.font;
+                    json = Some(event(
+                        "font",
+                        vec![("size", num(font.scale)), ("name", string(&self.te[font.font_name]))],
+                    ));
+                }
+                if e.tag == PSTag::T || e.tag == PSTag::TC {
+                    let t = e.event.text;
+                    let mut fields = vec![("x", num(t.x)), ("y", num(t.y)), ("text", string(&self.te[t.text]))];
+                    if t.angle != 0.0 {
+                        fields.push(("angle", num(t.angle)));
+                    }
+                    if e.tag == PSTag::TC {
+                        fields.push(("align", string("center")));
+                    }
+                    json = Some(event("text", fields));
+                }
+                if e.tag == PSTag::N {
+                    json = Some(event("comment", vec![("text", string(&self.te[e.event.text.text]))]));
+                }
+                if e.tag == PSTag::P {
+                    json = Some(event("postscript", vec![("text", string(&self.te[e.event.text.text]))]));
+                }
+                if e.tag == PSTag::GS {
+                    json = Some(event("gsave", vec![]));
+                }
+                if e.tag == PSTag::GR {
+                    json = Some(event("grestore", vec![]));
+                }
+                if e.tag == PSTag::S {
+                    json = Some(event("scale", vec![("factor", num(e.event.scale.scale))]));
+                }
+                if e.tag == PSTag::X {
+                    let t = e.event.translate;
+                    json = Some(event("translate", vec![("dx", num(t.dx)), ("dy", num(t.dy))]));
+                }
+                if e.tag == PSTag::LB {
+                    let fields = vec![("name", string(&self.te[e.event.text.text]))];
+                    open.push(("layer", fields, std::mem::take(&mut events)));
+                }
+                if e.tag == PSTag::OB {
+                    let (id, properties) = &self.objects[e.event.object];
+                    let properties = properties.iter().map(|(k, v)| (k.clone(), string(v))).collect();
+                    let fields = vec![("id", string(id)), ("properties", Json::Object(properties))];
+                    open.push(("object", fields, std::mem::take(&mut events)));
+                }
+                if e.tag == PSTag::LE || e.tag == PSTag::OE {
+                    match open.pop() {
+                        Some((kind, mut fields, outer)) => {
+                            fields.push(("events", Json::Array(std::mem::replace(&mut events, outer))));
+                            json = Some(event(kind, fields));
+                        }
+                        // An end without a layer or object draws as a
+                        // grestore
+                        None => json = Some(event("grestore", vec![])),
+                    }
+                }
+            }
+            if let Some(json) = json {
+                events.push(json);
+            }
+        }
+        // Layers and objects left open run to the end, and are marked so
+        // that they are read back open
+        while let Some((kind, mut fields, outer)) = open.pop() {
+            fields.push(("events", Json::Array(std::mem::replace(&mut events, outer))));
+            fields.push(("open", Json::Bool(true)));
+            events.push(event(kind, fields));
+        }
+        events
+    }
+
+    /// Reads a JSON scene, in the format written by write_json, and adds
+    /// its settings, notes, symbols, and events to this PSTool.  Returns
+    /// the number of events added.  A scene with an error stops at the
+    /// error, with everything before it added (and any layers and objects
+    /// it was in ended).
+    /// ```
+    /// let mut pst = pstools::PSTool::new();
+    /// let scene = r#"{"events": [{"type": "box", "llx": 0, "lly": 0, "urx": 10, "ury": 5}]}"#;
+    /// assert_eq!(pst.parse_json(scene), Ok(1));
+    /// assert_eq!(pst.bbox(), (-0.5, -0.5, 10.5, 5.5));
+    /// ```
+    pub fn parse_json(&mut self, json: &str) -> std::result::Result<usize, JsonError> {
+        let value = parse(json)?;
+        let scene = Fields::new(&value, "scene".to_string())?;
+        if let Some(format) = scene.opt_string("format")? {
+            if format != "pstools-scene" {
+                return Err(scene.error("format", format!("expected pstools-scene, found {}", format)));
+            }
+        }
+        if let Some(version) = scene.get("version") {
+            let version = number(version, &scene.at("version"))?;
+            if version > VERSION as f32 {
+                return Err(scene.error("version", format!("version {} is newer than this library ({})", version, VERSION)));
+            }
+        }
+        let before = self.events.len();
+        self.read_scene(&scene)?;
+        Ok(self.events.len() - before)
+    }
+
+    fn read_scene(&mut self, scene: &Fields) -> JsonResult<()> {
+        if let Some(settings) = scene.get("settings") {
+            self.read_settings(&Fields::new(settings, scene.at("settings"))?)?;
+        }
+        for (i, note) in scene.array("notes")?.iter().enumerate() {
+            self.add_note(text(note, &format!("{}[{}]", scene.at("notes"), i))?);
+        }
+        // Symbols are placed by their index in the scene, or by name
+        let first = self.symbols.len();
+        let symbols = scene.array("symbols")?;
+        for (i, symbol) in symbols.iter().enumerate() {
+            let symbol = Fields::new(symbol, format!("{}[{}]", scene.at("symbols"), i))?;
+            let name = symbol.string("name")?;
+            let mut tool = PSTool::new();
+            tool.read_scene(&Fields::new(symbol.required("scene")?, symbol.at("scene"))?)?;
+            self.define_symbol(name, &tool);
+        }
+        self.read_events(&scene.at("events"), scene.array("events")?, first..first + symbols.len())
+    }
+
+    fn read_settings(&mut self, settings: &Fields) -> JsonResult<()> {
+        if let Some(title) = settings.opt_string("title")? {
+            self.set_title(title);
+        }
+        if let Some(creator) = settings.opt_string("creator")? {
+            self.set_creator(creator);
+        }
+        if let Some(user) = settings.opt_string("for")? {
+            self.set_for(user);
+        }
+        if settings.bool_or("deterministic", false)? {
+            self.set_deterministic(true);
+        }
+        if settings.get("color_mode").is_some() {
+            self.set_color_mode(settings.keyword::<ColorMode>("color_mode", None)?);
+        }
+        if let Some(page) = settings.get("page") {
+            let page = Fields::new(page, settings.at("page"))?;
+            let mut setup = PageSetup::new(page.keyword::<PageSize>("size", None)?);
+            setup.orientation = page.keyword("orientation", Some(PageOrientation::Portrait))?;
+            setup.margin = page.num_or("margin", setup.margin)?;
+            setup.center = page.bool_or("center", setup.center)?;
+            self.set_page(setup);
+        }
+        if settings.get("max_size").is_some() {
+            self.set_max_size(settings.num("max_size")?);
+        }
+        if settings.get("border").is_some() {
+            self.set_border(settings.num("border")?);
+        }
+        if settings.get("bounds").is_some() {
+            match settings.nums("bounds")?[..] {
+                [llx, lly, urx, ury] => self.set_bounds(llx, lly, urx, ury),
+                _ => return Err(settings.error("bounds", "expected [llx, lly, urx, ury]".to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_events(&mut self, location: &str, events: &[Json], symbols: std::ops::Range<usize>) -> JsonResult<()> {
+        for (i, e) in events.iter().enumerate() {
+            let e = Fields::new(e, format!("{}[{}]", location, i))?;
+            self.read_event(&e, symbols.clone())?;
+        }
+        Ok(())
+    }
+
+    // The events of a layer or object, and its end unless it is marked
+    // open.  A layer or object with an error is ended too, so that the
+    // events read before the error are balanced.
+    fn read_nested(&mut self, e: &Fields, symbols: std::ops::Range<usize>, open: bool, end: fn(&mut PSTool)) -> JsonResult<()> {
+        let result = e.array("events").and_then(|events| self.read_events(&e.at("events"), events, symbols));
+        if result.is_err() || !open {
+            end(self);
+        }
+        result
+    }
+
+    fn read_event(&mut self, e: &Fields, symbols: std::ops::Range<usize>) -> JsonResult<()> {
+        let kind = e.string("type")?;
+        match kind.as_str() {
+            "color" => self.set_color(e.num("r")?, e.num("g")?, e.num("b")?, e.num_or("a", 1.0)?),
+            "fill" => {
+                let fill = e.bool_or("fill", true)?;
+                let pattern = e.keyword("pattern", Some(FillPattern::Solid))?;
+                if fill && pattern != FillPattern::Solid {
+                    let spacing = e.num("spacing")?;
+                    if spacing.is_nan() || spacing <= 0.0 {
+                        return Err(e.error("spacing", "the spacing must be more than zero".to_string()));
+                    }
+                    self.set_fill_pattern(pattern, spacing, e.num_or("angle", 0.0)?);
+                } else {
+                    self.set_fill(fill);
+                }
+            }
+            "line_width" => self.set_line_width(e.num("width")?),
+            "dash" => {
+                let pattern = e.nums("pattern")?;
+                check_dash(&pattern).map_err(|err| e.error("pattern", err))?;
+                self.set_dash(pattern, e.num_or("offset", 0.0)?);
+            }
+            "line_cap" => self.set_line_cap(e.keyword::<LineCap>("cap", None)?),
+            "line_join" => self.set_line_join(e.keyword::<LineJoin>("join", None)?),
+            "miter_limit" => self.set_miter_limit(e.num("limit")?),
+            "font" => self.set_font(e.num("size")?, e.string("name")?),
+            "box" => self.add_box(e.num("llx")?, e.num("lly")?, e.num("urx")?, e.num("ury")?),
+            "line" => self.add_line(e.num("x1")?, e.num("y1")?, e.num("x2")?, e.num("y2")?),
+            "circle" => self.add_circle(e.num("x")?, e.num("y")?, e.num("radius")?),
+            "arc" | "wedge" => {
+                let (x, y, r) = (e.num("x")?, e.num("y")?, e.num("radius")?);
+                let (start, end) = (e.num("start")?, e.num("end")?);
+                if kind == "arc" {
+                    self.add_arc(x, y, r, start, end);
+                } else {
+                    self.add_wedge(x, y, r, start, end);
+                }
+            }
+            "ellipse" => self.add_ellipse(e.num("x")?, e.num("y")?, e.num("rx")?, e.num("ry")?, e.num_or("angle", 0.0)?),
+            "rounded_box" => self.add_rounded_box(
+                e.num("llx")?,
+                e.num("lly")?,
+                e.num("urx")?,
+                e.num("ury")?,
+                e.num("radius")?,
+            ),
+            "curve" => self.add_curve(e.num("x1")?, e.num("y1")?, e.num("x2")?, e.num("y2")?, e.num("x3")?, e.num("y3")?),
+            "cubic" => self.add_cubic(
+                e.num("x0")?,
+                e.num("y0")?,
+                e.num("x1")?,
+                e.num("y1")?,
+                e.num("x2")?,
+                e.num("y2")?,
+                e.num("x3")?,
+                e.num("y3")?,
+            ),
+            "quadratic" => self.add_quadratic(
+                e.num("x0")?,
+                e.num("y0")?,
+                e.num("x1")?,
+                e.num("y1")?,
+                e.num("x2")?,
+                e.num("y2")?,
+            ),
+            "path" => {
+                let path = e.path()?;
+                self.add_path(&path, e.bool_or("fill", false)?, e.bool_or("stroke", true)?);
+            }
+            "arrow_style" => {
+                let head = e.keyword::<ArrowHead>("head", None)?;
+                self.set_arrow_head(head, e.num_or("length", self.arrow_length)?, e.num_or("width", self.arrow_width)?);
+            }
+            "arrow" => self.add_arrow(e.num("x1")?, e.num("y1")?, e.num("x2")?, e.num("y2")?),
+            "double_arrow" => self.add_double_arrow(e.num("x1")?, e.num("y1")?, e.num("x2")?, e.num("y2")?),
+            "curved_arrow" => {
+                self.add_curved_arrow(e.num("x1")?, e.num("y1")?, e.num("x2")?, e.num("y2")?, e.num("x3")?, e.num("y3")?)
+            }
+            "arrow_tip" => {
+                let (tip_x, tip_y) = e.point("tip")?;
+                let (lx, ly) = e.point("left")?;
+                let (rx, ry) = e.point("right")?;
+                self.push_arrow_tip(Head {
+                    tip_x,
+                    tip_y,
+                    lx,
+                    ly,
+                    rx,
+                    ry,
+                    filled: e.bool_or("filled", true)?,
+                });
+            }
+            "dimension" => self.add_dimension(
+                e.num("x1")?,
+                e.num("y1")?,
+                e.num("x2")?,
+                e.num("y2")?,
+                e.num("offset")?,
+                e.opt_string("label")?.unwrap_or_default(),
+            ),
+            "chart" => self.chart(
+                e.nums("data")?,
+                e.num_or("min", 0.0)?,
+                e.num_or("max", 0.0)?,
+                e.num("llx")?,
+                e.num("lly")?,
+                e.num("urx")?,
+                e.num("ury")?,
+            ),
+            "text" => {
+                let (x, y, t) = (e.num("x")?, e.num("y")?, e.string("text")?);
+                let angle = e.num_or("angle", 0.0)?;
+                match e.opt_string("align")?.as_deref() {
+                    None | Some("left") => self.add_text_rotated(x, y, angle, t),
+                    Some("center") => self.push_text(PSTag::TC, x, y, angle, t),
+                    Some(align) => return Err(e.error("align", format!("expected left or center, found {}", align))),
+                }
+            }
+            "comment" => self.add_comment(e.string("text")?),
+            "postscript" => self.add_postscript(e.string("text")?),
+            "gsave" => self.add_gsave(),
+            "grestore" => self.add_grestore(),
+            "scale" => self.add_scale(e.num("factor")?),
+            "translate" => self.add_translate(e.num("dx")?, e.num("dy")?),
+            "clip" => {
+                let path = e.path()?;
+                self.push_clip_path(&path);
+            }
+            "clip_rect" => self.push_clip_rect(e.num("llx")?, e.num("lly")?, e.num("urx")?, e.num("ury")?),
+            "pop_clip" => self.pop_clip(),
+            "place" => {
+                let index = match e.required("symbol")? {
+                    Json::String(name) => match self.symbols.iter().rposition(|(n, _)| n == name) {
+                        Some(index) => index,
+                        None => return Err(e.error("symbol", format!("no symbol named {}", name))),
+                    },
+                    Json::Number(n) => match n.parse::<usize>() {
+                        Ok(i) if i < symbols.len() => symbols.start + i,
+                        _ => return Err(e.error("symbol", format!("no symbol {} in the scene", n))),
+                    },
+                    _ => return Err(e.error("symbol", "expected a symbol index or name".to_string())),
+                };
+                let (x, y) = (e.num("x")?, e.num("y")?);
+                let orient = e.keyword("orientation", Some(Orientation::N))?;
+                self.place_symbol_index(index, x, y, orient, e.num_or("scale", 1.0)?);
+            }
+            "layer" => {
+                let open = e.bool_or("open", false)?;
+                self.begin_layer(e.string("name")?);
+                self.read_nested(e, symbols, open, PSTool::end_layer)?;
+            }
+            "object" => {
+                let id = e.string("id")?;
+                let mut properties = Vec::new();
+                match e.get("properties") {
+                    Some(Json::Object(fields)) => {
+                        for (key, value) in fields {
+                            properties.push((key.as_str(), text(value, &format!("{}.{}", e.at("properties"), key))?));
+                        }
+                    }
+                    Some(_) => return Err(e.error("properties", "expected an object".to_string())),
+                    None => {}
+                }
+                let properties: Vec<(&str, &str)> = properties.iter().map(|(k, v)| (*k, v.as_str())).collect();
+                let open = e.bool_or("open", false)?;
+                self.begin_object(id, &properties);
+                self.read_nested(e, symbols, open, PSTool::end_object)?;
+            }
+            _ => return Err(e.error("type", format!("unknown event type {}", kind))),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use crate::bbox::BBox;
+use crate::{PSTool, Transform};
+
+/// Lays out a multi-panel figure -- a page divided into rows and columns
+/// of panels, each holding a PSTool scaled to fit (keeping its aspect
+/// ratio), with a caption underneath and an optional frame.
+/// ```
+/// use pstools::PSTool;
+/// use pstools::layout::SubfigureLayout;
+/// let mut early = PSTool::new();
+/// early.add_box(0.0, 0.0, 100.0, 100.0);
+/// let mut late = PSTool::new();
+/// late.add_circle(50.0, 50.0, 50.0);
+/// let mut layout = SubfigureLayout::new(1, 2, 400.0, 220.0);
+/// layout.set_frames(true);
+/// let figure = layout.compose(&[(&early, "iteration 10"), (&late, "iteration 50")]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SubfigureLayout {
+    pub rows: usize,
+    pub columns: usize,
+    pub width: f32,
+    pub height: f32,
+    pub spacing: f32,
+    pub caption_height: f32,
+    pub font: String,
+    pub font_size: f32,
+    pub labels: bool,
+    pub frames: bool,
+}
+
+impl SubfigureLayout {
+    /// A layout of rows by columns panels, on a page of the given width
+    /// and height (in points), with the lower left corner at the origin.
+    pub fn new(rows: usize, columns: usize, width: f32, height: f32) -> SubfigureLayout {
+        SubfigureLayout {
+            rows: rows.max(1),
+            columns: columns.max(1),
+            width,
+            height,
+            spacing: 10.0,
+            caption_height: 16.0,
+            font: "Times-Roman".to_string(),
+            font_size: 10.0,
+            labels: true,
+            frames: false,
+        }
+    }
+    /// Space between panels, and around the edge of the page.
+    pub fn set_spacing(&mut self, spacing: f32) -> &mut SubfigureLayout {
+        self.spacing = spacing;
+        self
+    }
+    /// Font and point size for the captions, and the height of the strip
+    /// under each panel that the caption goes in.
+    pub fn set_caption_font(&mut self, font: String, size: f32, height: f32) -> &mut SubfigureLayout {
+        self.font = font;
+        self.font_size = size;
+        self.caption_height = height;
+        self
+    }
+    /// With labels on (the default), captions are prefixed with (a),
+    /// (b), (c), and so on, in reading order.
+    pub fn set_labels(&mut self, labels: bool) -> &mut SubfigureLayout {
+        self.labels = labels;
+        self
+    }
+    /// Draws a frame around each panel.
+    pub fn set_frames(&mut self, frames: bool) -> &mut SubfigureLayout {
+        self.frames = frames;
+        self
+    }
+
+    /// The regions for the panels, in reading order (left to right, then
+    /// top to bottom).  Each is a pair -- the area for the drawing, and
+    /// the strip under it for the caption.
+    pub fn panels(&self) -> Vec<(BBox, BBox)> {
+        let mut page = BBox::new();
+        page.addpoint(0.0, 0.0);
+        page.addpoint(self.width, self.height);
+
+        // Peel the rows off the top, then the columns off the left
+        let mut regions = Vec::new();
+        let mut rest = page;
+        for r in 0..self.rows {
+            let remaining = (self.rows - r) as f32;
+            let (below, row) = rest.split_h((remaining - 1.0) / remaining);
+            rest = below;
+            let mut rest_row = row;
+            for c in 0..self.columns {
+                let remaining = (self.columns - c) as f32;
+                let (cell, right) = rest_row.split_v(1.0 / remaining);
+                rest_row = right;
+                regions.push(self.split_cell(cell));
+            }
+        }
+        regions
+    }
+
+    // Trims half the spacing from each side of a cell (so that panels
+    // are a full spacing apart), and divides off the caption strip.
+    fn split_cell(&self, cell: BBox) -> (BBox, BBox) {
+        let mut inner = cell;
+        let h = self.spacing / 2.0;
+        inner.llx += h;
+        inner.lly += h;
+        inner.urx = (inner.urx - h).max(inner.llx);
+        inner.ury = (inner.ury - h).max(inner.lly);
+        let dy = inner.dy();
+        let bias = if dy > 0.0 {
+            (self.caption_height / dy).min(1.0)
+        } else {
+            0.0
+        };
+        let (caption, drawing) = inner.split_h(bias);
+        (drawing, caption)
+    }
+
+    /// Builds the figure, with each PSTool scaled to fit its panel and
+    /// centered in it, and the caption centered underneath.  Panels
+    /// beyond the rows and columns of the layout are ignored.  The
+    /// bounds of the result are set to the page.
+    pub fn compose(&self, panels: &[(&PSTool, &str)]) -> PSTool {
+        let mut figure = PSTool::new();
+        figure.set_font(self.font_size, self.font.clone());
+        for (i, ((tool, caption), (drawing, strip))) in panels.iter().zip(self.panels()).enumerate() {
+            let (llx, lly, urx, ury) = tool.bbox();
+            let (dx, dy) = (urx - llx, ury - lly);
+            if dx > 0.0 && dy > 0.0 {
+                let scale = (drawing.dx() / dx).min(drawing.dy() / dy);
+                let x = drawing.llx + (drawing.dx() - scale * dx) / 2.0 - scale * llx;
+                let y = drawing.lly + (drawing.dy() - scale * dy) / 2.0 - scale * lly;
+                figure.embed(tool, Transform::new(x, y, scale));
+            }
+            if self.frames {
+                figure.add_box(drawing.llx, drawing.lly, drawing.urx, drawing.ury);
+            }
+            let text = if self.labels {
+                format!("({}) {}", label(i), caption)
+            } else {
+                caption.to_string()
+            };
+            if !text.is_empty() {
+                let center = strip.center();
+                figure.add_text_centered(center.x, center.y - self.font_size / 3.0, text);
+            }
+        }
+        figure.set_bounds(0.0, 0.0, self.width, self.height);
+        figure
+    }
+}
+
+// Subfigure labels -- a through z, then aa, ab, and so on.
+fn label(i: usize) -> String {
+    let letter = (b'a' + (i % 26) as u8) as char;
+    if i < 26 {
+        letter.to_string()
+    } else {
+        format!("{}{}", label(i / 26 - 1), letter)
+    }
+}
@@ -25,17 +25,42 @@ pub mod bbox;
 /// Simple XY point locations
 pub mod point;
 
+/// Compound paths, built a segment at a time
+pub mod path;
+
+/// Multi-panel figures, with captions
+pub mod layout;
+
+// SVG output
+mod svg;
+
+// The interactive HTML viewer
+mod html;
+
+// The tokenizer and argument handling for the text file parser
+mod parse;
+pub use parse::{ParseError, ParseMode};
+
+// JSON scene input and output
+mod json;
+pub use json::JsonError;
+
+// Read-only views of the recorded events, and editing them
+mod events;
+pub use events::{Event, EventHandle};
+
 use bbox::BBox;
-use scan_fmt::scan_fmt;
+use path::{FillRule, PathBuilder};
 
 // use std::io::{BufRead, BufReader};
 // use std::io::{Error, ErrorKind};
 
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Result;
 use std::io::Write;
+use std::io::{BufRead, BufReader};
 
-use lineio::LineIO;
 // use std::fs::Path;
 
 #[derive(Clone, Copy)]
@@ -47,20 +72,355 @@ struct LBBox {
     pub ury: f32,
 }
 
-// Color in PostScript land is just RGB, but I'm keeping
-// an alpha channel here -- might adapt the code later to
-// generate a PNG file (where alpha could matter).
+// Colors are stored as RGB (and converted to CMYK or gray when
+// the file is written, if requested), along with an alpha channel.
+// PostScript has no alpha; the SVG output uses it directly, and
+// the PostScript output uses the Ghostscript constant alpha
+// extensions, so that a PDF made with gs has real transparency.
 #[derive(Clone, Copy)]
 struct Color {
     pub r: f32,
     pub g: f32,
     pub b: f32,
-    pub _a: f32,
+    pub a: f32,
+}
+
+impl Color {
+    // The color components for the requested output color mode, each
+    // passed through n (the rounding for deterministic output).
+    fn components(&self, mode: ColorMode, n: fn(f32) -> f32) -> String {
+        match mode {
+            ColorMode::Rgb => format!("{} {} {}", n(self.r), n(self.g), n(self.b)),
+            ColorMode::Cmyk => {
+                let (c, m, y, k) = rgb_to_cmyk(self.r, self.g, self.b);
+                format!("{} {} {} {}", n(c), n(m), n(y), n(k))
+            }
+            ColorMode::Gray => format!("{}", n(rgb_to_gray(self.r, self.g, self.b))),
+        }
+    }
+
+    // Builds the PostScript color operator for the requested output
+    // color mode.  Raw PostScript added with add_postscript is not
+    // converted.
+    fn postscript(&self, mode: ColorMode, n: fn(f32) -> f32) -> String {
+        let op = match mode {
+            ColorMode::Rgb => "setrgbcolor",
+            ColorMode::Cmyk => "setcmykcolor",
+            ColorMode::Gray => "setgray",
+        };
+        format!("{} {}", self.components(mode, n), op)
+    }
+
+    // Selects an (uncolored) fill pattern, painted in this color.
+    fn pattern(&self, mode: ColorMode, name: &str, n: fn(f32) -> f32) -> String {
+        let space = match mode {
+            ColorMode::Rgb => "DeviceRGB",
+            ColorMode::Cmyk => "DeviceCMYK",
+            ColorMode::Gray => "DeviceGray",
+        };
+        format!(
+            "[/Pattern /{}] setcolorspace {} {} setcolor",
+            space,
+            self.components(mode, n),
+            name
+        )
+    }
+}
+
+/// Color model used for the color operators in the generated output.
+/// Colors are always specified as RGB with set_color; the conversion
+/// happens when the PostScript file is written, so the same PSTool can
+/// be generated in several modes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorMode {
+    /// Red/Green/Blue, written with setrgbcolor (the default).
+    Rgb,
+    /// Cyan/Magenta/Yellow/blacK, written with setcmykcolor.  See
+    /// rgb_to_cmyk for the conversion.
+    Cmyk,
+    /// Grayscale, written with setgray.  See rgb_to_gray for the conversion.
+    Gray,
+}
+
+impl std::str::FromStr for ColorMode {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<ColorMode, String> {
+        match s {
+            "rgb" => Ok(ColorMode::Rgb),
+            "cmyk" => Ok(ColorMode::Cmyk),
+            "gray" | "grey" => Ok(ColorMode::Gray),
+            _ => Err(format!("unknown color mode {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ColorMode::Rgb => "rgb",
+            ColorMode::Cmyk => "cmyk",
+            ColorMode::Gray => "gray",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Converts an RGB color to CMYK using the simple device-independent
+/// formula: black is taken from the brightest channel (k = 1 - max(r, g, b)),
+/// and the remaining ink is c = (1 - r - k) / (1 - k), and likewise for
+/// magenta and yellow.  Pure black maps to (0, 0, 0, 1).  There is no
+/// color profile or ink limiting involved; if a printer needs something
+/// more careful, convert the PDF afterwards.
+/// ```
+/// use pstools::rgb_to_cmyk;
+/// assert_eq!(rgb_to_cmyk(0.0, 0.0, 0.0), (0.0, 0.0, 0.0, 1.0));
+/// assert_eq!(rgb_to_cmyk(1.0, 1.0, 1.0), (0.0, 0.0, 0.0, 0.0));
+/// assert_eq!(rgb_to_cmyk(1.0, 0.0, 0.0), (0.0, 1.0, 1.0, 0.0));
+/// assert_eq!(rgb_to_cmyk(0.5, 0.25, 0.0), (0.0, 0.5, 1.0, 0.5));
+/// ```
+pub fn rgb_to_cmyk(r: f32, g: f32, b: f32) -> (f32, f32, f32, f32) {
+    let k = 1.0 - r.max(g).max(b);
+    if k >= 1.0 {
+        return (0.0, 0.0, 0.0, 1.0);
+    }
+    let c = (1.0 - r - k) / (1.0 - k);
+    let m = (1.0 - g - k) / (1.0 - k);
+    let y = (1.0 - b - k) / (1.0 - k);
+    (c, m, y, k)
+}
+
+/// Converts an RGB color to a gray level, using the ITU-R BT.601
+/// luminance weights (0.299 r + 0.587 g + 0.114 b).
+/// ```
+/// use pstools::rgb_to_gray;
+/// assert_eq!(rgb_to_gray(0.0, 0.0, 0.0), 0.0);
+/// assert!((rgb_to_gray(1.0, 1.0, 1.0) - 1.0).abs() < 1e-6);
+/// assert!((rgb_to_gray(0.0, 1.0, 0.0) - 0.587).abs() < 1e-6);
+/// ```
+pub fn rgb_to_gray(r: f32, g: f32, b: f32) -> f32 {
+    0.299 * r + 0.587 * g + 0.114 * b
+}
+
+/// Paper sizes for set_page.  Dimensions are in points, portrait.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PageSize {
+    /// US Letter, 8.5 by 11 inches.
+    Letter,
+    /// ISO A4, 210 by 297 mm.
+    A4,
+    /// ISO A3, 297 by 420 mm.
+    A3,
+    /// Width and height, in points.
+    Custom(f32, f32),
+}
+
+impl PageSize {
+    /// The width and height of the page, in points.
+    pub fn dimensions(&self) -> (f32, f32) {
+        match *self {
+            PageSize::Letter => (612.0, 792.0),
+            PageSize::A4 => (595.0, 842.0),
+            PageSize::A3 => (842.0, 1191.0),
+            PageSize::Custom(w, h) => (w, h),
+        }
+    }
+    // The media name for the DSC comments
+    fn name(&self) -> &'static str {
+        match self {
+            PageSize::Letter => "Letter",
+            PageSize::A4 => "A4",
+            PageSize::A3 => "A3",
+            PageSize::Custom(_, _) => "Custom",
+        }
+    }
+}
+
+impl std::str::FromStr for PageSize {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<PageSize, String> {
+        match s.to_lowercase().as_str() {
+            "letter" => Ok(PageSize::Letter),
+            "a4" => Ok(PageSize::A4),
+            "a3" => Ok(PageSize::A3),
+            custom => {
+                // Custom sizes are given as WIDTHxHEIGHT, in points
+                let mut parts = custom.splitn(2, 'x');
+                match (
+                    parts.next().and_then(|w| w.parse::<f32>().ok()),
+                    parts.next().and_then(|h| h.parse::<f32>().ok()),
+                ) {
+                    (Some(w), Some(h)) if w > 0.0 && h > 0.0 => Ok(PageSize::Custom(w, h)),
+                    _ => Err(format!("unknown page size {}", s)),
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PageSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PageSize::Letter => f.write_str("letter"),
+            PageSize::A4 => f.write_str("a4"),
+            PageSize::A3 => f.write_str("a3"),
+            PageSize::Custom(w, h) => write!(f, "{}x{}", w, h),
+        }
+    }
+}
+
+/// Page orientation for set_page.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PageOrientation {
+    Portrait,
+    /// The page is turned sideways, so the width and height are swapped.
+    Landscape,
+}
+
+impl std::str::FromStr for PageOrientation {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<PageOrientation, String> {
+        match s {
+            "portrait" => Ok(PageOrientation::Portrait),
+            "landscape" => Ok(PageOrientation::Landscape),
+            _ => Err(format!("unknown page orientation {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for PageOrientation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            PageOrientation::Portrait => "portrait",
+            PageOrientation::Landscape => "landscape",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A page setup for generate -- the paper size and orientation, the
+/// margin on every side, and whether the figure is centered in the
+/// area inside the margins (otherwise it is placed at the top left).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PageSetup {
+    pub size: PageSize,
+    pub orientation: PageOrientation,
+    pub margin: f32,
+    pub center: bool,
+}
+
+impl PageSetup {
+    /// A portrait page with one inch margins, with the figure centered.
+    pub fn new(size: PageSize) -> PageSetup {
+        PageSetup {
+            size,
+            orientation: PageOrientation::Portrait,
+            margin: 72.0,
+            center: true,
+        }
+    }
+    /// The width and height of the page as printed, after the orientation.
+    pub fn dimensions(&self) -> (f32, f32) {
+        let (w, h) = self.size.dimensions();
+        match self.orientation {
+            PageOrientation::Portrait => (w, h),
+            PageOrientation::Landscape => (h, w),
+        }
+    }
+}
+
+/// Patterns for filled shapes, selected with set_fill_pattern.  Patterns
+/// are drawn in the current color, over a transparent background -- so
+/// overlapping regions with different patterns remain distinguishable.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FillPattern {
+    /// Solid fill, the same as set_fill(true).
+    Solid,
+    /// Parallel lines.
+    Hatch,
+    /// Two sets of lines, at right angles.
+    CrossHatch,
+    /// A grid of dots.
+    Dots,
+    /// Alternating filled squares.
+    Checkerboard,
+}
+
+impl std::str::FromStr for FillPattern {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<FillPattern, String> {
+        match s {
+            "solid" => Ok(FillPattern::Solid),
+            "hatch" => Ok(FillPattern::Hatch),
+            "crosshatch" => Ok(FillPattern::CrossHatch),
+            "dots" => Ok(FillPattern::Dots),
+            "checkerboard" | "checker" => Ok(FillPattern::Checkerboard),
+            _ => Err(format!("unknown fill pattern {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for FillPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            FillPattern::Solid => "solid",
+            FillPattern::Hatch => "hatch",
+            FillPattern::CrossHatch => "crosshatch",
+            FillPattern::Dots => "dots",
+            FillPattern::Checkerboard => "checkerboard",
+        };
+        f.write_str(name)
+    }
 }
 
 #[derive(Clone, Copy)]
 struct Fill {
     pub fill: bool,
+    pub pattern: FillPattern,
+    pub spacing: f32,
+    pub angle: f32,
+}
+
+impl Fill {
+    fn solid(fill: bool) -> Fill {
+        Fill {
+            fill,
+            pattern: FillPattern::Solid,
+            spacing: 0.0,
+            angle: 0.0,
+        }
+    }
+
+    fn same_pattern(&self, other: &Fill) -> bool {
+        self.pattern == other.pattern && self.spacing == other.spacing && self.angle == other.angle
+    }
+
+    // PostScript Level 2 pattern for a patterned fill, as a procedure that
+    // makes it.  The pattern is uncolored (PaintType 2), so that the
+    // current color is used when it is painted.  The pattern cell is
+    // spacing points on a side, rotated by the angle.  makepattern fixes
+    // the pattern to the CTM when it is called, so it is called where the
+    // fill is used -- after add_scale and add_translate, as in the SVG.
+    fn pattern_proc(&self, scale: f32, n: fn(f32) -> f32) -> String {
+        let s = n(self.spacing * scale);
+        let h = s / 2.0;
+        let (step, proc) = match self.pattern {
+            FillPattern::Hatch => (s, format!("0 {} moveto {} {} lineto stroke", h, s, h)),
+            FillPattern::CrossHatch => (
+                s,
+                format!(
+                    "0 {} moveto {} {} lineto {} 0 moveto {} {} lineto stroke",
+                    h, s, h, h, h, s
+                ),
+            ),
+            FillPattern::Dots => (s, format!("{} {} {} 0 360 arc fill", h, h, n(s / 5.0))),
+            FillPattern::Checkerboard => (2.0 * s, format!("0 0 {} {} rectfill {} {} {} {} rectfill", s, s, s, s, s, s)),
+            FillPattern::Solid => (s, String::new()),
+        };
+        format!(
+            "{{<< /PatternType 1 /PaintType 2 /TilingType 1 /BBox [0 0 {} {}] /XStep {} /YStep {} /PaintProc {{pop {} setlinewidth {}}} >> {} matrix rotate makepattern}}",
+            step, step, step, step, n(s / 8.0), proc, n(self.angle)
+        )
+    }
 }
 
 // Using a union to represent different types of events;
@@ -95,14 +455,291 @@ struct Translate {
     pub dy: f32,
 }
 
+// Cubic Bezier curves -- start point, two control points, and end point.
+// Curves from add_curve are always stroked; add_cubic and add_quadratic
+// follow the fill state.
 #[derive(Clone, Copy)]
 struct Curve {
+    pub x0: f32,
+    pub y0: f32,
     pub x1: f32,
     pub y1: f32,
     pub x2: f32,
     pub y2: f32,
     pub x3: f32,
     pub y3: f32,
+    pub fillable: bool,
+}
+
+// Arrow heads are triangles -- the tip, and the two back corners.
+#[derive(Clone, Copy)]
+struct Head {
+    pub tip_x: f32,
+    pub tip_y: f32,
+    pub lx: f32,
+    pub ly: f32,
+    pub rx: f32,
+    pub ry: f32,
+    pub filled: bool,
+}
+
+/// Arrow head styles, for add_arrow and the related calls.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ArrowHead {
+    /// No head -- just the line.
+    None,
+    /// Two lines meeting at the tip.
+    Open,
+    /// A filled triangle (the default).
+    Filled,
+}
+
+impl std::str::FromStr for ArrowHead {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<ArrowHead, String> {
+        match s {
+            "none" => Ok(ArrowHead::None),
+            "open" => Ok(ArrowHead::Open),
+            "filled" => Ok(ArrowHead::Filled),
+            _ => Err(format!("unknown arrow head {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ArrowHead {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ArrowHead::None => "none",
+            ArrowHead::Open => "open",
+            ArrowHead::Filled => "filled",
+        };
+        f.write_str(name)
+    }
+}
+
+// Arcs and wedges (pie slices), with angles in degrees
+#[derive(Clone, Copy)]
+struct Arc {
+    pub x: f32,
+    pub y: f32,
+    pub r: f32,
+    pub start: f32,
+    pub end: f32,
+}
+
+// Ellipses, rotated by angle degrees
+#[derive(Clone, Copy)]
+struct Ellipse {
+    pub x: f32,
+    pub y: f32,
+    pub rx: f32,
+    pub ry: f32,
+    pub angle: f32,
+}
+
+// Boxes with rounded corners
+#[derive(Clone, Copy)]
+struct RBox {
+    pub llx: f32,
+    pub lly: f32,
+    pub urx: f32,
+    pub ury: f32,
+    pub radius: f32,
+}
+
+impl RBox {
+    // Sorted corners, and the radius limited to half the smaller side
+    fn normalized(&self) -> (f32, f32, f32, f32, f32) {
+        let llx = self.llx.min(self.urx);
+        let lly = self.lly.min(self.ury);
+        let urx = self.llx.max(self.urx);
+        let ury = self.lly.max(self.ury);
+        let r = self.radius.max(0.0).min((urx - llx) / 2.0).min((ury - lly) / 2.0);
+        (llx, lly, urx, ury, r)
+    }
+}
+
+/// Orientations for placing symbols, following the LEF/DEF convention:
+/// N is as drawn, W, S, and E are rotated 90, 180, and 270 degrees
+/// counterclockwise, and the F variants are mirrored about the Y axis
+/// first.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Orientation {
+    N,
+    S,
+    E,
+    W,
+    FN,
+    FS,
+    FE,
+    FW,
+}
+
+impl Orientation {
+    /// The transformation matrix (a, b, c, d), as in PostScript concat --
+    /// a point (x, y) maps to (a x + c y, b x + d y).
+    pub fn matrix(&self) -> (f32, f32, f32, f32) {
+        match self {
+            Orientation::N => (1.0, 0.0, 0.0, 1.0),
+            Orientation::W => (0.0, 1.0, -1.0, 0.0),
+            Orientation::S => (-1.0, 0.0, 0.0, -1.0),
+            Orientation::E => (0.0, -1.0, 1.0, 0.0),
+            Orientation::FN => (-1.0, 0.0, 0.0, 1.0),
+            Orientation::FW => (0.0, 1.0, 1.0, 0.0),
+            Orientation::FS => (1.0, 0.0, 0.0, -1.0),
+            Orientation::FE => (0.0, -1.0, -1.0, 0.0),
+        }
+    }
+}
+
+impl std::str::FromStr for Orientation {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Orientation, String> {
+        match s {
+            "N" => Ok(Orientation::N),
+            "S" => Ok(Orientation::S),
+            "E" => Ok(Orientation::E),
+            "W" => Ok(Orientation::W),
+            "FN" => Ok(Orientation::FN),
+            "FS" => Ok(Orientation::FS),
+            "FE" => Ok(Orientation::FE),
+            "FW" => Ok(Orientation::FW),
+            _ => Err(format!("unknown orientation {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Orientation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Orientation::N => "N",
+            Orientation::S => "S",
+            Orientation::E => "E",
+            Orientation::W => "W",
+            Orientation::FN => "FN",
+            Orientation::FS => "FS",
+            Orientation::FE => "FE",
+            Orientation::FW => "FW",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A translation and uniform scale, used by embed.  A point (x, y) of
+/// the embedded PSTool is drawn at (dx + scale * x, dy + scale * y).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub dx: f32,
+    pub dy: f32,
+    pub scale: f32,
+}
+
+impl Transform {
+    pub fn new(dx: f32, dy: f32, scale: f32) -> Transform {
+        Transform { dx, dy, scale }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::new(0.0, 0.0, 1.0)
+    }
+}
+
+// A placed symbol -- the index into the symbols, and the placement.
+// The symbol is oriented and scaled, then translated by (x, y).
+#[derive(Clone, Copy)]
+struct Instance {
+    pub symbol: usize,
+    pub x: f32,
+    pub y: f32,
+    pub orient: Orientation,
+    pub scale: f32,
+}
+
+// Paths are kept in a separate vector, like strings, with the event
+// holding the index and whether to fill and/or stroke.
+#[derive(Clone, Copy)]
+struct Path {
+    pub path: usize, // Index into the paths
+    pub fill: bool,
+    pub stroke: bool,
+}
+
+// The dash pattern is variable length, so (like strings) the
+// pattern is kept in a separate vector, and the event holds an index.
+#[derive(Clone, Copy)]
+struct Dash {
+    pub pattern: usize, // Index into the dash patterns
+    pub offset: f32,
+}
+
+/// Line cap styles, matching PostScript setlinecap.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineCap {
+    /// Square end, flush with the end point (the default).
+    Butt,
+    /// Semicircular end, centered on the end point.
+    Round,
+    /// Square end, extending half a line width past the end point.
+    Square,
+}
+
+impl std::str::FromStr for LineCap {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<LineCap, String> {
+        match s {
+            "butt" | "0" => Ok(LineCap::Butt),
+            "round" | "1" => Ok(LineCap::Round),
+            "square" | "2" => Ok(LineCap::Square),
+            _ => Err(format!("unknown line cap {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for LineCap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            LineCap::Butt => "butt",
+            LineCap::Round => "round",
+            LineCap::Square => "square",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Line join styles, matching PostScript setlinejoin.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineJoin {
+    /// Sharp corners, limited by the miter limit (the default).
+    Miter,
+    /// Rounded corners.
+    Round,
+    /// Corners cut off square.
+    Bevel,
+}
+
+impl std::str::FromStr for LineJoin {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<LineJoin, String> {
+        match s {
+            "miter" | "0" => Ok(LineJoin::Miter),
+            "round" | "1" => Ok(LineJoin::Round),
+            "bevel" | "2" => Ok(LineJoin::Bevel),
+            _ => Err(format!("unknown line join {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for LineJoin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            LineJoin::Miter => "miter",
+            LineJoin::Round => "round",
+            LineJoin::Bevel => "bevel",
+        };
+        f.write_str(name)
+    }
 }
 
 // Events are stored in a vector, with a union structure.  To
@@ -112,7 +749,7 @@ struct Curve {
 // The unions do not have a String field -- for the events
 // where a String is required, we use an index into a vector
 // of strings.
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum PSTag {
     B,  // Box
     C,  // Color
@@ -129,8 +766,27 @@ enum PSTag {
     S,  // Scale
     GS, // Gsave
     GR, // GRestore
+    D,  // Dash pattern
+    LC, // Line Cap
+    LJ, // Line Join
+    ML, // Miter Limit
+    H,  // arrow Head
+    TC, // Text, Centered
+    A,  // Arc
+    WG, // WedGe
+    E,  // Ellipse
+    RB, // Rounded Box
+    PA, // PAth
+    CS, // Clip Save (gsave, then clip)
+    CR, // Clip Restore (grestore)
+    I,  // symbol Instance
+    LB, // Layer Begin (gsave)
+    LE, // Layer End (grestore)
+    OB, // Object Begin (gsave)
+    OE, // Object End (grestore)
 }
 
+#[derive(Clone, Copy)]
 union PSUnion {
     line: LBBox,
     curve: Curve,
@@ -141,18 +797,56 @@ union PSUnion {
     line_width: f32,
     scale: Scale,
     translate: Translate,
+    dash: Dash,
+    line_cap: LineCap,
+    line_join: LineJoin,
+    miter_limit: f32,
+    head: Head,
+    arc: Arc,
+    ellipse: Ellipse,
+    rbox: RBox,
+    path: Path,
+    instance: Instance,
+    object: usize, // Index into the objects
     // comment: Comment,
 }
 
+#[derive(Clone)]
 struct PSEvent {
     tag: PSTag,
     event: PSUnion,
 }
 
+// Settings for writing PostScript that apply to the whole file,
+// including the symbol procedures.
+struct PSOutput {
+    scale: f32,
+    round: fn(f32) -> f32,
+    patterns: Vec<Fill>,
+    uses_alpha: bool,
+    latin1: bool,
+    color_mode: ColorMode,
+}
+
+impl PSOutput {
+    // The operator to look up a font -- with text outside of ASCII, fonts
+    // are re-encoded for ISO Latin-1.
+    fn find_font(&self) -> &'static str {
+        if self.latin1 {
+            "rf"
+        } else {
+            "findfont"
+        }
+    }
+}
+
 struct PSStack {
     scale: f32,
     offset_x: f32,
     offset_y: f32,
+    line_width: f32,
+    line_cap: LineCap,
+    clip: Option<BBox>,
 }
 
 /// The PSTool structure records a series of PostScript events -- drawing of
@@ -165,6 +859,7 @@ struct PSStack {
 /// Scaling and offsets can be added with push, and restore to earlier versions
 /// with pop.  Note that if there is embedded PostScript in the output, it does
 /// not follow the scaling of the PSTool library.
+#[derive(Clone)]
 pub struct PSTool {
     bbox: BBox,
     border: f32,
@@ -177,10 +872,36 @@ pub struct PSTool {
     // stack: Vec<PSStack>,
     events: Vec<PSEvent>,
     te: Vec<String>,
+    dashes: Vec<Vec<f32>>,
+    paths: Vec<PathBuilder>,
+    symbols: Vec<(String, PSTool)>,
+    // The extents of each symbol, found once when it is defined
+    symbol_extents: Vec<BBox>,
+    // Object IDs, with their properties
+    objects: Vec<(String, Vec<(String, String)>)>,
     text_x: f32,
     text_y: f32,
     text_line_space: f32,
     notes: Vec<String>,
+    color_mode: ColorMode,
+    page: Option<PageSetup>,
+    max_size: f32,
+    title: Option<String>,
+    creator: Option<String>,
+    user: Option<String>,
+    deterministic: bool,
+    arrow_head: ArrowHead,
+    arrow_length: f32,
+    arrow_width: f32,
+    // Event handles, given out lazily (see events.rs)
+    ids: Vec<u64>,
+    next_id: u64,
+}
+
+impl Default for PSTool {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PSTool {
@@ -204,10 +925,27 @@ impl PSTool {
             border: 0.0,
             events: Vec::new(),
             te: Vec::new(),
+            dashes: Vec::new(),
+            paths: Vec::new(),
+            symbols: Vec::new(),
+            symbol_extents: Vec::new(),
+            objects: Vec::new(),
             text_x: 0.0,
             text_y: 0.0,
             text_line_space: 12.0,
             notes: Vec::new(),
+            color_mode: ColorMode::Rgb,
+            page: None,
+            max_size: 10000.0,
+            title: None,
+            creator: None,
+            user: None,
+            deterministic: false,
+            arrow_head: ArrowHead::Filled,
+            arrow_length: 6.0,
+            arrow_width: 4.0,
+            ids: Vec::new(),
+            next_id: 0,
         }
     }
 
@@ -228,21 +966,19 @@ impl PSTool {
             b = 1.0 - b * b;
         }
         if i % 3 == 0 {
-            let temp = g;
-            g = b;
-            b = temp;
+            std::mem::swap(&mut g, &mut b);
         }
         let sum = r + g + b;
         if ((i % 2 == 0) || (i % 3 == 1)) && sum > 1.4 {
-            r = r * 0.8;
-            g = g * 0.6;
-            b = b * 0.7;
+            r *= 0.8;
+            g *= 0.6;
+            b *= 0.7;
         }
         let sum = r + g + b;
         if sum > 2.0 {
-            r = r * 0.6;
-            g = g * 0.6;
-            b = b * 0.6;
+            r *= 0.6;
+            g *= 0.6;
+            b *= 0.6;
         }
 
         if i % 2 == 0 {
@@ -271,7 +1007,7 @@ impl PSTool {
     }
 
     /// Add a line between the indicated coordinates, using the current
-    /// selected color.  Saved into the struct with delta offsets
+    /// selected color.
     pub fn add_line(&mut self, llx: f32, lly: f32, urx: f32, ury: f32) {
         self.events.push(PSEvent {
             tag: PSTag::L,
@@ -282,7 +1018,7 @@ impl PSTool {
                     // lly: lly * self.scale + self.offset_y,
                     // urx: urx * self.scale + self.offset_x,
                     // ury: ury * self.scale + self.offset_y,
-                    llx, lly, urx: urx - llx, ury: ury - lly,
+                    llx, lly, urx, ury,
                 },
             },
         });
@@ -306,88 +1042,485 @@ impl PSTool {
             },
         });
     }
-    /// Add a curve, using a start, mid, and end point.  PostScript supports
-    /// Bezier curves; a curve can be helpful in showing a connection where
-    /// co-linear connections might often overlap.
-    pub fn add_curve(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32) {
+    /// Add a circular arc (just the outline), centered at (x, y), running
+    /// counterclockwise from the start angle to the end angle.  Angles are
+    /// in degrees, with zero along the positive X axis.
+    pub fn add_arc(&mut self, x: f32, y: f32, r: f32, start: f32, end: f32) {
         self.events.push(PSEvent {
-            tag: PSTag::V,
+            tag: PSTag::A,
             event: PSUnion {
-                curve: Curve {
-                    // x1: x1 * self.scale + self.offset_x,
-                    // y1: y1 * self.scale + self.offset_y,
-                    // x2: x2 * self.scale + self.offset_x,
-                    // y2: y2 * self.scale + self.offset_y,
-                    // x3: x3 * self.scale + self.offset_x,
-                    // y3: y3 * self.scale + self.offset_y,
-                    x1, y1, x2, y2, x3, y3,
-                },
+                arc: Arc { x, y, r, start, end },
             },
         });
     }
 
-    /// Adds raw PostScript commands to the instruction stream.
-    /// Can be used to get full access to PostScript functionality
-    /// (gsave/grestore, translation, scaling, and so on).  Note that
-    /// the bounding box of the output will not correctly track the
-    /// graphic elements introduced with raw PostScript.
-    pub fn add_postscript(&mut self, t: String) {
+    /// Add a wedge (a pie slice) centered at (x, y), running
+    /// counterclockwise from the start angle to the end angle, using the
+    /// current fill status and color.
+    pub fn add_wedge(&mut self, x: f32, y: f32, r: f32, start: f32, end: f32) {
         self.events.push(PSEvent {
-            tag: PSTag::P,
+            tag: PSTag::WG,
             event: PSUnion {
-                text: Text {
-                    text: self.te.len(),
-                    x: 0.0,
-                    y: 0.0,
-                    angle: 0.0,
-                },
+                arc: Arc { x, y, r, start, end },
             },
         });
-        self.te.push(t);
     }
 
-    /// Adds text onto the display at the specified coordintes. The currently
-    /// selected color, font, and font size will be utilized.  Note that
-    /// currently the String is not character-escaped; close parentheses
-    /// may not render correctly (or cause invalid PostScript generation).
-    pub fn add_text(&mut self, x: f32, y: f32, t: String) {
+    /// Add an ellipse centered at (x, y), with radii rx and ry, rotated
+    /// counterclockwise by angle degrees.  Uses the current fill status and
+    /// color.
+    pub fn add_ellipse(&mut self, x: f32, y: f32, rx: f32, ry: f32, angle: f32) {
         self.events.push(PSEvent {
-            tag: PSTag::T,
+            tag: PSTag::E,
             event: PSUnion {
-                text: Text {
-                    text: self.te.len(),
-                    // x: x * self.scale + self.offset_x,
-                    // y: y * self.scale + self.offset_y,
-                    x, y,
-                    angle: 0.0,
-                },
+                ellipse: Ellipse { x, y, rx, ry, angle },
             },
         });
-        self.te.push(t);
     }
-    /// Adds text onto the display at the specified coordintes. The currently
-    /// selected color, font, and font size will be utilized.  Note that
-    /// currently the String is not character-escaped; close parentheses
-    /// may not render correctly (or cause invalid PostScript generation).
-    pub fn add_text_rotated(&mut self, x: f32, y: f32, angle: f32, t: String) {
+
+    /// Add an axis-aligned box with rounded corners.  The radius is
+    /// limited to half of the shorter side (and a radius of zero or less
+    /// gives square corners).  Uses the current fill status and color.
+    pub fn add_rounded_box(&mut self, llx: f32, lly: f32, urx: f32, ury: f32, radius: f32) {
+        let mut rbox = RBox {
+            llx,
+            lly,
+            urx,
+            ury,
+            radius,
+        };
+        rbox.radius = rbox.normalized().4;
         self.events.push(PSEvent {
-            tag: PSTag::T,
+            tag: PSTag::RB,
+            event: PSUnion { rbox },
+        });
+    }
+
+    /// Adds a compound path, built with a PathBuilder, as a single
+    /// event.  The path can be filled (with the path's fill rule, and the
+    /// current fill pattern if one has been set with set_fill_pattern),
+    /// stroked with the current line settings, or both.
+    pub fn add_path(&mut self, path: &PathBuilder, fill: bool, stroke: bool) {
+        self.events.push(PSEvent {
+            tag: PSTag::PA,
             event: PSUnion {
-                text: Text {
-                    text: self.te.len(),
-                    // x: x * self.scale + self.offset_x,
-                    // y: y * self.scale + self.offset_y,
-                    x, y,
-                    angle: angle,
+                path: Path {
+                    path: self.paths.len(),
+                    fill,
+                    stroke,
                 },
             },
         });
-        self.te.push(t);
+        self.paths.push(path.clone());
     }
 
-    /// Sets the location for text lines (and auto-increments the
-    /// line position with each add_text_nl call).
-    pub fn set_text_ln(&mut self, x: f32, y: f32) {
+    /// Restricts drawing to a rectangle, until the matching pop_clip.
+    /// Clipping regions nest -- each new region is intersected with the
+    /// ones before.  The bounding box of the output is limited to the
+    /// clipping region as well.
+    pub fn push_clip_rect(&mut self, llx: f32, lly: f32, urx: f32, ury: f32) {
+        let mut path = PathBuilder::new();
+        path.move_to(llx, lly)
+            .line_to(urx, lly)
+            .line_to(urx, ury)
+            .line_to(llx, ury)
+            .close_path();
+        self.push_clip_path(&path);
+    }
+
+    /// Restricts drawing to the inside of a path (using the path's fill
+    /// rule), until the matching pop_clip.  For the bounding box, the
+    /// clipping region is taken to be the bounding box of the path.
+    pub fn push_clip_path(&mut self, path: &PathBuilder) {
+        self.events.push(PSEvent {
+            tag: PSTag::CS,
+            event: PSUnion {
+                path: Path {
+                    path: self.paths.len(),
+                    fill: false,
+                    stroke: false,
+                },
+            },
+        });
+        self.paths.push(path.clone());
+    }
+
+    /// Removes the most recent clipping region.  Clipping is done inside
+    /// a gsave/grestore pair, so any color, font, line, or transformation
+    /// changes made since the push_clip are undone as well.
+    pub fn pop_clip(&mut self) {
+        self.events.push(PSEvent {
+            tag: PSTag::CR,
+            event: PSUnion {
+                fill: Fill::solid(false),
+            },
+        })
+    }
+
+    /// Defines a named symbol -- a PSTool holding, for example, a standard
+    /// cell master -- which can then be placed any number of times with
+    /// place_symbol.  In the PostScript output, each symbol becomes a
+    /// procedure, so each placement costs a single line.  Symbols can
+    /// contain symbols of their own.  Redefining a name affects only the
+    /// placements made afterwards.  Color is inherited from the point
+    /// of placement, unless the symbol sets its own.
+    pub fn define_symbol(&mut self, name: String, symbol: &PSTool) {
+        self.symbols.push((name, symbol.clone()));
+        self.symbol_extents.push(symbol.extents());
+    }
+
+    /// Places an instance of a symbol defined with define_symbol.  The
+    /// symbol is oriented, then scaled, and the lower left corner of its
+    /// bounding box (as bbox would report it, including half the line
+    /// width) is placed at (x, y), as in DEF placements.  Returns false,
+    /// adding nothing, if no symbol has the name.
+    pub fn place_symbol(&mut self, name: &str, x: f32, y: f32, orient: Orientation, scale: f32) -> bool {
+        match self.symbols.iter().rposition(|(n, _)| n == name) {
+            Some(index) => {
+                self.place_symbol_index(index, x, y, orient, scale);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Places the symbol at an index into the symbols, as place_symbol does.
+    fn place_symbol_index(&mut self, index: usize, x: f32, y: f32, orient: Orientation, scale: f32) {
+        let (llx, lly) = self.placed_corner(index, orient, scale);
+        self.events.push(PSEvent {
+            tag: PSTag::I,
+            event: PSUnion {
+                instance: Instance {
+                    symbol: index,
+                    x: x - llx,
+                    y: y - lly,
+                    orient,
+                    scale,
+                },
+            },
+        });
+    }
+
+    // Where the lower left corner of a symbol lands after the orientation
+    // and scaling (before the translation of the instance).
+    fn placed_corner(&self, index: usize, orient: Orientation, scale: f32) -> (f32, f32) {
+        let extents = &self.symbol_extents[index];
+        if !extents.valid {
+            return (0.0, 0.0);
+        }
+        let (a, b, c, d) = orient.matrix();
+        let mut placed = BBox::new();
+        for (px, py) in [
+            (extents.llx, extents.lly),
+            (extents.urx, extents.lly),
+            (extents.urx, extents.ury),
+            (extents.llx, extents.ury),
+        ] {
+            placed.addpoint(scale * (a * px + c * py), scale * (b * px + d * py));
+        }
+        (placed.llx, placed.lly)
+    }
+
+    /// Add a curve, using a start, mid, and end point.  PostScript supports
+    /// Bezier curves; a curve can be helpful in showing a connection where
+    /// co-linear connections might often overlap.  The start point is also
+    /// the first control point of the Bezier curve, and the mid point the
+    /// second; use add_cubic for full control.
+    pub fn add_curve(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32) {
+        self.events.push(PSEvent {
+            tag: PSTag::V,
+            event: PSUnion {
+                curve: Curve {
+                    // x1: x1 * self.scale + self.offset_x,
+                    // y1: y1 * self.scale + self.offset_y,
+                    // x2: x2 * self.scale + self.offset_x,
+                    // y2: y2 * self.scale + self.offset_y,
+                    // x3: x3 * self.scale + self.offset_x,
+                    // y3: y3 * self.scale + self.offset_y,
+                    x0: x1, y0: y1, x1, y1, x2, y2, x3, y3,
+                    fillable: false,
+                },
+            },
+        });
+    }
+
+    /// Add a cubic Bezier curve from (x0, y0) to (x3, y3), with control
+    /// points (x1, y1) and (x2, y2).  With fill on, the curve is closed
+    /// with a straight line back to the start and filled.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_cubic(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32) {
+        self.events.push(PSEvent {
+            tag: PSTag::V,
+            event: PSUnion {
+                curve: Curve {
+                    x0, y0, x1, y1, x2, y2, x3, y3,
+                    fillable: true,
+                },
+            },
+        });
+    }
+
+    /// Add a quadratic Bezier curve from (x0, y0) to (x2, y2), with the
+    /// control point (x1, y1).  The curve is converted exactly to a cubic,
+    /// with control points two thirds of the way from each end point to
+    /// the quadratic control point.  Follows the fill state, as with
+    /// add_cubic.
+    pub fn add_quadratic(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, x2: f32, y2: f32) {
+        let (c1x, c1y, c2x, c2y) = quadratic_controls(x0, y0, x1, y1, x2, y2);
+        self.add_cubic(x0, y0, c1x, c1y, c2x, c2y, x2, y2);
+    }
+
+    /// Sets the style and size of arrow heads, for add_arrow,
+    /// add_double_arrow, add_curved_arrow, and add_dimension.  The length
+    /// is measured back from the tip, and the width across the base of the
+    /// head.  Arrows default to filled heads, 6 points long and 4 wide.
+    pub fn set_arrow_head(&mut self, head: ArrowHead, length: f32, width: f32) {
+        self.arrow_head = head;
+        self.arrow_length = length;
+        self.arrow_width = width;
+    }
+
+    // Adds an arrow head with the tip at (x, y), pointing in the
+    // direction (dx, dy).  Returns the point where the shaft should end --
+    // the base of a filled head, so that wide lines don't poke through
+    // the tip.
+    fn add_arrow_head(&mut self, x: f32, y: f32, dx: f32, dy: f32) -> (f32, f32) {
+        let len = (dx * dx + dy * dy).sqrt();
+        if self.arrow_head == ArrowHead::None || len == 0.0 {
+            return (x, y);
+        }
+        let (ux, uy) = (dx / len, dy / len);
+        let bx = x - ux * self.arrow_length;
+        let by = y - uy * self.arrow_length;
+        let (nx, ny) = (-uy * self.arrow_width / 2.0, ux * self.arrow_width / 2.0);
+        self.push_arrow_tip(Head {
+            tip_x: x,
+            tip_y: y,
+            lx: bx + nx,
+            ly: by + ny,
+            rx: bx - nx,
+            ry: by - ny,
+            filled: self.arrow_head == ArrowHead::Filled,
+        });
+        if self.arrow_head == ArrowHead::Filled {
+            (bx, by)
+        } else {
+            (x, y)
+        }
+    }
+
+    fn push_arrow_tip(&mut self, head: Head) {
+        self.events.push(PSEvent {
+            tag: PSTag::H,
+            event: PSUnion { head },
+        });
+    }
+
+    /// Adds a straight arrow from (x1, y1) to (x2, y2), with the head
+    /// at the end point.  The head uses the style from set_arrow_head,
+    /// and is included in the bounding box.  An arrow shorter than a
+    /// filled head is just the head.
+    pub fn add_arrow(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        let (ex, ey) = self.add_arrow_head(x2, y2, x2 - x1, y2 - y1);
+        self.add_shaft(x1, y1, ex, ey, x2 - x1, y2 - y1);
+    }
+
+    /// Adds a straight arrow with heads at both ends.  If filled heads
+    /// meet or overlap, there is no shaft between them.
+    pub fn add_double_arrow(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        let (sx, sy) = self.add_arrow_head(x1, y1, x1 - x2, y1 - y2);
+        let (ex, ey) = self.add_arrow_head(x2, y2, x2 - x1, y2 - y1);
+        self.add_shaft(sx, sy, ex, ey, x2 - x1, y2 - y1);
+    }
+
+    // Adds the shaft of a straight arrow, between the points returned by
+    // add_arrow_head, unless pulling the ends back under the heads has
+    // turned it around (it would stick out of the heads backwards).
+    fn add_shaft(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, dx: f32, dy: f32) {
+        if (x2 - x1) * dx + (y2 - y1) * dy > 0.0 || (dx == 0.0 && dy == 0.0) {
+            self.add_line(x1, y1, x2, y2);
+        }
+    }
+
+    /// Adds a curved arrow, using the same start, mid, and end points as
+    /// add_curve, with the head at the end point.  The head follows the
+    /// direction of the curve as it arrives at the end point.  Under a
+    /// filled head, the curve stops where it enters the head, as the
+    /// shaft of add_arrow does.
+    pub fn add_curved_arrow(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32) {
+        let (mut dx, mut dy) = (x3 - x2, y3 - y2);
+        if dx == 0.0 && dy == 0.0 {
+            dx = x3 - x1;
+            dy = y3 - y1;
+        }
+        if self.add_arrow_head(x3, y3, dx, dy) == (x3, y3) {
+            self.add_curve(x1, y1, x2, y2, x3, y3);
+            return;
+        }
+        // Find where the curve is the head's length from the tip, and
+        // split it there (de Casteljau).  The start point is both of the
+        // first two control points, so the first part is also a curve of
+        // the add_curve kind.
+        let lerp = |(ax, ay): (f32, f32), (bx, by): (f32, f32), t: f32| (ax + (bx - ax) * t, ay + (by - ay) * t);
+        let split = |t: f32| {
+            let q1 = lerp((x1, y1), (x2, y2), t);
+            let q2 = lerp((x2, y2), (x3, y3), t);
+            let r0 = lerp((x1, y1), q1, t);
+            let r1 = lerp(q1, q2, t);
+            (r0, lerp(r0, r1, t))
+        };
+        let inside = |(x, y): (f32, f32)| (x - x3).hypot(y - y3) <= self.arrow_length;
+        if inside((x1, y1)) {
+            return;
+        }
+        let (mut lo, mut hi) = (0.0, 1.0);
+        for _ in 0..24 {
+            let t = (lo + hi) / 2.0;
+            if inside(split(t).1) {
+                hi = t;
+            } else {
+                lo = t;
+            }
+        }
+        let ((cx, cy), (ex, ey)) = split(lo);
+        self.add_curve(x1, y1, cx, cy, ex, ey);
+    }
+
+    /// Adds a dimension line, measuring the distance between (x1, y1) and
+    /// (x2, y2).  The dimension line is drawn parallel to the measured
+    /// span, offset to the left (as seen looking from the first point to
+    /// the second) by the given amount, with extension lines back to the
+    /// two points and arrow heads at both ends.  The label is centered
+    /// above the dimension line, in the current font; if the label is
+    /// empty, the distance is used (with one decimal place).
+    pub fn add_dimension(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, offset: f32, label: String) {
+        let dx = x2 - x1;
+        let dy = y2 - y1;
+        let len = (dx * dx + dy * dy).sqrt();
+        if len == 0.0 {
+            return;
+        }
+        let (nx, ny) = (-dy / len, dx / len);
+        let (ax, ay) = (x1 + nx * offset, y1 + ny * offset);
+        let (bx, by) = (x2 + nx * offset, y2 + ny * offset);
+
+        // Extension lines run a little past the dimension line
+        let over = offset.signum() * self.arrow_length / 2.0;
+        if offset != 0.0 {
+            self.add_line(x1, y1, ax + nx * over, ay + ny * over);
+            self.add_line(x2, y2, bx + nx * over, by + ny * over);
+        }
+        self.add_double_arrow(ax, ay, bx, by);
+
+        // Keep the label reading left to right
+        let mut angle = dy.atan2(dx).to_degrees();
+        let (mut tx, mut ty) = (nx, ny);
+        if angle > 90.0 || angle <= -90.0 {
+            angle -= 180.0f32.copysign(angle);
+            tx = -tx;
+            ty = -ty;
+        }
+        let gap = self.font_scale * 0.3;
+        let label = if label.is_empty() {
+            format!("{:.1}", len)
+        } else {
+            label
+        };
+        self.push_text(
+            PSTag::TC,
+            (ax + bx) / 2.0 + tx * gap,
+            (ay + by) / 2.0 + ty * gap,
+            angle,
+            label,
+        );
+    }
+
+    /// Adds raw PostScript commands to the instruction stream.
+    /// Can be used to get full access to PostScript functionality
+    /// (gsave/grestore, translation, scaling, and so on).  Note that
+    /// the bounding box of the output will not correctly track the
+    /// graphic elements introduced with raw PostScript.  The text is
+    /// written as it is, so it should be ASCII (the output is declared
+    /// as Clean7Bit).
+    pub fn add_postscript(&mut self, t: String) {
+        self.events.push(PSEvent {
+            tag: PSTag::P,
+            event: PSUnion {
+                text: Text {
+                    text: self.te.len(),
+                    x: 0.0,
+                    y: 0.0,
+                    angle: 0.0,
+                },
+            },
+        });
+        self.te.push(t);
+    }
+
+    /// Adds text onto the display at the specified coordintes. The currently
+    /// selected color, font, and font size will be utilized.  Note that
+    /// currently the String is not character-escaped; close parentheses
+    /// may not render correctly (or cause invalid PostScript generation).
+    pub fn add_text(&mut self, x: f32, y: f32, t: String) {
+        self.events.push(PSEvent {
+            tag: PSTag::T,
+            event: PSUnion {
+                text: Text {
+                    text: self.te.len(),
+                    // x: x * self.scale + self.offset_x,
+                    // y: y * self.scale + self.offset_y,
+                    x, y,
+                    angle: 0.0,
+                },
+            },
+        });
+        self.te.push(t);
+    }
+    /// Adds text onto the display at the specified coordintes. The currently
+    /// selected color, font, and font size will be utilized.  Note that
+    /// currently the String is not character-escaped; close parentheses
+    /// may not render correctly (or cause invalid PostScript generation).
+    pub fn add_text_rotated(&mut self, x: f32, y: f32, angle: f32, t: String) {
+        self.events.push(PSEvent {
+            tag: PSTag::T,
+            event: PSUnion {
+                text: Text {
+                    text: self.te.len(),
+                    // x: x * self.scale + self.offset_x,
+                    // y: y * self.scale + self.offset_y,
+                    x, y,
+                    angle,
+                },
+            },
+        });
+        self.te.push(t);
+    }
+
+    /// Adds text centered horizontally on the specified coordinates,
+    /// which is handy for labels and captions.  The currently selected
+    /// color, font, and font size will be utilized.
+    pub fn add_text_centered(&mut self, x: f32, y: f32, t: String) {
+        self.push_text(PSTag::TC, x, y, 0.0, t);
+    }
+
+    fn push_text(&mut self, tag: PSTag, x: f32, y: f32, angle: f32, t: String) {
+        self.events.push(PSEvent {
+            tag,
+            event: PSUnion {
+                text: Text {
+                    text: self.te.len(),
+                    x,
+                    y,
+                    angle,
+                },
+            },
+        });
+        self.te.push(t);
+    }
+
+    /// Sets the location for text lines (and auto-increments the
+    /// line position with each add_text_nl call).
+    pub fn set_text_ln(&mut self, x: f32, y: f32) {
         self.text_x = x; // x * self.scale + self.offset_x;
         self.text_y = y; // y * self.scale + self.offset_y;
     }
@@ -400,7 +1533,7 @@ impl PSTool {
         // a time, with characters encoded into u8.
         // https://docs.rs/urlencoding/2.1.3/src/urlencoding/enc.rs.html#72-74
         self.add_text(self.text_x, self.text_y, t);
-        self.text_y = self.text_y - self.text_line_space;
+        self.text_y -= self.text_line_space;
     }
 
     /// Adds commented text to the PostScript output; while this
@@ -446,12 +1579,62 @@ impl PSTool {
         })
     }
 
+    /// Sets the dash pattern for lines and outlines.  The pattern
+    /// alternates the lengths of dashes and gaps (so vec![3.0, 2.0] is
+    /// 3 points on, 2 points off), starting offset points into the pattern.
+    /// An empty pattern returns to solid lines.  Lengths can't be
+    /// negative, and can't all be zero; a pattern like that is ignored.
+    pub fn set_dash(&mut self, pattern: Vec<f32>, offset: f32) {
+        if check_dash(&pattern).is_err() {
+            return;
+        }
+        self.events.push(PSEvent {
+            tag: PSTag::D,
+            event: PSUnion {
+                dash: Dash {
+                    pattern: self.dashes.len(),
+                    offset: offset * self.scale,
+                },
+            },
+        });
+        self.dashes
+            .push(pattern.iter().map(|d| d * self.scale).collect());
+    }
+
+    /// Sets the shape at the ends of lines and curves.  Round caps look
+    /// nicer for wires; the default is butt caps.
+    pub fn set_line_cap(&mut self, cap: LineCap) {
+        self.events.push(PSEvent {
+            tag: PSTag::LC,
+            event: PSUnion { line_cap: cap },
+        })
+    }
+
+    /// Sets the shape of corners where line segments meet (outlines of
+    /// boxes, for example).  The default is miter joins.
+    pub fn set_line_join(&mut self, join: LineJoin) {
+        self.events.push(PSEvent {
+            tag: PSTag::LJ,
+            event: PSUnion { line_join: join },
+        })
+    }
+
+    /// Sets the miter limit -- sharp corners with mitered joins are
+    /// beveled if the miter would be longer than this ratio to the line
+    /// width.  PostScript defaults to 10.
+    pub fn set_miter_limit(&mut self, limit: f32) {
+        self.events.push(PSEvent {
+            tag: PSTag::ML,
+            event: PSUnion { miter_limit: limit },
+        })
+    }
+
     /// Adds a graphic context save event
     pub fn add_gsave(&mut self) {
         self.events.push(PSEvent {
             tag: PSTag::GS,
             event: PSUnion {
-                fill: Fill { fill: false },
+                fill: Fill::solid(false),
             },
         })
     }
@@ -461,17 +1644,103 @@ impl PSTool {
         self.events.push(PSEvent {
             tag: PSTag::GR,
             event: PSUnion {
-                fill: Fill { fill: false },
+                fill: Fill::solid(false),
+            },
+        })
+    }
+
+    /// Starts a named layer -- a group of events that viewers can show
+    /// or hide together, such as the placement or the routing.  Layers
+    /// are drawn inside a gsave/grestore pair, so color, font, line, and
+    /// transformation changes end with the layer.  In SVG output, a layer
+    /// is a group with a data-layer attribute.
+    pub fn begin_layer(&mut self, name: String) {
+        self.events.push(PSEvent {
+            tag: PSTag::LB,
+            event: PSUnion {
+                text: Text {
+                    text: self.te.len(),
+                    x: 0.0,
+                    y: 0.0,
+                    angle: 0.0,
+                },
+            },
+        });
+        self.te.push(name);
+    }
+
+    /// Ends the most recent layer
+    pub fn end_layer(&mut self) {
+        self.events.push(PSEvent {
+            tag: PSTag::LE,
+            event: PSUnion {
+                fill: Fill::solid(false),
+            },
+        })
+    }
+
+    /// Starts an object -- a group of events, such as a cell's box and
+    /// its label, that viewers identify as one thing.  The ID is the name
+    /// to show and search for (a cell or net name, say), and the
+    /// properties are any other key/value pairs.  In PostScript output,
+    /// the ID and properties are comments, and an annotation for PDF
+    /// (made with Ghostscript) covers the object; in SVG output, the
+    /// object is a group with the ID as data-id, data- attributes for the
+    /// properties, and a title for a tooltip.  As with layers, the object is drawn
+    /// inside a gsave/grestore pair.
+    pub fn begin_object(&mut self, id: String, properties: &[(&str, &str)]) {
+        self.events.push(PSEvent {
+            tag: PSTag::OB,
+            event: PSUnion {
+                object: self.objects.len(),
+            },
+        });
+        let properties = properties.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        self.objects.push((id, properties));
+    }
+
+    /// Ends the most recent object
+    pub fn end_object(&mut self) {
+        self.events.push(PSEvent {
+            tag: PSTag::OE,
+            event: PSUnion {
+                fill: Fill::solid(false),
             },
         })
     }
 
+    /// Adds the events from a function as one object, as with
+    /// begin_object and end_object.
+    /// ```
+    /// let mut pst = pstools::PSTool::new();
+    /// pst.add_object("U1".to_string(), &[("cell", "INV_X1")], |p| {
+    ///     p.add_box(0.0, 0.0, 4.0, 10.0);
+    ///     p.add_text(0.5, 4.0, "U1".to_string());
+    /// });
+    /// assert_eq!(pst.find_object("U1"), pst.handle(0));
+    /// ```
+    pub fn add_object<F: FnOnce(&mut PSTool)>(&mut self, id: String, properties: &[(&str, &str)], draw: F) {
+        self.begin_object(id, properties);
+        draw(self);
+        self.end_object();
+    }
+
+    /// The handle of the first object with an ID (the handle of its
+    /// begin_object event), if there is one.
+    pub fn find_object(&self, id: &str) -> Option<EventHandle> {
+        let index = self
+            .events
+            .iter()
+            .position(|e| e.tag == PSTag::OB && unsafe { self.objects[e.event.object].0 == id })?;
+        self.handle(index)
+    }
+
     /// Adds a scaling effect event
     pub fn add_scale(&mut self, scale: f32) {
         self.events.push(PSEvent {
             tag: PSTag::S,
             event: PSUnion {
-                scale: Scale { scale: scale },
+                scale: Scale { scale },
             },
         })
     }
@@ -481,41 +1750,196 @@ impl PSTool {
         self.events.push(PSEvent {
             tag: PSTag::X,
             event: PSUnion {
-                translate: Translate { dx: dx, dy: dy },
+                translate: Translate { dx, dy },
             },
         })
     }
 
-    /// Generates a very simple two-dimensional chart, using floating
-    /// point numbers from the data vector.  The size of the chart is
-    /// specified by the bounding coordinates.  The data in the input
-    /// vector is scaled so that the range of values fills the vertical
-    /// span of the chart.  The chart is written with the currently
-    /// specified color.  If the input max and min are equal, the range
-    /// is determined by the data.  Otherwise, the supplied max and min will
-    /// be used (with these limits clamping the data).
-    pub fn chart(
-        &mut self,
-        data: Vec<f32>,
-        min: f32,
-        max: f32,
-        llx: f32,
-        lly: f32,
-        urx: f32,
-        ury: f32,
-    ) {
-        if data.len() == 0 || llx == urx || lly == ury {
-            return;
-        }
+    /// Appends the events of another PSTool, under a gsave/grestore
+    /// pair with the translation and scale of the transform.  Text,
+    /// notes, dash patterns, paths, and symbols are copied along with the
+    /// events; symbols defined in the other PSTool become available for
+    /// placement here as well (one with the same name as a symbol here is
+    /// renamed with a number added, so the name still places this one).
+    /// The other PSTool's events start from the default color, fill, line
+    /// style, and font, so it draws the same as it would on its own.  This
+    /// is useful for assembling figure panels that were built separately.
+    pub fn embed(&mut self, other: &PSTool, transform: Transform) {
+        // The fill state isn't part of the graphic state, so the current
+        // one is put back afterward.
+        let fill = self
+            .events
+            .iter()
+            .rev()
+            .find(|e| e.tag == PSTag::F)
+            .map(|e| unsafe { e.event.fill })
+            .unwrap_or(Fill::solid(false));
 
-        let mut chart_min;
-        let mut chart_max;
-        if min != max {
-            chart_min = min;
-            chart_max = max;
-        } else {
-            chart_min = data[0];
-            chart_max = data[0];
+        self.add_gsave();
+        self.add_translate(transform.dx, transform.dy);
+        self.add_scale(transform.scale);
+        self.reset_state();
+
+        let te = self.te.len();
+        let dashes = self.dashes.len();
+        let paths = self.paths.len();
+        let symbols = self.symbols.len();
+        let objects = self.objects.len();
+
+        let mut depth = 0;
+        for e in &other.events {
+            let mut e = e.clone();
+            unsafe {
+                match e.tag {
+                    PSTag::T | PSTag::TC | PSTag::N | PSTag::P | PSTag::LB => e.event.text.text += te,
+                    PSTag::FN => e.event.font.font_name += te,
+                    PSTag::D => e.event.dash.pattern += dashes,
+                    PSTag::PA | PSTag::CS => e.event.path.path += paths,
+                    PSTag::I => e.event.instance.symbol += symbols,
+                    PSTag::OB => e.event.object += objects,
+                    _ => {}
+                }
+            }
+            if opens(e.tag) {
+                depth += 1;
+            }
+            if closes(e.tag) && depth > 0 {
+                depth -= 1;
+            }
+            self.events.push(e);
+        }
+        // Close anything the other PSTool left open, so that the
+        // transform is undone
+        for _ in 0..depth {
+            self.add_grestore();
+        }
+        self.add_grestore();
+        self.events.push(PSEvent {
+            tag: PSTag::F,
+            event: PSUnion { fill },
+        });
+
+        self.te.extend(other.te.iter().cloned());
+        self.dashes.extend(other.dashes.iter().cloned());
+        self.paths.extend(other.paths.iter().cloned());
+        for (name, symbol) in &other.symbols {
+            let mut unique = name.clone();
+            let mut n = 1;
+            while self.symbols.iter().any(|(s, _)| *s == unique) {
+                n += 1;
+                unique = format!("{}.{}", name, n);
+            }
+            self.symbols.push((unique, symbol.clone()));
+        }
+        self.symbol_extents.extend_from_slice(&other.symbol_extents);
+        self.objects.extend(other.objects.iter().cloned());
+        self.notes.extend(other.notes.iter().cloned());
+    }
+
+    // Adds events that put the color, fill, line style, and font back to
+    // their defaults (those of a new PSTool).  The values are used as they
+    // are, without the scale set with set_scale.
+    fn reset_state(&mut self) {
+        self.set_color(0.0, 0.0, 0.0, 1.0);
+        self.set_fill(false);
+        self.events.push(PSEvent {
+            tag: PSTag::W,
+            event: PSUnion { line_width: 1.0 },
+        });
+        self.events.push(PSEvent {
+            tag: PSTag::D,
+            event: PSUnion {
+                dash: Dash {
+                    pattern: self.dashes.len(),
+                    offset: 0.0,
+                },
+            },
+        });
+        self.dashes.push(Vec::new());
+        self.set_line_cap(LineCap::Butt);
+        self.set_line_join(LineJoin::Miter);
+        self.set_miter_limit(10.0);
+        self.events.push(PSEvent {
+            tag: PSTag::FN,
+            event: PSUnion {
+                font: Font {
+                    scale: 12.0,
+                    font_name: self.te.len(),
+                },
+            },
+        });
+        self.te.push("Courier".to_string());
+    }
+
+    /// Arranges PSTools in a grid -- left to right, then top to bottom,
+    /// with the given number of columns and spacing between the cells --
+    /// and returns a PSTool with all of them embedded.  Each column is as
+    /// wide as its widest PSTool, and each row as tall as its tallest; the
+    /// PSTools are placed at the lower left of their cells, using their
+    /// bounding boxes (including any border).
+    pub fn compose_grid(tools: &[&PSTool], columns: usize, spacing: f32) -> PSTool {
+        let columns = columns.max(1);
+        let rows = tools.len().div_ceil(columns);
+        let bounds: Vec<(f32, f32, f32, f32)> = tools.iter().map(|t| t.bbox()).collect();
+
+        let mut widths = vec![0.0f32; columns];
+        let mut heights = vec![0.0f32; rows];
+        for (i, (llx, lly, urx, ury)) in bounds.iter().enumerate() {
+            widths[i % columns] = widths[i % columns].max(urx - llx);
+            heights[i / columns] = heights[i / columns].max(ury - lly);
+        }
+
+        // The first row is at the top, so rows are stacked up from the last
+        let mut row_y = vec![0.0f32; rows];
+        for r in (0..rows.saturating_sub(1)).rev() {
+            row_y[r] = row_y[r + 1] + heights[r + 1] + spacing;
+        }
+        let mut column_x = vec![0.0f32; columns];
+        for c in 1..columns {
+            column_x[c] = column_x[c - 1] + widths[c - 1] + spacing;
+        }
+
+        let mut grid = PSTool::new();
+        for (i, tool) in tools.iter().enumerate() {
+            let (llx, lly, _, _) = bounds[i];
+            let x = column_x[i % columns] - llx;
+            let y = row_y[i / columns] - lly;
+            grid.embed(tool, Transform::new(x, y, 1.0));
+        }
+        grid
+    }
+
+    /// Generates a very simple two-dimensional chart, using floating
+    /// point numbers from the data vector.  The size of the chart is
+    /// specified by the bounding coordinates.  The data in the input
+    /// vector is scaled so that the range of values fills the vertical
+    /// span of the chart.  The chart is written with the currently
+    /// specified color.  If the input max and min are equal, the range
+    /// is determined by the data.  Otherwise, the supplied max and min will
+    /// be used (with these limits clamping the data).
+    #[allow(clippy::too_many_arguments)]
+    pub fn chart(
+        &mut self,
+        data: Vec<f32>,
+        min: f32,
+        max: f32,
+        llx: f32,
+        lly: f32,
+        urx: f32,
+        ury: f32,
+    ) {
+        if data.is_empty() || llx == urx || lly == ury {
+            return;
+        }
+
+        let mut chart_min;
+        let mut chart_max;
+        if min != max {
+            chart_min = min;
+            chart_max = max;
+        } else {
+            chart_min = data[0];
+            chart_max = data[0];
             for v in &data {
                 if chart_min > *v {
                     chart_min = *v;
@@ -552,15 +1976,19 @@ impl PSTool {
 
     /// Sets the color for object rendering, using Red/Green/Blue
     /// hues, where each of these values is in the range of 0.0-1.0.
-    /// The library currently also supports an alpha color channel,
-    /// which is not supported by PostScript.  Future versions of this
-    /// library may add support for PNG file generation (where alpha
-    /// would be relevant).
+    /// The alpha channel (1.0 is opaque) is written as fill-opacity and
+    /// stroke-opacity in SVG output.  PostScript has no alpha; the
+    /// generated file uses the Ghostscript .setfillconstantalpha and
+    /// .setstrokeconstantalpha extensions, so converting with gs (9.53 or
+    /// later, run with -dALLOWPSTRANSPARENCY) gives a PDF with true
+    /// transparency.  Without the flag, and in other PostScript
+    /// interpreters, the extensions are skipped and everything is drawn
+    /// opaque; see uses_alpha to warn about this.
     pub fn set_color(&mut self, r: f32, g: f32, b: f32, a: f32) {
         self.events.push(PSEvent {
             tag: PSTag::C,
             event: PSUnion {
-                color: Color { r, g, b, _a: a },
+                color: Color { r, g, b, a },
             },
         });
     }
@@ -571,7 +1999,128 @@ impl PSTool {
         self.events.push(PSEvent {
             tag: PSTag::F,
             event: PSUnion {
-                fill: Fill { fill: state },
+                fill: Fill::solid(state),
+            },
+        });
+    }
+
+    /// Selects the color model for the generated output -- RGB (the
+    /// default), CMYK for print shops, or grayscale for venues that
+    /// require it.  Every color event is converted when the file is
+    /// written, including the colors used by charts.
+    pub fn set_color_mode(&mut self, mode: ColorMode) {
+        self.color_mode = mode;
+    }
+
+    /// Sets the %%Title of the PostScript output (by default, the file name).
+    pub fn set_title(&mut self, title: String) {
+        self.title = Some(title);
+    }
+
+    /// Sets the %%Creator of the PostScript output -- the program that
+    /// made the figure.  The default is the PSTools version.
+    pub fn set_creator(&mut self, creator: String) {
+        self.creator = Some(creator);
+    }
+
+    /// Sets the %%For of the PostScript output -- the person the figure
+    /// is for.  By default, this is the user name from the environment.
+    pub fn set_for(&mut self, user: String) {
+        self.user = Some(user);
+    }
+
+    /// Deterministic mode, for figures kept under version control.  The
+    /// PostScript output then depends only on the events: the creation
+    /// date is pinned to 1970-01-01, the title defaults to the file name
+    /// without its directory, %%For is left out unless set_for is used,
+    /// and every number -- coordinates, angles, widths, scale factors, and
+    /// color components -- is rounded to 0.001, so that differences in
+    /// the math libraries of different platforms don't show up.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Writes generate's output as a page of the given size, rather than
+    /// as an EPS file sized to the figure.  The figure (the bounds from
+    /// set_bounds, or the bounding box) is scaled to fit inside the
+    /// margins, keeping its aspect ratio; output_scale reports the scale.
+    pub fn set_page(&mut self, page: PageSetup) {
+        self.page = Some(page);
+    }
+
+    /// Goes back to EPS output, sized to the figure (the default).
+    pub fn clear_page(&mut self) {
+        self.page = None;
+    }
+
+    /// Sets the largest EPS figure generate will write, in points.  Larger
+    /// figures are scaled down to fit, since some PostScript interpreters
+    /// have trouble with very large pages.  The default is 10000 points;
+    /// zero turns the scaling off.  This does not apply with set_page.
+    pub fn set_max_size(&mut self, max_size: f32) {
+        self.max_size = max_size;
+    }
+
+    /// The scale generate applies to the figure -- the fit-to-page scale
+    /// with set_page, otherwise 1.0 unless the figure is larger than the
+    /// maximum size.
+    pub fn output_scale(&self) -> f32 {
+        let (llx, lly, urx, ury) = self.output_bounds();
+        let dx = urx - llx;
+        let dy = ury - lly;
+        if let Some(page) = self.page {
+            let (w, h) = page.dimensions();
+            let w = w - 2.0 * page.margin;
+            let h = h - 2.0 * page.margin;
+            if dx <= 0.0 || dy <= 0.0 || w <= 0.0 || h <= 0.0 {
+                return 1.0;
+            }
+            return (w / dx).min(h / dy);
+        }
+        let max_dim = dx.max(dy);
+        if self.max_size > 0.0 && max_dim > self.max_size {
+            self.max_size / max_dim
+        } else {
+            1.0
+        }
+    }
+
+    // Where the figure's origin goes on the page, after scaling.
+    fn page_offset(&self, page: &PageSetup, scale: f32) -> (f32, f32) {
+        let (llx, lly, urx, ury) = self.output_bounds();
+        let (w, h) = page.dimensions();
+        let dx = (urx - llx) * scale;
+        let dy = (ury - lly) * scale;
+        let (x, y) = if page.center {
+            ((w - dx) / 2.0, (h - dy) / 2.0)
+        } else {
+            (page.margin, h - page.margin - dy)
+        };
+        (x - llx * scale, y - lly * scale)
+    }
+
+    /// Turns on filling with a hatch, cross-hatch, dot, or checkerboard
+    /// pattern (or solid, which is the same as set_fill(true)).  The
+    /// spacing is the distance between the lines, dots, or squares, and
+    /// the angle rotates the pattern; a Hatch with an angle of 45 is the
+    /// usual diagonal hatch.  The pattern is drawn in the current color,
+    /// and applies to boxes and circles.  Use set_fill(false) to return
+    /// to outlines, or set_fill(true) for solid fills.  The spacing has to
+    /// be more than zero; otherwise, the call is ignored.  Like line
+    /// widths, the spacing is scaled by add_scale.
+    pub fn set_fill_pattern(&mut self, pattern: FillPattern, spacing: f32, angle: f32) {
+        if spacing.is_nan() || spacing <= 0.0 {
+            return;
+        }
+        self.events.push(PSEvent {
+            tag: PSTag::F,
+            event: PSUnion {
+                fill: Fill {
+                    fill: true,
+                    pattern,
+                    spacing: spacing * self.scale,
+                    angle,
+                },
             },
         });
     }
@@ -602,7 +2151,7 @@ impl PSTool {
     /// reasonable.  Set the scale prior to adding elements; the scaling
     /// factor is applied to coordinates as these elements are added.
     pub fn deprecated_set_scale(&mut self, scale: f32) {
-        self.scale = self.scale * scale;
+        self.scale *= scale;
         let new_font = self.font.clone();
         let new_scale = self.font_scale;
         self.set_font(new_scale, new_font);
@@ -639,346 +2188,1904 @@ impl PSTool {
     }
 
     /// Returns the bounding box of elements that have been added.
-    /// The bounding box does not track text entries -- only lines, and boxes.
+    /// The bounding box does not track text entries -- only lines, boxes,
+    /// and circles.  Outlines include half the current line width.
     pub fn bbox(&self) -> (f32, f32, f32, f32) {
+        let bbox = self.extents();
+        if !bbox.valid {
+            return (0.0, 0.0, 0.0, 0.0);
+        }
+
+        // Expand the bbox by the requested border size
+        let llx = bbox.llx - self.border;
+        let lly = bbox.lly - self.border;
+        let urx = bbox.urx + self.border;
+        let ury = bbox.ury + self.border;
+
+        (llx, lly, urx, ury)
+    }
+
+    // The extents of the events, without the border.  Symbols use this
+    // for the placement and extents of their instances.
+    fn extents(&self) -> BBox {
+        self.measure().0
+    }
+
+    // The extents of the events, and the extents of each object (by its
+    // index in the objects).
+    fn measure(&self) -> (BBox, Vec<BBox>) {
         let mut bbox = bbox::BBox::new();
+        let mut objects = vec![BBox::new(); self.objects.len()];
+        let mut open = Vec::new();
         let mut scale = 1.0;
         let mut offset_x = 0.0;
         let mut offset_y = 0.0;
 
+        // Outlines extend half a line width past the coordinates, so the
+        // line width, cap, and fill state are tracked as well.
+        let mut line_width = 1.0;
+        let mut line_cap = LineCap::Butt;
+        let mut fillstate = false;
+
+        // Clipping regions, in output coordinates.  Each object's extents
+        // are intersected with the active clip.
+        let mut clip: Option<BBox> = None;
+
+        let symbol_extents = &self.symbol_extents;
+
         let mut stack = Vec::new();
 
         for e in &self.events {
+            // Extents of this event's object, if it draws anything
+            let mut obj = BBox::new();
             unsafe {
-                if e.tag == PSTag::GS {
+                if opens(e.tag) {
                     stack.push(PSStack {
                         scale,
                         offset_x,
                         offset_y,
+                        line_width,
+                        line_cap,
+                        clip,
                     });
                 }
-                if e.tag == PSTag::GR {
-                    if stack.len() > 0 {
+                if e.tag == PSTag::CS {
+                    let local = self.paths[e.event.path.path].bbox();
+                    let mut region = BBox::new();
+                    if local.valid {
+                        region.addpoint(local.llx * scale + offset_x, local.lly * scale + offset_y);
+                        region.addpoint(local.urx * scale + offset_x, local.ury * scale + offset_y);
+                    }
+                    clip = Some(match clip {
+                        Some(c) => c.intersect(&region),
+                        None => region,
+                    });
+                }
+                if closes(e.tag) {
+                    if !stack.is_empty() {
                         let state = stack.pop().unwrap();
                         scale = state.scale;
                         offset_x = state.offset_x;
                         offset_y = state.offset_y;
+                        line_width = state.line_width;
+                        line_cap = state.line_cap;
+                        clip = state.clip;
                     } else {
                         println!("PSTools: Unmatched graphic restore");
                     }
                 }
-                if e.tag == PSTag::S {
-                    scale = scale * e.event.scale.scale;
+                if e.tag == PSTag::S {
+                    scale *= e.event.scale.scale;
+                }
+                if e.tag == PSTag::X {
+                    offset_x += scale * e.event.translate.dx;
+                    offset_y += scale * e.event.translate.dy;
+                }
+                if e.tag == PSTag::W {
+                    line_width = e.event.line_width;
+                }
+                if e.tag == PSTag::LC {
+                    line_cap = e.event.line_cap;
+                }
+                if e.tag == PSTag::F {
+                    fillstate = e.event.fill.fill;
+                }
+
+                // Half the stroke width, in output coordinates.  Square caps
+                // stick out past the end of a line as well, so allow for
+                // them in any direction.
+                let mut pad = 0.5 * line_width * scale;
+                if line_cap == LineCap::Square {
+                    pad *= std::f32::consts::SQRT_2;
+                }
+                let filled_pad = if fillstate { 0.0 } else { pad };
+
+                if e.tag == PSTag::B || e.tag == PSTag::L {
+                    // In the events, boxes and lines have both corners
+                    let pad = if e.tag == PSTag::B { filled_pad } else { pad };
+                    let x = e.event.line.llx * scale + offset_x;
+                    let y = e.event.line.lly * scale + offset_y;
+                    obj.addpoint(x - pad, y - pad);
+                    obj.addpoint(x + pad, y + pad);
+
+                    let x = e.event.line.urx * scale + offset_x;
+                    let y = e.event.line.ury * scale + offset_y;
+                    obj.addpoint(x - pad, y - pad);
+                    obj.addpoint(x + pad, y + pad);
+                }
+                if e.tag == PSTag::H {
+                    let h = e.event.head;
+                    let pad = if h.filled { 0.0 } else { pad };
+                    for (x, y) in [(h.tip_x, h.tip_y), (h.lx, h.ly), (h.rx, h.ry)] {
+                        let x = x * scale + offset_x;
+                        let y = y * scale + offset_y;
+                        obj.addpoint(x - pad, y - pad);
+                        obj.addpoint(x + pad, y + pad);
+                    }
+                }
+                // The remaining shapes compute their extents locally; with
+                // only scaling and translation, the corners can then be
+                // mapped to the output.
+                let mut local = BBox::new();
+                let mut local_pad = filled_pad;
+                if e.tag == PSTag::A {
+                    let a = e.event.arc;
+                    local.addarc(a.x, a.y, a.r, a.start, a.end);
+                    local_pad = pad;
+                }
+                if e.tag == PSTag::WG {
+                    let a = e.event.arc;
+                    local.addarc(a.x, a.y, a.r, a.start, a.end);
+                    local.addpoint(a.x, a.y);
+                }
+                if e.tag == PSTag::E {
+                    let el = e.event.ellipse;
+                    let (sin, cos) = el.angle.to_radians().sin_cos();
+                    let hx = ((el.rx * cos).powi(2) + (el.ry * sin).powi(2)).sqrt();
+                    let hy = ((el.rx * sin).powi(2) + (el.ry * cos).powi(2)).sqrt();
+                    local.addpoint(el.x - hx, el.y - hy);
+                    local.addpoint(el.x + hx, el.y + hy);
+                }
+                if e.tag == PSTag::RB {
+                    let b = e.event.rbox;
+                    local.addpoint(b.llx, b.lly);
+                    local.addpoint(b.urx, b.ury);
+                }
+                if e.tag == PSTag::V {
+                    let c = e.event.curve;
+                    local.addcubic(c.x0, c.y0, c.x1, c.y1, c.x2, c.y2, c.x3, c.y3);
+                    if !(c.fillable && fillstate) {
+                        local_pad = pad;
+                    }
+                }
+                if e.tag == PSTag::PA {
+                    local = self.paths[e.event.path.path].bbox();
+                    local_pad = if e.event.path.stroke { pad } else { 0.0 };
+                }
+                if local.valid {
+                    obj.addpoint(
+                        local.llx * scale + offset_x - local_pad,
+                        local.lly * scale + offset_y - local_pad,
+                    );
+                    obj.addpoint(
+                        local.urx * scale + offset_x + local_pad,
+                        local.ury * scale + offset_y + local_pad,
+                    );
+                }
+                if e.tag == PSTag::R {
+                    // Circles have the center, and the radius in urx
+                    let x = e.event.line.llx * scale + offset_x;
+                    let y = e.event.line.lly * scale + offset_y;
+                    let r = e.event.line.urx.abs() * scale + filled_pad;
+                    obj.addpoint(x - r, y - r);
+                    obj.addpoint(x + r, y + r);
+                }
+                if e.tag == PSTag::I {
+                    // The corners of the symbol's extents, oriented, scaled,
+                    // and placed
+                    let inst = e.event.instance;
+                    let sym = symbol_extents[inst.symbol];
+                    if sym.valid {
+                        let (a, b, c, d) = inst.orient.matrix();
+                        for (px, py) in [
+                            (sym.llx, sym.lly),
+                            (sym.urx, sym.lly),
+                            (sym.urx, sym.ury),
+                            (sym.llx, sym.ury),
+                        ] {
+                            let x = inst.scale * (a * px + c * py) + inst.x;
+                            let y = inst.scale * (b * px + d * py) + inst.y;
+                            obj.addpoint(x * scale + offset_x, y * scale + offset_y);
+                        }
+                    }
+                }
+            }
+            if let Some(c) = clip {
+                obj = obj.intersect(&c);
+            }
+            if obj.valid {
+                bbox.addpoint(obj.llx, obj.lly);
+                bbox.addpoint(obj.urx, obj.ury);
+                for &i in &open {
+                    let o: &mut BBox = &mut objects[i];
+                    o.addpoint(obj.llx, obj.lly);
+                    o.addpoint(obj.urx, obj.ury);
+                }
+            }
+            if e.tag == PSTag::OB {
+                open.push(unsafe { e.event.object });
+            }
+            if e.tag == PSTag::OE {
+                open.pop();
+            }
+        }
+
+        (bbox, objects)
+    }
+    // The distinct fill patterns used, in order of first use (those
+    // in symbols first).
+    fn fill_patterns(&self) -> Vec<Fill> {
+        let mut patterns: Vec<Fill> = Vec::new();
+        for (_, symbol) in &self.symbols {
+            for fill in symbol.fill_patterns() {
+                if !patterns.iter().any(|p| p.same_pattern(&fill)) {
+                    patterns.push(fill);
+                }
+            }
+        }
+        for e in &self.events {
+            if e.tag == PSTag::F {
+                let fill = unsafe { e.event.fill };
+                if fill.pattern != FillPattern::Solid
+                    && !patterns.iter().any(|p| p.same_pattern(&fill))
+                {
+                    patterns.push(fill);
+                }
+            }
+        }
+        patterns
+    }
+
+    // The fonts used, in order of first use (those in symbols first).
+    // Courier is the initial font, so it is always included.
+    fn fonts(&self) -> Vec<String> {
+        let mut fonts = vec!["Courier".to_string()];
+        let used = self
+            .symbols
+            .iter()
+            .flat_map(|(_, s)| s.fonts())
+            .chain(
+                self.events
+                    .iter()
+                    .filter(|e| e.tag == PSTag::FN)
+                    .map(|e| self.te[unsafe { e.event.font.font_name }].clone()),
+            );
+        for font in used {
+            if !fonts.contains(&font) {
+                fonts.push(font);
+            }
+        }
+        fonts
+    }
+
+    // True if any text has characters outside of ASCII.
+    fn uses_latin1(&self) -> bool {
+        self.events.iter().any(|e| {
+            (e.tag == PSTag::T || e.tag == PSTag::TC)
+                && !self.te[unsafe { e.event.text.text }].is_ascii()
+        }) || self.symbols.iter().any(|(_, s)| s.uses_latin1())
+    }
+
+    /// True if any color is translucent (alpha below 1.0).  Alpha is only
+    /// rendered from the PostScript output by Ghostscript 9.53 or later,
+    /// run with -dALLOWPSTRANSPARENCY (otherwise it is drawn opaque), so
+    /// callers may want to warn.
+    pub fn uses_alpha(&self) -> bool {
+        self.events
+            .iter()
+            .any(|e| e.tag == PSTag::C && unsafe { e.event.color.a } < 1.0)
+            || self.symbols.iter().any(|(_, s)| s.uses_alpha())
+    }
+
+    // The bounds used for output -- the explicit bounds from
+    // set_bounds if there are any, otherwise the bounding box of the
+    // events (including the border).
+    fn output_bounds(&self) -> (f32, f32, f32, f32) {
+        if self.bbox.valid {
+            (self.bbox.llx, self.bbox.lly, self.bbox.urx, self.bbox.ury)
+        } else {
+            self.bbox()
+        }
+    }
+
+    /// Returns the length of the event vector -- the number of objects
+    /// that have been added.  If no events have been added to a PSTool,
+    /// PostScript output will not be generated.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns true if no events have been added.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// generates the PostScript output from the stored events.
+    /// Returns the number of events processed (zero would indicate
+    /// some sort of error).
+    pub fn generate(&self, filepath: String) -> Result<usize> {
+        // let mut f;
+
+        // if the file path is empty, just print to standard out
+        // f = Box::new(File::create(filepath).unwrap()) as Box<dyn Write>;
+        let mut f = match File::create(&filepath) {
+            Ok(file) => file,
+            Err(err) => {
+                println!("File open error {}", err);
+                return Ok(0);
+            }
+        };
+
+        let (llx, lly, urx, ury) = self.output_bounds();
+        let scale = self.output_scale();
+
+        if self.page.is_some() {
+            writeln!(&mut f, "%!PS-Adobe-3.0")?;
+        } else {
+            writeln!(&mut f, "%!PS-Adobe-3.0 EPSF-3.0")?;
+        }
+        // In deterministic mode, nothing depends on the time or the
+        // environment, and coordinates are rounded.
+        let round: fn(f32) -> f32 = if self.deterministic { round_output } else { |v| v };
+        let (llx, lly, urx, ury) = (round(llx * scale), round(lly * scale), round(urx * scale), round(ury * scale));
+        let title = match &self.title {
+            Some(title) => title.clone(),
+            None if self.deterministic => std::path::Path::new(&filepath)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            None => filepath.clone(),
+        };
+        writeln!(&mut f, "%%Title: {}", dsc_text(&title))?;
+        let creator = self.creator.clone().unwrap_or_else(pstools_version);
+        writeln!(&mut f, "%%Creator: {}", dsc_text(&creator))?;
+        let secs = if self.deterministic {
+            0
+        } else {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0)
+        };
+        writeln!(&mut f, "%%CreationDate: {}", dsc_date(secs))?;
+        let user = if self.deterministic {
+            self.user.clone()
+        } else {
+            self.user
+                .clone()
+                .or_else(|| std::env::var("USER").ok())
+                .or_else(|| std::env::var("USERNAME").ok())
+        };
+        if let Some(user) = user {
+            writeln!(&mut f, "%%For: {}", dsc_text(&user))?;
+        }
+        if let Some(page) = self.page {
+            let (w, h) = page.dimensions();
+            writeln!(&mut f, "%%BoundingBox: 0 0 {} {}", w.ceil(), h.ceil())?;
+            writeln!(&mut f, "%%HiResBoundingBox: 0 0 {} {}", w, h)?;
+            writeln!(&mut f, "%%DocumentMedia: {} {} {} 0 () ()", page.size.name(), w, h)?;
+            // The page size is already swapped; this tells viewers which
+            // way up to show it.
+            let orientation = match page.orientation {
+                PageOrientation::Portrait => "Portrait",
+                PageOrientation::Landscape => "Landscape",
+            };
+            writeln!(&mut f, "%%Orientation: {}", orientation)?;
+        } else {
+            // The bounding box has to be in integers; the exact one is
+            // given as well.
+            writeln!(
+                &mut f,
+                "%%BoundingBox: {} {} {} {}",
+                llx.floor(), lly.floor(), urx.ceil(), ury.ceil()
+            )?;
+            writeln!(&mut f, "%%HiResBoundingBox: {} {} {} {}", llx, lly, urx, ury)?;
+        }
+        writeln!(&mut f, "%%DocumentData: Clean7Bit")?;
+        for (i, font) in self.fonts().iter().enumerate() {
+            if i == 0 {
+                writeln!(&mut f, "%%DocumentNeededResources: font {}", font)?;
+            } else {
+                writeln!(&mut f, "%%+ font {}", font)?;
+            }
+        }
+        writeln!(&mut f, "%%LanguageLevel: 2")?;
+        writeln!(&mut f, "%%Pages: 1")?;
+        writeln!(&mut f, "%%EndComments")?;
+        // Ghostscript only defines the alpha operators with this flag
+        let uses_alpha = self.uses_alpha();
+        let transparency = if uses_alpha { "-dALLOWPSTRANSPARENCY " } else { "" };
+        if self.page.is_some() {
+            writeln!(&mut f, "%% gs {}-o filename.pdf -sDEVICE=pdfwrite filename.ps", transparency)?;
+        } else {
+            writeln!(
+                &mut f,
+                "%% gs {}-o filename.pdf -sDEVICE=pdfwrite -dEPSCrop filename.ps",
+                transparency
+            )?;
+        }
+
+        writeln!(&mut f, "%% Binghamton PSTools PostScript Generator")?;
+        writeln!(
+            &mut f,
+            "%% https://github.com/profmadden/pstools_r for more information."
+        )?;
+        writeln!(&mut f, "%% ").unwrap();
+        for s in &self.notes {
+            writeln!(&mut f, "%% {}", dsc_text(s))?;
+        }
+
+        writeln!(&mut f, "%%BeginProlog")?;
+        // Some definitions for boxes and lines, to make the files more compact
+        writeln!(&mut f, "/bs {{/h 2 1 roll def /w 2 1 roll def /oy 2 1 roll def /ox 2 1 roll def newpath ox oy moveto")?;
+        writeln!(&mut f, "ox w add oy lineto")?;
+        writeln!(&mut f, "ox w add oy h add lineto")?;
+        writeln!(&mut f, "ox oy h add lineto")?;
+        writeln!(&mut f, "closepath stroke}} def")?;
+
+        // Filled box
+        writeln!(&mut f, "/bf {{/h 2 1 roll def /w 2 1 roll def /oy 2 1 roll def /ox 2 1 roll def newpath ox oy moveto")?;
+        writeln!(&mut f, "ox w add oy lineto")?;
+        writeln!(&mut f, "ox w add oy h add lineto")?;
+        writeln!(&mut f, "ox oy h add lineto")?;
+        writeln!(&mut f, "closepath fill}} def")?;        
+
+        // Line
+        writeln!(&mut f, "/ln {{/h 2 1 roll def /w 2 1 roll def /oy 2 1 roll def /ox 2 1 roll def newpath ox oy moveto")?;
+        writeln!(&mut f, "w h lineto")?;
+        writeln!(&mut f, "stroke}} def")?;                
+
+        // Text beyond ASCII needs fonts with the ISO Latin-1 encoding
+        let latin1 = self.uses_latin1();
+        if latin1 {
+            writeln!(&mut f, "/rf {{findfont dup length dict begin {{1 index /FID ne {{def}} {{pop pop}} ifelse}} forall /Encoding ISOLatin1Encoding def currentdict end /PSToolsLatin1 exch definefont}} def")?;
+        }
+
+        // Alpha, if any color uses it.  The operators only exist in
+        // Ghostscript, so check for them before use.
+        if uses_alpha {
+            writeln!(&mut f, "%% Transparency requires Ghostscript 9.53 or later, with -dALLOWPSTRANSPARENCY")?;
+            writeln!(&mut f, "/sa {{/.setfillconstantalpha where {{pop dup .setfillconstantalpha .setstrokeconstantalpha}} {{pop}} ifelse}} def")?;
+        }
+
+        // Objects become PDF annotations; pdfmark is ignored by printers.
+        if !self.objects.is_empty() {
+            writeln!(&mut f, "/pdfmark where {{pop}} {{userdict /pdfmark /cleartomark load put}} ifelse")?;
+        }
+
+        writeln!(&mut f, "%%EndProlog")?;
+
+        // Document setup -- the page size, fill patterns (one definition
+        // for each distinct pattern used), and symbol procedures
+        writeln!(&mut f, "%%BeginSetup")?;
+        if let Some(page) = self.page {
+            let (w, h) = page.dimensions();
+            writeln!(&mut f, "<< /PageSize [{} {}] >> setpagedevice", w, h)?;
+        }
+        let patterns = self.fill_patterns();
+        for (i, p) in patterns.iter().enumerate() {
+            writeln!(&mut f, "/pat{} {} def", i, p.pattern_proc(scale, round))?;
+        }
+        let out = PSOutput {
+            scale,
+            round,
+            patterns,
+            uses_alpha,
+            latin1,
+            color_mode: self.color_mode,
+        };
+        self.write_symbols(&mut f, &out, "")?;
+        writeln!(&mut f, "%%EndSetup")?;
+
+        writeln!(&mut f, "%%Page: 1 1")?;
+        writeln!(&mut f, "/Courier {} {} scalefont setfont", out.find_font(), round(12.0 * scale))?;
+        if scale != 1.0 {
+            // Line widths are scaled with everything else, including the
+            // default of 1
+            writeln!(&mut f, "{} setlinewidth", round(scale))?;
+        }
+        if let Some(page) = self.page {
+            let (x, y) = self.page_offset(&page, scale);
+            writeln!(&mut f, "{} {} translate", round(x), round(y))?;
+        }
+        self.write_events(&mut f, &out, "")?;
+        self.write_annotations(&mut f, &out)?;
+        writeln!(&mut f, "showpage")?;
+        writeln!(&mut f, "%%Trailer")?;
+        writeln!(&mut f, "%%EOF\n").unwrap();
+
+        Ok(self.events.len())
+    }
+
+    // Writes an invisible PDF annotation over each object, with the ID as
+    // the name and the properties as the contents, so that PDF viewers
+    // show them on hover.
+    fn write_annotations(&self, f: &mut dyn Write, out: &PSOutput) -> Result<()> {
+        let n = out.round;
+        let scale = out.scale;
+        let (_, extents) = self.measure();
+        for ((id, properties), b) in self.objects.iter().zip(extents) {
+            if !b.valid {
+                continue;
+            }
+            let contents: Vec<String> = properties.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+            writeln!(
+                f,
+                "[ /Rect [{} {} {} {}] /Subtype /Square /BS << /W 0 >> /NM ({}) /T ({}) /Contents ({}) /ANN pdfmark",
+                n(b.llx * scale),
+                n(b.lly * scale),
+                n(b.urx * scale),
+                n(b.ury * scale),
+                ps_string(id),
+                ps_string(id),
+                ps_string(&contents.join("\n"))
+            )?;
+        }
+        Ok(())
+    }
+
+    // Writes the PostScript for the events.  Symbols have their own
+    // PSTool, so this is also used for the symbol procedures; the prefix
+    // keeps the procedure names of nested symbols distinct.
+    fn write_events(&self, f: &mut dyn Write, out: &PSOutput, prefix: &str) -> Result<()> {
+        let scale = out.scale;
+        let n = out.round;
+        let patterns = &out.patterns;
+        let uses_alpha = out.uses_alpha;
+        // Patterned fills need the current color, which gsave/grestore
+        // also cover.
+        let mut fill = Fill::solid(false);
+        let mut color = Color {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+            a: 1.0,
+        };
+        let mut color_stack = Vec::new();
+        for e in &self.events {
+            // println!("Got event ");
+            unsafe {
+                // Patterned fills are wrapped in gsave/grestore, so that the
+                // pattern color space does not stick.
+                let (fill_start, fill_end) = if fill.pattern == FillPattern::Solid {
+                    (String::new(), String::new())
+                } else {
+                    let i = patterns.iter().position(|p| p.same_pattern(&fill)).unwrap();
+                    (
+                        format!("gsave {} ", color.pattern(out.color_mode, &format!("pat{}", i), n)),
+                        " grestore".to_string(),
+                    )
+                };
+                if e.tag == PSTag::C {
+                    color = e.event.color;
+                    let c = e.event.color;
+                    writeln!(f, "{}", c.postscript(out.color_mode, n)).unwrap();
+                    if uses_alpha {
+                        writeln!(f, "{} sa", n(c.a))?;
+                    }
+                }
+                if e.tag == PSTag::B {
+                    if fill.fill {
+                        writeln!(
+                            f,
+                            "{}{} {} {} {} bf{}", fill_start, n(scale * e.event.line.llx), n(scale * e.event.line.lly),
+                                                n(scale * (e.event.line.urx - e.event.line.llx)), n(scale * (e.event.line.ury - e.event.line.lly)), fill_end)?;
+                    } else {
+                        writeln!(
+                            f,
+                            "{} {} {} {} bs", n(scale * e.event.line.llx), n(scale * e.event.line.lly),
+                            n(scale * (e.event.line.urx - e.event.line.llx)), n(scale * (e.event.line.ury - e.event.line.lly)))?;
+                    }
+                }
+                if e.tag == PSTag::L {
+                    writeln!(f, "{} {} {} {} ln", n(scale * e.event.line.llx), n(scale * e.event.line.lly),
+                        n(scale * e.event.line.urx), n(scale * e.event.line.ury))?;
+                }
+                if e.tag == PSTag::W {
+                    writeln!(f, "{} setlinewidth", n(e.event.line_width * scale))?;
+                }
+                if e.tag == PSTag::D {
+                    let pattern: Vec<String> = self.dashes[e.event.dash.pattern]
+                        .iter()
+                        .map(|d| n(d * scale).to_string())
+                        .collect();
+                    writeln!(
+                        f,
+                        "[{}] {} setdash",
+                        pattern.join(" "),
+                        n(e.event.dash.offset * scale)
+                    )?;
+                }
+                if e.tag == PSTag::LC {
+                    writeln!(f, "{} setlinecap", e.event.line_cap as u8)?;
+                }
+                if e.tag == PSTag::LJ {
+                    writeln!(f, "{} setlinejoin", e.event.line_join as u8)?;
+                }
+                if e.tag == PSTag::ML {
+                    writeln!(f, "{} setmiterlimit", n(e.event.miter_limit))?;
+                }
+                if e.tag == PSTag::R {
+                    if fill.fill {
+                        writeln!(
+                            f,
+                            "{}newpath {} {} {} 0 360 arc fill{}",
+                            fill_start, n(scale * e.event.line.llx), n(scale * e.event.line.lly), n(scale * e.event.line.urx), fill_end
+                        )?;
+                    } else {
+                        writeln!(
+                            f,
+                            "newpath {} {} {} 0 360 arc stroke",
+                            n(scale * e.event.line.llx), n(scale * e.event.line.lly), n(scale * e.event.line.urx)
+                        )?;
+                    }
+                }
+                // Fillable shapes end with the fill (or stroke)
+                let paint = if fill.fill {
+                    format!("fill{}", fill_end)
+                } else {
+                    "stroke".to_string()
+                };
+                let paint_start = if fill.fill { fill_start.as_str() } else { "" };
+                if e.tag == PSTag::A {
+                    let a = e.event.arc;
+                    writeln!(
+                        f,
+                        "newpath {} {} {} {} {} arc stroke",
+                        n(scale * a.x), n(scale * a.y), n(scale * a.r), n(a.start), n(a.end)
+                    )?;
+                }
+                if e.tag == PSTag::WG {
+                    let a = e.event.arc;
+                    writeln!(
+                        f,
+                        "{}newpath {} {} moveto {} {} {} {} {} arc closepath {}",
+                        paint_start, n(scale * a.x), n(scale * a.y), n(scale * a.x), n(scale * a.y), n(scale * a.r), n(a.start), n(a.end), paint
+                    )?;
+                }
+                if e.tag == PSTag::E {
+                    // Scale a unit circle, then restore the matrix before
+                    // stroking, so that the line width isn't distorted.
+                    let el = e.event.ellipse;
+                    writeln!(
+                        f,
+                        "{}newpath matrix currentmatrix {} {} translate {} rotate {} {} scale 0 0 1 0 360 arc setmatrix {}",
+                        paint_start, n(scale * el.x), n(scale * el.y), n(el.angle), n(scale * el.rx), n(scale * el.ry), paint
+                    )?;
+                }
+                if e.tag == PSTag::RB {
+                    let (llx, lly, urx, ury, r) = e.event.rbox.normalized();
+                    let (llx, lly, urx, ury, r) = (n(scale * llx), n(scale * lly), n(scale * urx), n(scale * ury), n(scale * r));
+                    // arcto fails on a zero radius, so square corners are
+                    // drawn as a plain box
+                    if r <= 0.0 {
+                        writeln!(
+                            f,
+                            "{}newpath {} {} moveto {} {} lineto {} {} lineto {} {} lineto closepath {}",
+                            paint_start, llx, lly, urx, lly, urx, ury, llx, ury, paint
+                        )?;
+                    } else {
+                        writeln!(
+                            f,
+                            "{}newpath {} {} moveto {} {} {} {} {} arcto 4 {{pop}} repeat {} {} {} {} {} arcto 4 {{pop}} repeat",
+                            paint_start, llx + r, lly, urx, lly, urx, ury, r, urx, ury, llx, ury, r
+                        )?;
+                        writeln!(
+                            f,
+                            "{} {} {} {} {} arcto 4 {{pop}} repeat {} {} {} {} {} arcto 4 {{pop}} repeat closepath {}",
+                            llx, ury, llx, lly, r, llx, lly, urx, lly, r, paint
+                        )?;
+                    }
+                }
+                if e.tag == PSTag::PA {
+                    let p = e.event.path;
+                    let path = &self.paths[p.path];
+                    let fill_op = match path.fill_rule {
+                        FillRule::NonZero => "fill",
+                        FillRule::EvenOdd => "eofill",
+                    };
+                    let paint = match (p.fill, p.stroke) {
+                        (true, true) => format!("gsave {}{}{} grestore stroke", fill_start, fill_op, fill_end),
+                        (true, false) => format!("{}{}{}", fill_start, fill_op, fill_end),
+                        (false, true) => "stroke".to_string(),
+                        (false, false) => "newpath".to_string(),
+                    };
+                    writeln!(f, "{} {}", path.postscript(scale, n), paint)?;
+                }
+                if e.tag == PSTag::V {
+                    let c = e.event.curve;
+                    let (start, paint) = if c.fillable && fill.fill {
+                        (paint_start, format!("closepath {}", paint))
+                    } else {
+                        ("", "stroke".to_string())
+                    };
+                    writeln!(
+                        f,
+                        "{}newpath {} {} moveto {} {} {} {} {} {} curveto {}",
+                        start,
+                        n(scale * c.x0),
+                        n(scale * c.y0),
+                        n(scale * c.x1),
+                        n(scale * c.y1),
+                        n(scale * c.x2),
+                        n(scale * c.y2),
+                        n(scale * c.x3),
+                        n(scale * c.y3),
+                        paint
+                    )?;
+                }
+                if e.tag == PSTag::F {
+                    fill = e.event.fill;
+                }
+                if e.tag == PSTag::T {
+                    if e.event.text.angle != 0.0 {
+                        writeln!(
+                            f,
+                            "gsave {} {} translate {} rotate 0 0 moveto",
+                            n(scale * e.event.text.x), n(scale * e.event.text.y), n(e.event.text.angle)
+                        )
+                        .unwrap();
+                        writeln!(f, "({}) show grestore", ps_string(&self.te[e.event.text.text])).unwrap();
+                    } else {
+                        writeln!(f, "{} {} moveto", n(scale * e.event.text.x), n(scale * e.event.text.y)).unwrap();
+                        writeln!(f, "({}) show", ps_string(&self.te[e.event.text.text])).unwrap();
+                    }
+                }
+                if e.tag == PSTag::TC {
+                    writeln!(
+                        f,
+                        "gsave {} {} translate {} rotate",
+                        n(scale * e.event.text.x), n(scale * e.event.text.y), n(e.event.text.angle)
+                    )?;
+                    writeln!(
+                        f,
+                        "({}) dup stringwidth pop 2 div neg 0 moveto show grestore",
+                        ps_string(&self.te[e.event.text.text])
+                    )?;
+                }
+                if e.tag == PSTag::H {
+                    let h = e.event.head;
+                    writeln!(
+                        f,
+                        "newpath {} {} moveto {} {} lineto {} {} lineto {}",
+                        n(scale * h.lx), n(scale * h.ly), n(scale * h.tip_x), n(scale * h.tip_y), n(scale * h.rx), n(scale * h.ry),
+                        if h.filled { "closepath fill" } else { "stroke" }
+                    )?;
+                }
+                if e.tag == PSTag::N {
+                    writeln!(f, "%% {}", dsc_text(&self.te[e.event.text.text])).unwrap();
+                }
+                if e.tag == PSTag::FN {
+                    writeln!(
+                        f,
+                        "/{} {} {} scalefont setfont",
+                        self.te[e.event.font.font_name], out.find_font(), n(e.event.font.scale * scale)
+                    )
+                    .unwrap();
+                }
+                if e.tag == PSTag::P {
+                    writeln!(f, "{}", self.te[e.event.text.text])?;
+                }
+                if e.tag == PSTag::GS {
+                    writeln!(f, "gsave")?;
+                    color_stack.push(color);
+                }
+                if e.tag == PSTag::LB {
+                    writeln!(f, "%% Layer: {}", dsc_text(&self.te[e.event.text.text]))?;
+                    writeln!(f, "gsave")?;
+                    color_stack.push(color);
+                }
+                if e.tag == PSTag::CS {
+                    let path = &self.paths[e.event.path.path];
+                    let clip_op = match path.fill_rule {
+                        FillRule::NonZero => "clip",
+                        FillRule::EvenOdd => "eoclip",
+                    };
+                    writeln!(f, "gsave {} {} newpath", path.postscript(scale, n), clip_op)?;
+                    color_stack.push(color);
+                }
+                if e.tag == PSTag::OB {
+                    let (id, properties) = &self.objects[e.event.object];
+                    writeln!(f, "%% Object: {}", dsc_text(id))?;
+                    for (key, value) in properties {
+                        writeln!(f, "%%   {}: {}", dsc_text(key), dsc_text(value))?;
+                    }
+                    writeln!(f, "gsave")?;
+                    color_stack.push(color);
+                }
+                if closes(e.tag) {
+                    writeln!(f, "grestore")?;
+                    if let Some(c) = color_stack.pop() {
+                        color = c;
+                    }
+                }
+                if e.tag == PSTag::S {
+                    writeln!(
+                        f,
+                        "{} {} scale",
+                        n(e.event.scale.scale), n(e.event.scale.scale)
+                    )?;
+                }
+                if e.tag == PSTag::X {
+                    writeln!(
+                        f,
+                        "{} {} translate",
+                        n(scale * e.event.translate.dx), n(scale * e.event.translate.dy)
+                    )?;
+                }
+                if e.tag == PSTag::I {
+                    let inst = e.event.instance;
+                    let (a, b, c, d) = inst.orient.matrix();
+                    writeln!(
+                        f,
+                        "gsave {} {} translate [{} {} {} {} 0 0] concat {} {} scale sym{}{} grestore",
+                        n(scale * inst.x), n(scale * inst.y), n(a), n(b), n(c), n(d), n(inst.scale), n(inst.scale), prefix, inst.symbol
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Writes a procedure for each symbol, after the procedures for any
+    // symbols nested inside it.
+    fn write_symbols(&self, f: &mut dyn Write, out: &PSOutput, prefix: &str) -> Result<()> {
+        for (i, (name, symbol)) in self.symbols.iter().enumerate() {
+            let inner = format!("{}{}_", prefix, i);
+            symbol.write_symbols(f, out, &inner)?;
+            writeln!(f, "%% Symbol {}", dsc_text(name))?;
+            writeln!(f, "/sym{}{} {{", prefix, i)?;
+            symbol.write_events(f, out, &inner)?;
+            writeln!(f, "}} def")?;
+        }
+        Ok(())
+    }
+
+    /// Simple text file commands can be parsed, and converted into PostScript.  There should be one command
+    /// per line.  Blank lines, and lines starting with a hash mark are ignored.  Lines with
+    /// errors are skipped, and returned with the file, line, and column of each problem (a file
+    /// that can't be opened is an error at line 0).  Files can also set variables, loop, define
+    /// macros, and include other files (set, for, define, and include).  Returns the number of
+    /// commands run.  This is parse_with in lenient mode.
+    pub fn parse(&mut self, filename: String) -> std::result::Result<usize, Vec<ParseError>> {
+        self.parse_with(&filename, ParseMode::Lenient)
+    }
+
+    /// Parses a text command file, as with parse.  In strict mode, parsing stops at the
+    /// first error (the commands before it have been added); in lenient
+    /// mode, lines with errors are skipped, and all of the errors are
+    /// returned.  On success, returns the number of commands read.
+    pub fn parse_with(
+        &mut self,
+        filename: &str,
+        mode: ParseMode,
+    ) -> std::result::Result<usize, Vec<ParseError>> {
+        let mut errors = Vec::new();
+        let count = self.parse_file(filename, mode, &mut errors);
+        if errors.is_empty() {
+            Ok(count)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses text commands from any buffered reader -- standard input,
+    /// for example, with std::io::stdin().lock().  The name is used in
+    /// error messages.  Errors are handled as in parse_with.
+    pub fn parse_reader<R: BufRead>(
+        &mut self,
+        reader: R,
+        name: &str,
+        mode: ParseMode,
+    ) -> std::result::Result<usize, Vec<ParseError>> {
+        let mut errors = Vec::new();
+        let count = self.parse_lines(reader, name, mode, &mut errors);
+        if errors.is_empty() {
+            Ok(count)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses text commands from a string, with one command per line.
+    /// Errors are handled as in parse_with.
+    /// ```
+    /// use pstools::{PSTool, ParseMode};
+    /// let mut pst = PSTool::new();
+    /// let count = pst.parse_str("color 1 0 0\nbox 0 0 10 10\n", ParseMode::Strict).unwrap();
+    /// assert_eq!(count, 2);
+    /// ```
+    pub fn parse_str(&mut self, commands: &str, mode: ParseMode) -> std::result::Result<usize, Vec<ParseError>> {
+        self.parse_reader(commands.as_bytes(), "<string>", mode)
+    }
+
+    fn parse_file(&mut self, filename: &str, mode: ParseMode, errors: &mut Vec<ParseError>) -> usize {
+        match File::open(filename) {
+            Ok(file) => self.parse_lines(BufReader::new(file), filename, mode, errors),
+            Err(err) => {
+                errors.push(ParseError {
+                    file: filename.to_string(),
+                    line: 0,
+                    column: 0,
+                    message: format!("can't open file: {}", err),
+                });
+                0
+            }
+        }
+    }
+
+    // Reads commands a line at a time, adding errors to the list.  Returns
+    // the number of commands run.
+    fn parse_lines<R: BufRead>(
+        &mut self,
+        reader: R,
+        name: &str,
+        mode: ParseMode,
+        errors: &mut Vec<ParseError>,
+    ) -> usize {
+        let mut script = parse::Script::new(mode);
+        let count = self.read_script(reader, name, &mut script);
+        if !script.stopped() {
+            for (symbol, line, _) in std::mem::take(&mut script.symbols) {
+                script.error(
+                    &line,
+                    parse::LineError {
+                        column: 1,
+                        message: format!("symbol {} has no endsymbol", symbol),
+                    },
+                );
+            }
+        }
+        errors.append(&mut script.errors);
+        count
+    }
+
+    // Runs the lines from a reader (a file, or an included file).  A for
+    // loop or macro definition has to end in the file it starts in.
+    fn read_script<R: BufRead>(&mut self, mut reader: R, name: &str, script: &mut parse::Script) -> usize {
+        let outer_block = script.block.take();
+        let mut count = 0;
+        let mut number = 0;
+        let mut buffer = Vec::new();
+        while !script.stopped() {
+            buffer.clear();
+            number += 1;
+            let mut line = parse::Line {
+                file: name.to_string(),
+                number,
+                text: String::new(),
+            };
+            match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(err) => {
+                    let message = format!("read error: {}", err);
+                    script.error(&line, parse::LineError { column: 0, message });
+                    break;
+                }
+            }
+            match std::str::from_utf8(&buffer) {
+                Ok(text) => {
+                    line.text = text.to_string();
+                    count += self.script_line(&line, script);
+                }
+                Err(e) => {
+                    let column = e.valid_up_to() + 1;
+                    let message = "not valid UTF-8".to_string();
+                    script.error(&line, parse::LineError { column, message });
+                }
+            }
+        }
+        if let Some(block) = std::mem::replace(&mut script.block, outer_block) {
+            if !script.stopped() {
+                let command = parse::first_word(&block.header.text).to_string();
+                let message = format!("{} has no end", command);
+                script.error(&block.header, parse::LineError { column: 1, message });
+            }
+        }
+        count
+    }
+
+    // Runs one line of a script -- or, inside a for loop or macro
+    // definition, adds it to the body.  Returns the number of commands run.
+    fn script_line(&mut self, line: &parse::Line, script: &mut parse::Script) -> usize {
+        if let Some(block) = &mut script.block {
+            match parse::first_word(&line.text) {
+                "for" | "define" => block.nesting += 1,
+                "end" => block.nesting -= 1,
+                _ => {}
+            }
+            if block.nesting > 0 {
+                block.body.push(line.clone());
+                return 0;
+            }
+            let block = script.block.take().unwrap();
+            return self.run_block(block, script);
+        }
+        let action = {
+            let parse::Script {
+                vars, macros, symbols, ..
+            } = &mut *script;
+            match parse::Args::new(&line.text, vars) {
+                Ok(Some(mut args)) => self.script_action(&mut args, line, macros, symbols),
+                Ok(None) => return 0,
+                Err(e) => Err(e),
+            }
+        };
+        let result = action.and_then(|action| match action {
+            parse::Action::Done => Ok(1),
+            parse::Action::Set(name, value) => {
+                script.vars.insert(name, value);
+                Ok(1)
+            }
+            parse::Action::Block(kind) => {
+                script.block = Some(parse::Block {
+                    kind,
+                    header: line.clone(),
+                    body: Vec::new(),
+                    nesting: 1,
+                });
+                Ok(1)
+            }
+            parse::Action::Include(file, name) => {
+                script.enter()?;
+                let count = self.read_script(BufReader::new(file), &name, script);
+                script.depth -= 1;
+                Ok(count + 1)
+            }
+            parse::Action::Call(name, values) => {
+                script.enter()?;
+                let count = self.call_macro(&name, values, script);
+                script.depth -= 1;
+                Ok(count + 1)
+            }
+        });
+        match result {
+            Ok(count) => count,
+            Err(e) => {
+                script.error(line, e);
+                0
+            }
+        }
+    }
+
+    // Works out what a line of a script does.  Drawing commands are run
+    // here; the rest are left to script_line, which can change the state
+    // of the script.
+    fn script_action(
+        &mut self,
+        args: &mut parse::Args,
+        line: &parse::Line,
+        macros: &HashMap<String, parse::Macro>,
+        symbols: &mut Vec<(String, parse::Line, PSTool)>,
+    ) -> std::result::Result<parse::Action, parse::LineError> {
+        let command = args.command().to_string();
+        match command.as_str() {
+            "set" => {
+                let name = args.word("name")?;
+                let value = args.expr("expression")?;
+                Ok(parse::Action::Set(name, value))
+            }
+            "for" => {
+                let var = args.word("name")?;
+                let (start, end) = (args.num("start")?, args.num("end")?);
+                let step = args.opt_num("step")?.unwrap_or(1.0);
+                args.end()?;
+                if step == 0.0 {
+                    return Err(args.last_error("the step can't be zero".to_string()));
+                }
+                Ok(parse::Action::Block(parse::BlockKind::For { var, start, end, step }))
+            }
+            "define" => {
+                let name = args.word("name")?;
+                if parse::is_command(&name) {
+                    return Err(args.last_error(format!("{} is a command, and can't be redefined", name)));
+                }
+                let mut params = Vec::new();
+                while args.has_more() {
+                    params.push(args.word("parameter")?);
+                }
+                Ok(parse::Action::Block(parse::BlockKind::Define { name, params }))
+            }
+            "end" => Err(parse::LineError {
+                column: 1,
+                message: "end: no for or define to end".to_string(),
+            }),
+            "include" => {
+                let file = args.string("filename")?;
+                args.end()?;
+                // Relative to the directory of the file doing the including
+                let path = std::path::Path::new(&line.file)
+                    .parent()
+                    .unwrap_or(std::path::Path::new(""))
+                    .join(file);
+                match File::open(&path) {
+                    Ok(f) => Ok(parse::Action::Include(f, path.to_string_lossy().to_string())),
+                    Err(err) => Err(args.last_error(format!("can't open {}: {}", path.display(), err))),
+                }
+            }
+            name if macros.contains_key(name) => {
+                let mut values = Vec::new();
+                for param in &macros[name].params {
+                    values.push(args.num(param)?);
+                }
+                args.end()?;
+                Ok(parse::Action::Call(command, values))
+            }
+            _ => self.run_line(args, line, symbols).map(|_| parse::Action::Done),
+        }
+    }
+
+    // Runs a for loop, or saves a macro, once the end of the block is
+    // found.  Returns the number of commands run.
+    fn run_block(&mut self, block: parse::Block, script: &mut parse::Script) -> usize {
+        match block.kind {
+            parse::BlockKind::For { var, start, end, step } => {
+                // The end is included, allowing for rounding in the steps
+                let last = end + step * 1e-4;
+                let saved = script.vars.get(&var).copied();
+                let mut count = 0;
+                for i in 0.. {
+                    let value = start + i as f32 * step;
+                    if (step > 0.0 && value > last) || (step < 0.0 && value < last) || script.stopped() {
+                        break;
+                    }
+                    if i == parse::MAX_ITERATIONS {
+                        let message = "for: too many iterations".to_string();
+                        script.error(&block.header, parse::LineError { column: 1, message });
+                        break;
+                    }
+                    script.vars.insert(var.clone(), value);
+                    for line in &block.body {
+                        if script.stopped() {
+                            break;
+                        }
+                        count += self.script_line(line, script);
+                    }
+                }
+                match saved {
+                    Some(value) => script.vars.insert(var, value),
+                    None => script.vars.remove(&var),
+                };
+                count
+            }
+            parse::BlockKind::Define { name, params } => {
+                script.macros.insert(name, parse::Macro { params, body: block.body });
+                0
+            }
+        }
+    }
+
+    // Runs the body of a macro, with the parameters set to the values.
+    // Variables with the names of the parameters are restored afterwards.
+    fn call_macro(&mut self, name: &str, values: Vec<f32>, script: &mut parse::Script) -> usize {
+        let (params, body) = match script.macros.get(name) {
+            Some(m) => (m.params.clone(), m.body.clone()),
+            None => return 0,
+        };
+        let saved: Vec<Option<f32>> = params.iter().map(|p| script.vars.get(p).copied()).collect();
+        for (param, value) in params.iter().zip(values) {
+            script.vars.insert(param.clone(), value);
+        }
+        let mut count = 0;
+        for line in &body {
+            if script.stopped() {
+                break;
+            }
+            count += self.script_line(line, script);
+        }
+        for (param, value) in params.into_iter().zip(saved) {
+            match value {
+                Some(value) => script.vars.insert(param, value),
+                None => script.vars.remove(&param),
+            };
+        }
+        count
+    }
+
+    // Runs one command, handling symbol definitions -- the commands
+    // between "symbol name" and "endsymbol" go into a separate PSTool,
+    // which is then defined as a symbol (and symbols can nest).
+    fn run_line(
+        &mut self,
+        args: &mut parse::Args,
+        line: &parse::Line,
+        open: &mut Vec<(String, parse::Line, PSTool)>,
+    ) -> std::result::Result<(), parse::LineError> {
+        match args.command() {
+            "symbol" => {
+                let name = args.word("name")?;
+                args.end()?;
+                open.push((name, line.clone(), PSTool::new()));
+            }
+            "endsymbol" => {
+                args.end()?;
+                let (name, _, symbol) = open.pop().ok_or_else(|| parse::LineError {
+                    column: 1,
+                    message: "endsymbol: no symbol to end".to_string(),
+                })?;
+                match open.last_mut() {
+                    Some((_, _, outer)) => outer.define_symbol(name, &symbol),
+                    None => self.define_symbol(name, &symbol),
+                }
+            }
+            _ => {
+                // Symbols defined outside can be placed inside a symbol;
+                // they are copied in when first used.
+                if let (Some(name), Some((_, _, inner))) = (args.peek(), open.last()) {
+                    if args.command() == "place" && !inner.symbols.iter().any(|(n, _)| n == name) {
+                        let outer = open[..open.len() - 1]
+                            .iter()
+                            .rev()
+                            .map(|(_, _, tool)| tool)
+                            .chain(std::iter::once(&*self))
+                            .find_map(|tool| tool.symbols.iter().rfind(|(n, _)| n == name));
+                        if let Some((n, symbol)) = outer.cloned() {
+                            open.last_mut().unwrap().2.define_symbol(n, &symbol);
+                        }
+                    }
+                }
+                match open.last_mut() {
+                    Some((_, _, inner)) => inner.run_command(args)?,
+                    None => self.run_command(args)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Runs one command from a text file.  All of the arguments are read
+    // and checked before anything is added, so a line with an error has
+    // no effect.
+    fn run_command(&mut self, args: &mut parse::Args) -> std::result::Result<(), parse::LineError> {
+        let command = args.command().to_string();
+        match command.as_str() {
+            "color" => {
+                let (r, g, b) = (args.num("r")?, args.num("g")?, args.num("b")?);
+                args.end()?;
+                self.set_color(r, g, b, 1.0);
+            }
+            "colora" => {
+                let (r, g, b, a) = (args.num("r")?, args.num("g")?, args.num("b")?, args.num("a")?);
+                args.end()?;
+                self.set_color(r, g, b, a);
+            }
+            "fill" => {
+                if !args.has_more() {
+                    self.set_fill(true);
+                } else if args.next_is_num() {
+                    let fill = args.num("fill")?;
+                    args.end()?;
+                    self.set_fill(fill != 0.0);
+                } else {
+                    let pattern = args.keyword::<FillPattern>("pattern")?;
+                    let spacing = args.num("spacing")?;
+                    if spacing.is_nan() || spacing <= 0.0 {
+                        return Err(args.last_error("the spacing must be more than zero".to_string()));
+                    }
+                    let angle = args.opt_num("angle")?.unwrap_or(0.0);
+                    args.end()?;
+                    self.set_fill_pattern(pattern, spacing, angle);
+                }
+            }
+            "box" => {
+                let (x1, y1, x2, y2) = (args.num("llx")?, args.num("lly")?, args.num("urx")?, args.num("ury")?);
+                args.end()?;
+                self.add_box(x1, y1, x2, y2);
+            }
+            "line" => {
+                let (x1, y1, x2, y2) = (args.num("x1")?, args.num("y1")?, args.num("x2")?, args.num("y2")?);
+                args.end()?;
+                self.add_line(x1, y1, x2, y2);
+            }
+            "circle" => {
+                let (x, y, r) = (args.num("x")?, args.num("y")?, args.num("radius")?);
+                args.end()?;
+                self.add_circle(x, y, r);
+            }
+            "arc" | "wedge" => {
+                let (x, y, r) = (args.num("x")?, args.num("y")?, args.num("radius")?);
+                let (start, end) = (args.num("start")?, args.num("end")?);
+                args.end()?;
+                if command == "arc" {
+                    self.add_arc(x, y, r, start, end);
+                } else {
+                    self.add_wedge(x, y, r, start, end);
+                }
+            }
+            "ellipse" => {
+                let (x, y, rx, ry) = (args.num("x")?, args.num("y")?, args.num("rx")?, args.num("ry")?);
+                let angle = args.num("angle")?;
+                args.end()?;
+                self.add_ellipse(x, y, rx, ry, angle);
+            }
+            "roundbox" => {
+                let (x1, y1, x2, y2) = (args.num("llx")?, args.num("lly")?, args.num("urx")?, args.num("ury")?);
+                let r = args.num("radius")?;
+                args.end()?;
+                self.add_rounded_box(x1, y1, x2, y2, r);
+            }
+            "curve" => {
+                let (x1, y1, x2, y2) = (args.num("x1")?, args.num("y1")?, args.num("x2")?, args.num("y2")?);
+                let (x3, y3) = (args.num("x3")?, args.num("y3")?);
+                args.end()?;
+                self.add_curve(x1, y1, x2, y2, x3, y3);
+            }
+            "cubic" => {
+                let (x0, y0, x1, y1) = (args.num("x0")?, args.num("y0")?, args.num("cx1")?, args.num("cy1")?);
+                let (x2, y2, x3, y3) = (args.num("cx2")?, args.num("cy2")?, args.num("x3")?, args.num("y3")?);
+                args.end()?;
+                self.add_cubic(x0, y0, x1, y1, x2, y2, x3, y3);
+            }
+            "quad" => {
+                let (x0, y0, x1, y1) = (args.num("x0")?, args.num("y0")?, args.num("cx")?, args.num("cy")?);
+                let (x2, y2) = (args.num("x2")?, args.num("y2")?);
+                args.end()?;
+                self.add_quadratic(x0, y0, x1, y1, x2, y2);
+            }
+            "dash" => {
+                // No lengths for solid lines
+                let offset = args.num("offset")?;
+                let pattern = args.nums("dash length")?;
+                check_dash(&pattern).map_err(|e| args.last_error(e))?;
+                self.set_dash(pattern, offset);
+            }
+            "linecap" => {
+                let cap = args.keyword::<LineCap>("cap")?;
+                args.end()?;
+                self.set_line_cap(cap);
+            }
+            "linejoin" => {
+                let join = args.keyword::<LineJoin>("join")?;
+                args.end()?;
+                self.set_line_join(join);
+            }
+            "miterlimit" => {
+                let limit = args.num("limit")?;
+                args.end()?;
+                self.set_miter_limit(limit);
+            }
+            "font" => {
+                let scale = args.num("pointsize")?;
+                let font = args.string("fontname")?;
+                args.end()?;
+                self.set_font(scale, font);
+            }
+            "text" => {
+                let (x, y) = (args.num("x")?, args.num("y")?);
+                let t = args.string("string")?;
+                args.end()?;
+                self.add_text(x, y, t);
+            }
+            "comment" | "pscomment" => {
+                let t = args.string("string")?;
+                args.end()?;
+                self.add_comment(t);
+            }
+            "nofill" => {
+                args.end()?;
+                self.set_fill(false);
+            }
+            "path" => {
+                let paint = args.word("paint")?;
+                let (fill, stroke) = match paint.as_str() {
+                    "fill" => (true, false),
+                    "stroke" => (false, true),
+                    "both" => (true, true),
+                    "none" => (false, false),
+                    _ => return Err(args.bad_keyword(&paint, "fill, stroke, both, or none")),
+                };
+                let path = args.path()?;
+                self.add_path(&path, fill, stroke);
+            }
+            "clippath" => {
+                let path = args.path()?;
+                self.push_clip_path(&path);
+            }
+            "cliprect" => {
+                let (x1, y1, x2, y2) = (args.num("llx")?, args.num("lly")?, args.num("urx")?, args.num("ury")?);
+                args.end()?;
+                self.push_clip_rect(x1, y1, x2, y2);
+            }
+            "popclip" => {
+                args.end()?;
+                self.pop_clip();
+            }
+            "arrowhead" => {
+                let head = args.keyword::<ArrowHead>("head")?;
+                let (length, width) = if args.has_more() {
+                    (args.num("length")?, args.num("width")?)
+                } else {
+                    (self.arrow_length, self.arrow_width)
+                };
+                args.end()?;
+                self.set_arrow_head(head, length, width);
+            }
+            "arrow" | "doublearrow" => {
+                let (x1, y1, x2, y2) = (args.num("x1")?, args.num("y1")?, args.num("x2")?, args.num("y2")?);
+                args.end()?;
+                if command == "arrow" {
+                    self.add_arrow(x1, y1, x2, y2);
+                } else {
+                    self.add_double_arrow(x1, y1, x2, y2);
+                }
+            }
+            "curvedarrow" => {
+                let (x1, y1, x2, y2) = (args.num("x1")?, args.num("y1")?, args.num("x2")?, args.num("y2")?);
+                let (x3, y3) = (args.num("x3")?, args.num("y3")?);
+                args.end()?;
+                self.add_curved_arrow(x1, y1, x2, y2, x3, y3);
+            }
+            "arrowtip" => {
+                let (x, y) = (args.num("x")?, args.num("y")?);
+                let (lx, ly, rx, ry) = (args.num("lx")?, args.num("ly")?, args.num("rx")?, args.num("ry")?);
+                let head = args.keyword::<ArrowHead>("head")?;
+                args.end()?;
+                self.push_arrow_tip(Head {
+                    tip_x: x,
+                    tip_y: y,
+                    lx,
+                    ly,
+                    rx,
+                    ry,
+                    filled: head == ArrowHead::Filled,
+                });
+            }
+            "dimension" => {
+                let (x1, y1, x2, y2) = (args.num("x1")?, args.num("y1")?, args.num("x2")?, args.num("y2")?);
+                let offset = args.num("offset")?;
+                let label = if args.has_more() {
+                    args.string("label")?
+                } else {
+                    String::new()
+                };
+                args.end()?;
+                self.add_dimension(x1, y1, x2, y2, offset, label);
+            }
+            "chart" => {
+                let (min, max) = (args.num("min")?, args.num("max")?);
+                let (x1, y1, x2, y2) = (args.num("llx")?, args.num("lly")?, args.num("urx")?, args.num("ury")?);
+                let data = args.nums("value")?;
+                self.chart(data, min, max, x1, y1, x2, y2);
+            }
+            "linewidth" => {
+                let width = args.num("width")?;
+                args.end()?;
+                self.set_line_width(width);
+            }
+            "textrotated" => {
+                let (x, y, angle) = (args.num("x")?, args.num("y")?, args.num("angle")?);
+                let t = args.string("string")?;
+                args.end()?;
+                self.add_text_rotated(x, y, angle, t);
+            }
+            "textcentered" => {
+                let (x, y) = (args.num("x")?, args.num("y")?);
+                let t = args.string("string")?;
+                args.end()?;
+                self.add_text_centered(x, y, t);
+            }
+            "textcenteredrotated" => {
+                let (x, y, angle) = (args.num("x")?, args.num("y")?, args.num("angle")?);
+                let t = args.string("string")?;
+                args.end()?;
+                self.push_text(PSTag::TC, x, y, angle, t);
+            }
+            "settextln" => {
+                let (x, y) = (args.num("x")?, args.num("y")?);
+                args.end()?;
+                self.set_text_ln(x, y);
+            }
+            "textln" => {
+                let t = args.string("string")?;
+                args.end()?;
+                self.add_text_ln(t);
+            }
+            "note" => {
+                let t = args.string("string")?;
+                args.end()?;
+                self.add_note(t);
+            }
+            "postscript" => {
+                let t = args.string("string")?;
+                args.end()?;
+                self.add_postscript(t);
+            }
+            "gsave" => {
+                args.end()?;
+                self.add_gsave();
+            }
+            "grestore" => {
+                args.end()?;
+                self.add_grestore();
+            }
+            "layer" => {
+                let name = args.string("name")?;
+                args.end()?;
+                self.begin_layer(name);
+            }
+            "endlayer" => {
+                args.end()?;
+                self.end_layer();
+            }
+            "object" => {
+                let id = args.item("id")?;
+                let mut properties = Vec::new();
+                while args.has_more() {
+                    let key = args.item("key")?;
+                    properties.push((key, args.item("value")?));
                 }
-                if e.tag == PSTag::X {
-                    offset_x = offset_x + scale * e.event.translate.dx;
-                    offset_y = offset_y + scale * e.event.translate.dy;
+                let properties: Vec<(&str, &str)> = properties.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+                self.begin_object(id, &properties);
+            }
+            "endobject" => {
+                args.end()?;
+                self.end_object();
+            }
+            "scale" => {
+                let factor = args.num("factor")?;
+                args.end()?;
+                self.add_scale(factor);
+            }
+            "translate" => {
+                let (dx, dy) = (args.num("dx")?, args.num("dy")?);
+                args.end()?;
+                self.add_translate(dx, dy);
+            }
+            "place" => {
+                let name = args.word("name")?;
+                if !self.symbols.iter().any(|(n, _)| *n == name) {
+                    return Err(args.bad_keyword(&name, "a symbol defined earlier"));
                 }
-                if e.tag == PSTag::C {}
-                if e.tag == PSTag::B || e.tag == PSTag::L || e.tag == PSTag::R {
-                    // In the events, we have locations as lower left and
-                    // delta x, delta y
-                    let x = e.event.line.llx * scale + offset_x;
-                    let y = e.event.line.lly * scale + offset_y;
-                    bbox.addpoint(x, y);
-
-                    let x = e.event.line.urx * scale + offset_x;
-                    let y = e.event.line.ury * scale + offset_y;
-                    bbox.addpoint( x,  y);
+                let (x, y) = (args.num("x")?, args.num("y")?);
+                let orient = if args.has_more() {
+                    args.keyword::<Orientation>("orientation")?
+                } else {
+                    Orientation::N
+                };
+                let scale = args.opt_num("scale")?.unwrap_or(1.0);
+                args.end()?;
+                self.place_symbol(&name, x, y, orient, scale);
+            }
+            "border" => {
+                let border = args.num("width")?;
+                args.end()?;
+                self.set_border(border);
+            }
+            "bounds" => {
+                let (x1, y1, x2, y2) = (args.num("llx")?, args.num("lly")?, args.num("urx")?, args.num("ury")?);
+                args.end()?;
+                self.set_bounds(x1, y1, x2, y2);
+            }
+            "page" => {
+                if args.peek() == Some("none") {
+                    args.word("size")?;
+                    args.end()?;
+                    self.clear_page();
+                } else {
+                    let mut page = PageSetup::new(args.keyword::<PageSize>("size")?);
+                    if args.has_more() && !args.next_is_num() {
+                        page.orientation = args.keyword::<PageOrientation>("orientation")?;
+                    }
+                    if let Some(margin) = args.opt_num("margin")? {
+                        page.margin = margin;
+                        if args.has_more() {
+                            match args.word("placement")?.as_str() {
+                                "center" => page.center = true,
+                                "topleft" => page.center = false,
+                                other => return Err(args.bad_keyword(other, "center or topleft")),
+                            }
+                        }
+                    }
+                    args.end()?;
+                    self.set_page(page);
                 }
             }
+            "maxsize" => {
+                let max_size = args.num("points")?;
+                args.end()?;
+                self.set_max_size(max_size);
+            }
+            "colormode" => {
+                let mode = args.keyword::<ColorMode>("mode")?;
+                args.end()?;
+                self.set_color_mode(mode);
+            }
+            "deterministic" => {
+                let on = args.opt_num("deterministic")?.unwrap_or(1.0);
+                args.end()?;
+                self.set_deterministic(on != 0.0);
+            }
+            "title" => {
+                let t = args.string("string")?;
+                args.end()?;
+                self.set_title(t);
+            }
+            "creator" => {
+                let t = args.string("string")?;
+                args.end()?;
+                self.set_creator(t);
+            }
+            "user" => {
+                let t = args.string("string")?;
+                args.end()?;
+                self.set_for(t);
+            }
+            _ => return Err(args.unknown_command()),
         }
-
-        if !bbox.valid {
-            return (0.0, 0.0, 0.0, 0.0);
-        }
-
-        // Expand the bbox by the requested border size
-        let llx = bbox.llx - self.border;
-        let lly = bbox.lly - self.border;
-        let urx = bbox.urx + self.border;
-        let ury = bbox.ury + self.border;
-
-        (llx, lly, urx, ury)
-    }
-    /// Returns the length of the event vector -- the number of objects
-    /// that have been added.  If no events have been added to a PSTool,
-    /// PostScript output will not be generated.
-    pub fn len(&self) -> usize {
-        self.events.len()
+        Ok(())
     }
 
-    /// generates the PostScript output from the stored events.
-    /// Returns the number of events processed (zero would indicate
-    /// some sort of error).
-    pub fn generate(&self, filepath: String) -> Result<usize> {
-        // let mut f;
-
-        // if the file path is empty, just print to standard out
-        // f = Box::new(File::create(filepath).unwrap()) as Box<dyn Write>;
-        let mut f = match File::create(filepath) {
-            Ok(file) => file,
-            Err(err) => {
-                println!("File open error {}", err);
-                return Ok(0);
-            }
-        };
-
-        let scale;
-        let (llx, lly, urx, ury) = self.bbox();
-        let dx = urx - llx;
-        let dy = ury - lly;
-        let max_dim = dx.max(dy);
-        if max_dim > 10000.0 {
-            scale = 10000.0 / max_dim;
-        } else {
-            scale = 1.0;
+    /// Writes the PSTool as text commands, in the format read by parse --
+    /// so that a scene built in Rust can be saved, edited by hand, and
+    /// drawn again later.  The settings (page, bounds, title, and so on)
+    /// and notes come first, then the events in order, with each symbol
+    /// written as a symbol block before its first placement.  Parsing the
+    /// output gives an equivalent PSTool.  Builder state that is not part
+    /// of the figure (the text line position and the arrow head style)
+    /// is not written; arrows and dimensions come back as their parts.
+    /// ```
+    /// use pstools::{PSTool, ParseMode};
+    /// let mut pst = PSTool::new();
+    /// pst.set_color(1.0, 0.0, 0.0, 1.0);
+    /// pst.add_text(10.0, 10.0, "Hello".to_string());
+    /// let mut text = Vec::new();
+    /// pst.write_commands(&mut text).unwrap();
+    /// assert_eq!(String::from_utf8(text).unwrap(), "color 1 0 0\ntext 10 10 \"Hello\"\n");
+    /// ```
+    pub fn write_commands(&self, w: &mut dyn Write) -> Result<()> {
+        if let Some(title) = &self.title {
+            writeln!(w, "title {}", parse::quote(title))?;
+        }
+        if let Some(creator) = &self.creator {
+            writeln!(w, "creator {}", parse::quote(creator))?;
+        }
+        if let Some(user) = &self.user {
+            writeln!(w, "user {}", parse::quote(user))?;
+        }
+        if self.deterministic {
+            writeln!(w, "deterministic")?;
+        }
+        if self.color_mode != ColorMode::Rgb {
+            writeln!(w, "colormode {}", self.color_mode)?;
+        }
+        if let Some(page) = self.page {
+            let placement = if page.center { "center" } else { "topleft" };
+            writeln!(w, "page {} {} {} {}", page.size, page.orientation, page.margin, placement)?;
+        }
+        if self.max_size != 10000.0 {
+            writeln!(w, "maxsize {}", self.max_size)?;
+        }
+        if self.border != 0.0 {
+            writeln!(w, "border {}", self.border)?;
         }
-        
-        // let mut f = unsafe { std::os::unix::io::from_raw_fd(3); }
-        let (origin_x, origin_y, urx, ury) = self.bbox();
-        // println!("Bounding box {} {}  {} {}", origin_x, origin_y, urx, ury);
-
-        writeln!(&mut f, "%!PS-Adobe-3.0 EPSF-3.0")?;
-        writeln!(&mut f, "%%DocumentData: Clean7Bit")?;
         if self.bbox.valid {
-            writeln!(&mut f, "%%Origin: {} {}", self.bbox.llx, self.bbox.lly)?;
-            writeln!(
-                &mut f,
-                "%%BoundingBox: {} {} {} {}",
-//                self.bbox.llx, self.bbox.lly, self.bbox.urx, self.bbox.ury
-                llx * scale, lly * scale, urx * scale, ury * scale
-            )?;
-        } else {
-            writeln!(&mut f, "%%Origin: {} {}", origin_x, origin_y)?;
-            writeln!(
-                &mut f,
-                "%%BoundingBox: {} {} {} {}",
-                origin_x * scale, origin_y * scale, urx * scale, ury * scale
-            )?;
+            let b = &self.bbox;
+            writeln!(w, "bounds {} {} {} {}", b.llx, b.lly, b.urx, b.ury)?;
         }
-        writeln!(&mut f, "%%LanguageLevel: 2")?;
-        writeln!(&mut f, "%%Pages: 1")?;
-        writeln!(&mut f, "%%Page: 1 1")?;
-        writeln!(
-            &mut f,
-            "%% gs -o filename.pdf -sDEVICE=pdfwrite -dEPSCrop filename.ps"
-        )?;
-
-        writeln!(&mut f, "%% Binghamton PSTools PostScript Generator")?;
-        writeln!(
-            &mut f,
-            "%% https://github.com/profmadden/pstools_r for more information."
-        )?;
-
-        // Some definitions for boxes and lines, to make the files more compact
-        writeln!(&mut f, "/bs {{/h 2 1 roll def /w 2 1 roll def /oy 2 1 roll def /ox 2 1 roll def newpath ox oy moveto")?;
-        writeln!(&mut f, "ox w add oy lineto")?;
-        writeln!(&mut f, "ox w add oy h add lineto")?;
-        writeln!(&mut f, "ox oy h add lineto")?;
-        writeln!(&mut f, "closepath stroke}} def")?;
-
-        // Filled box
-        writeln!(&mut f, "/bf {{/h 2 1 roll def /w 2 1 roll def /oy 2 1 roll def /ox 2 1 roll def newpath ox oy moveto")?;
-        writeln!(&mut f, "ox w add oy lineto")?;
-        writeln!(&mut f, "ox w add oy h add lineto")?;
-        writeln!(&mut f, "ox oy h add lineto")?;
-        writeln!(&mut f, "closepath fill}} def")?;        
-
-        // Line
-        writeln!(&mut f, "/ln {{/h 2 1 roll def /w 2 1 roll def /oy 2 1 roll def /ox 2 1 roll def newpath ox oy moveto")?;
-        writeln!(&mut f, "w h lineto")?;
-        writeln!(&mut f, "stroke}} def")?;                
+        self.write_command_events(w)
+    }
 
-        writeln!(&mut f, "%% ").unwrap();
-        for s in &self.notes {
-            writeln!(&mut f, "%% {}", s,)?;
+    // The notes, symbols, and events, for write_commands.  Symbols are
+    // written in order, just before the first placement that needs them,
+    // so that a symbol redefined under the same name is found by the
+    // placements made between the definitions.
+    fn write_command_events(&self, w: &mut dyn Write) -> Result<()> {
+        for note in &self.notes {
+            writeln!(w, "note {}", parse::quote(note))?;
         }
-        writeln!(&mut f, "/Courier findfont {} scalefont setfont", 12.0 * scale)?;
-        let mut fillstate = false;
+        let mut defined = 0;
         for e in &self.events {
-            // println!("Got event ");
             unsafe {
-                if e.tag == PSTag::C {
-                    let c = e.event.color;
-                    writeln!(&mut f, "{} {} {} setrgbcolor", c.r, c.g, c.b).unwrap();
+                if e.tag == PSTag::I {
+                    let i = e.event.instance;
+                    while defined <= i.symbol {
+                        let (name, symbol) = &self.symbols[defined];
+                        writeln!(w, "symbol {}", parse::word(name))?;
+                        symbol.write_commands(w)?;
+                        writeln!(w, "endsymbol")?;
+                        defined += 1;
+                    }
+                    // place puts the lower left corner at (x, y)
+                    let (llx, lly) = self.placed_corner(i.symbol, i.orient, i.scale);
+                    writeln!(
+                        w,
+                        "place {} {} {} {} {}",
+                        parse::word(&self.symbols[i.symbol].0),
+                        i.x + llx,
+                        i.y + lly,
+                        i.orient,
+                        i.scale
+                    )?;
                 }
                 if e.tag == PSTag::B {
-                    if fillstate {
-                        writeln!(
-                            &mut f,
-                            "{} {} {} {} bf", scale * e.event.line.llx, scale * e.event.line.lly,
-                                                scale * (e.event.line.urx - e.event.line.llx), scale * (e.event.line.ury - e.event.line.lly))?;
-                    } else {
-                        writeln!(
-                            &mut f,
-                            "{} {} {} {} bs", scale * e.event.line.llx, scale * e.event.line.lly,
-                            scale * (e.event.line.urx - e.event.line.llx), scale * (e.event.line.ury - e.event.line.lly))?;
-                    }
+                    let b = e.event.line;
+                    writeln!(w, "box {} {} {} {}", b.llx, b.lly, b.urx, b.ury)?;
                 }
                 if e.tag == PSTag::L {
-                    writeln!(&mut f, "{} {} {} {} ln", scale * e.event.line.llx, scale * e.event.line.lly,
-                        scale * (e.event.line.urx - e.event.line.llx), scale * (e.event.line.ury - e.event.line.lly))?;
-                }
-                if e.tag == PSTag::W {
-                    writeln!(&mut f, "{} setlinewidth", e.event.line_width).unwrap();
+                    let b = e.event.line;
+                    writeln!(w, "line {} {} {} {}", b.llx, b.lly, b.urx, b.ury)?;
                 }
                 if e.tag == PSTag::R {
-                    if fillstate {
+                    let b = e.event.line;
+                    writeln!(w, "circle {} {} {}", b.llx, b.lly, b.urx)?;
+                }
+                if e.tag == PSTag::A || e.tag == PSTag::WG {
+                    let a = e.event.arc;
+                    let command = if e.tag == PSTag::A { "arc" } else { "wedge" };
+                    writeln!(w, "{} {} {} {} {} {}", command, a.x, a.y, a.r, a.start, a.end)?;
+                }
+                if e.tag == PSTag::E {
+                    let el = e.event.ellipse;
+                    writeln!(w, "ellipse {} {} {} {} {}", el.x, el.y, el.rx, el.ry, el.angle)?;
+                }
+                if e.tag == PSTag::RB {
+                    let r = e.event.rbox;
+                    writeln!(w, "roundbox {} {} {} {} {}", r.llx, r.lly, r.urx, r.ury, r.radius)?;
+                }
+                if e.tag == PSTag::V {
+                    let c = e.event.curve;
+                    if c.fillable {
                         writeln!(
-                            &mut f,
-                            "newpath {} {} {} 0 360 arc fill",
-                            scale * e.event.line.llx, scale * e.event.line.lly, scale * e.event.line.urx
+                            w,
+                            "cubic {} {} {} {} {} {} {} {}",
+                            c.x0, c.y0, c.x1, c.y1, c.x2, c.y2, c.x3, c.y3
                         )?;
                     } else {
-                        writeln!(
-                            &mut f,
-                            "newpath {} {} {} 0 360 arc stroke",
-                            scale * e.event.line.llx, scale * e.event.line.lly, scale * e.event.line.urx
-                        )?;
+                        writeln!(w, "curve {} {} {} {} {} {}", c.x1, c.y1, c.x2, c.y2, c.x3, c.y3)?;
                     }
                 }
-                if e.tag == PSTag::V {
+                if e.tag == PSTag::H {
+                    let h = e.event.head;
+                    let head = if h.filled { ArrowHead::Filled } else { ArrowHead::Open };
                     writeln!(
-                        &mut f,
-                        "newpath {} {} moveto {} {} {} {} {} {} curveto stroke",
-                        scale * e.event.curve.x1,
-                        scale * e.event.curve.y1,
-                        scale * e.event.curve.x1,
-                        scale * e.event.curve.y1,
-                        scale * e.event.curve.x2,
-                        scale * e.event.curve.y2,
-                        scale * e.event.curve.x3,
-                        scale * e.event.curve.y3
+                        w,
+                        "arrowtip {} {} {} {} {} {} {}",
+                        h.tip_x, h.tip_y, h.lx, h.ly, h.rx, h.ry, head
                     )?;
                 }
-                if e.tag == PSTag::F {
-                    fillstate = e.event.fill.fill;
+                if e.tag == PSTag::PA || e.tag == PSTag::CS {
+                    let p = e.event.path;
+                    let path = &self.paths[p.path];
+                    let mut line = if e.tag == PSTag::CS {
+                        "clippath".to_string()
+                    } else {
+                        let paint = match (p.fill, p.stroke) {
+                            (true, true) => "both",
+                            (true, false) => "fill",
+                            (false, true) => "stroke",
+                            (false, false) => "none",
+                        };
+                        format!("path {}", paint)
+                    };
+                    if path.fill_rule != FillRule::NonZero {
+                        line += &format!(" {}", path.fill_rule);
+                    }
+                    for seg in path.segments() {
+                        line += &match *seg {
+                            path::PathSegment::MoveTo(x, y) => format!(" M {} {}", x, y),
+                            path::PathSegment::LineTo(x, y) => format!(" L {} {}", x, y),
+                            path::PathSegment::CurveTo(x1, y1, x2, y2, x3, y3) => {
+                                format!(" C {} {} {} {} {} {}", x1, y1, x2, y2, x3, y3)
+                            }
+                            path::PathSegment::Arc(x, y, r, start, end) => {
+                                format!(" A {} {} {} {} {}", x, y, r, start, end)
+                            }
+                            path::PathSegment::ClosePath => " Z".to_string(),
+                        };
+                    }
+                    writeln!(w, "{}", line)?;
                 }
-                if e.tag == PSTag::T {
-                    if e.event.text.angle != 0.0 {
-                        writeln!(
-                            &mut f,
-                            "gsave {} {} translate {} rotate 0 0 moveto",
-                            scale * e.event.text.x, scale * e.event.text.y, e.event.text.angle
-                        )
-                        .unwrap();
-                        writeln!(&mut f, "({}) show grestore", self.te[e.event.text.text]).unwrap();
+                if e.tag == PSTag::CR {
+                    writeln!(w, "popclip")?;
+                }
+                if e.tag == PSTag::C {
+                    let c = e.event.color;
+                    if c.a == 1.0 {
+                        writeln!(w, "color {} {} {}", c.r, c.g, c.b)?;
                     } else {
-                        writeln!(&mut f, "{} {} moveto", scale * e.event.text.x, scale * e.event.text.y).unwrap();
-                        writeln!(&mut f, "({}) show", self.te[e.event.text.text]).unwrap();
+                        writeln!(w, "colora {} {} {} {}", c.r, c.g, c.b, c.a)?;
                     }
                 }
-                if e.tag == PSTag::N {
-                    writeln!(&mut f, "%% {}", self.te[e.event.text.text]).unwrap();
+                if e.tag == PSTag::F {
+                    let fill = e.event.fill;
+                    if fill.fill && fill.pattern != FillPattern::Solid {
+                        writeln!(w, "fill {} {} {}", fill.pattern, fill.spacing, fill.angle)?;
+                    } else if fill.fill {
+                        writeln!(w, "fill")?;
+                    } else {
+                        writeln!(w, "nofill")?;
+                    }
+                }
+                if e.tag == PSTag::W {
+                    writeln!(w, "linewidth {}", e.event.line_width)?;
+                }
+                if e.tag == PSTag::D {
+                    let d = e.event.dash;
+                    let mut line = format!("dash {}", d.offset);
+                    for length in &self.dashes[d.pattern] {
+                        line += &format!(" {}", length);
+                    }
+                    writeln!(w, "{}", line)?;
+                }
+                if e.tag == PSTag::LC {
+                    writeln!(w, "linecap {}", e.event.line_cap)?;
+                }
+                if e.tag == PSTag::LJ {
+                    writeln!(w, "linejoin {}", e.event.line_join)?;
+                }
+                if e.tag == PSTag::ML {
+                    writeln!(w, "miterlimit {}", e.event.miter_limit)?;
                 }
                 if e.tag == PSTag::FN {
-                    writeln!(
-                        &mut f,
-                        "/{} findfont {} scalefont setfont",
-                        self.te[e.event.font.font_name], e.event.font.scale * scale
-                    )
-                    .unwrap();
+                    let font = e.event.font;
+                    writeln!(w, "font {} {}", font.scale, parse::quote(&self.te[font.font_name]))?;
+                }
+                if e.tag == PSTag::T || e.tag == PSTag::TC {
+                    let t = e.event.text;
+                    let text = parse::quote(&self.te[t.text]);
+                    match (e.tag == PSTag::TC, t.angle == 0.0) {
+                        (false, true) => writeln!(w, "text {} {} {}", t.x, t.y, text)?,
+                        (false, false) => writeln!(w, "textrotated {} {} {} {}", t.x, t.y, t.angle, text)?,
+                        (true, true) => writeln!(w, "textcentered {} {} {}", t.x, t.y, text)?,
+                        (true, false) => writeln!(w, "textcenteredrotated {} {} {} {}", t.x, t.y, t.angle, text)?,
+                    }
+                }
+                if e.tag == PSTag::N {
+                    writeln!(w, "comment {}", parse::quote(&self.te[e.event.text.text]))?;
                 }
                 if e.tag == PSTag::P {
-                    writeln!(&mut f, "{}", self.te[e.event.text.text])?;
+                    writeln!(w, "postscript {}", parse::quote(&self.te[e.event.text.text]))?;
                 }
                 if e.tag == PSTag::GS {
-                    writeln!(&mut f, "gsave")?;
+                    writeln!(w, "gsave")?;
                 }
                 if e.tag == PSTag::GR {
-                    writeln!(&mut f, "grestore")?;
+                    writeln!(w, "grestore")?;
                 }
-                if e.tag == PSTag::S {
-                    writeln!(
-                        &mut f,
-                        "{} {} scale",
-                        e.event.scale.scale, e.event.scale.scale
-                    )?;
+                if e.tag == PSTag::LB {
+                    writeln!(w, "layer {}", parse::quote(&self.te[e.event.text.text]))?;
                 }
-                if e.tag == PSTag::X {
-                    writeln!(
-                        &mut f,
-                        "{} {} translate",
-                        scale * e.event.translate.dx, scale * e.event.translate.dy
-                    )?;
+                if e.tag == PSTag::LE {
+                    writeln!(w, "endlayer")?;
                 }
-            }
-        }
-        writeln!(&mut f, "%%EOF\n").unwrap();
-
-        Ok(self.events.len())
-    }
-
-    /// Simple text file commands can be parsed, and converted into PostScript.  There should be one command
-    /// per line.  Blank lines, and lines starting with a hash mark are ignored.  I need to figure
-    /// out the proper Rust way to read from either a file or standard input.
-    pub fn parse(&mut self, filename: String) -> Result<usize> {
-        // let mut reader;
-
-        // let f = File::open(filename).unwrap();
-        // reader = BufReader::with_capacity(32000, f);
-        let mut lineio = match LineIO::new(&filename) {
-            Ok(reader) => reader,
-            Err(error) => {panic!("File opening error: {error:?}");}
-        };
-
-        loop {
-            let line = lineio.getline();
-            match line {
-                Ok(s) => {
-                    // println!("Input line {s}");
-                    if let Ok((x1, y1, x2, y2)) =
-                        scan_fmt!(&s, "box {} {} {} {}", f32, f32, f32, f32)
-                    {
-                        self.add_box(x1, y1, x2, y2);
-                        continue;
-                    }
-                    if let Ok((x1, y1, x2, y2)) =
-                        scan_fmt!(&s, "line {} {} {} {}", f32, f32, f32, f32)
-                    {
-                        self.add_line(x1, y1, x2, y2);
-                        continue;
-                    }
-                    if let Ok((x, y, r)) = scan_fmt!(&s, "circle {} {} {}", f32, f32, f32) {
-                        self.add_circle(x, y, r);
-                        continue;
-                    }
-                    if let Ok((r, g, b)) = scan_fmt!(&s, "color {} {} {}", f32, f32, f32) {
-                        self.set_color(r, g, b, 1.0);
-                        continue;
-                    }
-                    if let Ok(fill) = scan_fmt!(&s, "fill {}", usize) {
-                        self.set_fill(fill != 0);
-                        continue;
-                    }
-                    if let Ok((x1, y1, x2, y2, x3, y3)) =
-                        scan_fmt!(&s, "curve {} {} {} {} {} {}", f32, f32, f32, f32, f32, f32)
-                    {
-                        self.add_curve(x1, y1, x2, y2, x3, y3);
-                        continue;
-                    }
-                    if let Ok((scale, font)) = scan_fmt!(&s, "font {} {}", f32, String) {
-                        self.set_font(scale, font);
-                        continue;
-                    }
-                    if let Ok((x, y, str)) = scan_fmt!(&s, "text {} {} {}", f32, f32, String) {
-                        self.add_text(x, y, str);
-                        continue;
+                if e.tag == PSTag::OB {
+                    let (id, properties) = &self.objects[e.event.object];
+                    let mut line = format!("object {}", parse::word(id));
+                    for (key, value) in properties {
+                        line += &format!(" {} {}", parse::word(key), parse::word(value));
                     }
-                    if let Ok(str) = scan_fmt!(&s, "comment {}", String) {
-                        self.add_comment(str);
-                        continue;
-                    }
-                    // If we have hit this line, it's something unrecognized.
-                    println!("Unrecognized line: {s}");
+                    writeln!(w, "{}", line)?;
+                }
+                if e.tag == PSTag::OE {
+                    writeln!(w, "endobject")?;
+                }
+                if e.tag == PSTag::S {
+                    writeln!(w, "scale {}", e.event.scale.scale)?;
                 }
-                _ => {
-                    // Expect that there will be no additional input, and we stop reading there
-                    return Ok(0);
+                if e.tag == PSTag::X {
+                    let t = e.event.translate;
+                    writeln!(w, "translate {} {}", t.dx, t.dy)?;
                 }
             }
         }
+        // Symbols that were never placed
+        for (name, symbol) in &self.symbols[defined..] {
+            writeln!(w, "symbol {}", parse::word(name))?;
+            symbol.write_commands(w)?;
+            writeln!(w, "endsymbol")?;
+        }
+        Ok(())
     }
 
     /// This routine adds a number of events to the PSTool object, as a means
@@ -1079,7 +4186,116 @@ impl PSTool {
 }
 
 
+/// Control points of the cubic Bezier curve that is identical to a
+/// quadratic curve from (x0, y0) to (x2, y2) with control point (x1, y1).
+/// Returns the two control points (c1x, c1y, c2x, c2y).
+pub fn quadratic_controls(x0: f32, y0: f32, x1: f32, y1: f32, x2: f32, y2: f32) -> (f32, f32, f32, f32) {
+    (
+        x0 + 2.0 / 3.0 * (x1 - x0),
+        y0 + 2.0 / 3.0 * (y1 - y0),
+        x2 + 2.0 / 3.0 * (x1 - x2),
+        y2 + 2.0 / 3.0 * (y1 - y2),
+    )
+}
+
 /// Returns information string for the installed version.
 pub fn pstools_version() -> String {
     "PSTools version 0.1.0".to_string()
 }
+
+// Text for a PostScript string, between parentheses.  Parentheses and
+// backslashes are escaped; characters beyond ASCII are written as octal
+// escapes for the ISO Latin-1 encoding, and anything beyond Latin-1 can't
+// be shown with the standard fonts, so it becomes a question mark.
+fn ps_string(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            ' '..='~' => out.push(c),
+            '\u{a0}'..='\u{ff}' => out.push_str(&format!("\\{:03o}", c as u32)),
+            _ => out.push('?'),
+        }
+    }
+    out
+}
+
+// Checks a dash pattern: setdash fails for negative lengths, or lengths
+// that are all zero.
+fn check_dash(pattern: &[f32]) -> std::result::Result<(), String> {
+    if pattern.iter().any(|d| d.is_nan() || *d < 0.0) {
+        return Err("dash lengths can't be negative".to_string());
+    }
+    if !pattern.is_empty() && pattern.iter().all(|d| *d == 0.0) {
+        return Err("dash lengths can't all be zero".to_string());
+    }
+    Ok(())
+}
+
+// Events that save the graphic state, and the ones that restore it
+fn opens(tag: PSTag) -> bool {
+    matches!(tag, PSTag::GS | PSTag::CS | PSTag::LB | PSTag::OB)
+}
+
+fn closes(tag: PSTag) -> bool {
+    matches!(tag, PSTag::GR | PSTag::CR | PSTag::LE | PSTag::OE)
+}
+
+// DSC comments are a single line of text, and 7-bit (the header says
+// Clean7Bit), so control characters become spaces, and characters beyond
+// ASCII are written as \u{XXXX}.
+fn dsc_text(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            ' '..='~' => out.push(c),
+            c if c.is_control() => out.push(' '),
+            c => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+        }
+    }
+    out
+}
+
+// Rounds a coordinate to 0.001 points, for deterministic output.  Negative
+// zero is written as 0.
+fn round_output(v: f32) -> f32 {
+    let r = (v * 1000.0).round() / 1000.0;
+    if r == 0.0 {
+        0.0
+    } else {
+        r
+    }
+}
+
+// A time (UTC, in seconds since 1970) for %%CreationDate, as
+// YYYY-MM-DD HH:MM:SS.
+fn dsc_date(secs: i64) -> String {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
@@ -0,0 +1,837 @@
+// The tokenizer for the text file parser, and the argument handling for
+// the commands.  Each line is a command name followed by arguments --
+// numbers, keywords, and strings.  Strings are either double-quoted (with
+// escapes), or the rest of the line.  Errors carry the column, so that
+// the parser can report where things went wrong.
+//
+// Numbers can be arithmetic expressions, using variables set with "set";
+// the state of a script (variables, macros, loops being read) is kept in
+// Script.
+use crate::path::PathBuilder;
+use crate::PSTool;
+use std::collections::HashMap;
+use std::fmt;
+
+/// How parse_with handles errors in the input.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ParseMode {
+    /// Stop at the first error.
+    Strict,
+    /// Skip lines with errors, keep going, and report all of them at the end.
+    Lenient,
+}
+
+/// An error in a text command file.  Lines and columns count from 1.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParseError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// An error within a line -- the column, and what went wrong.
+pub(crate) struct LineError {
+    pub column: usize,
+    pub message: String,
+}
+
+// The commands, with their arguments, for error messages.  Arguments in
+// brackets are optional.
+const USAGE: &[&str] = &[
+    "color r g b",
+    "colora r g b a",
+    "fill [1|0]",
+    "fill pattern spacing [angle]",
+    "nofill",
+    "box llx lly urx ury",
+    "line x1 y1 x2 y2",
+    "circle x y radius",
+    "arc x y radius start end",
+    "wedge x y radius start end",
+    "ellipse x y rx ry angle",
+    "roundbox llx lly urx ury radius",
+    "curve x1 y1 x2 y2 x3 y3",
+    "cubic x0 y0 cx1 cy1 cx2 cy2 x3 y3",
+    "quad x0 y0 cx cy x2 y2",
+    "path fill|stroke|both|none [nonzero|evenodd] segments",
+    "clippath [nonzero|evenodd] segments",
+    "cliprect llx lly urx ury",
+    "popclip",
+    "arrowhead none|open|filled [length width]",
+    "arrow x1 y1 x2 y2",
+    "doublearrow x1 y1 x2 y2",
+    "curvedarrow x1 y1 x2 y2 x3 y3",
+    "arrowtip x y lx ly rx ry open|filled",
+    "dimension x1 y1 x2 y2 offset [label]",
+    "chart min max llx lly urx ury v1 v2 ...",
+    "linewidth width",
+    "dash offset [d1 d2 ...]",
+    "linecap butt|round|square",
+    "linejoin miter|round|bevel",
+    "miterlimit limit",
+    "font pointsize fontname",
+    "text x y string",
+    "textrotated x y angle string",
+    "textcentered x y string",
+    "textcenteredrotated x y angle string",
+    "settextln x y",
+    "textln string",
+    "comment string",
+    "set name expression",
+    "for name start end [step]",
+    "end",
+    "define name [parameters]",
+    "include filename",
+    "pscomment string",
+    "note string",
+    "postscript string",
+    "gsave",
+    "grestore",
+    "layer name",
+    "endlayer",
+    "object id [key value ...]",
+    "endobject",
+    "scale factor",
+    "translate dx dy",
+    "symbol name",
+    "endsymbol",
+    "place name x y [orientation [scale]]",
+    "border width",
+    "bounds llx lly urx ury",
+    "page letter|a4|a3|WxH|none [portrait|landscape] [margin [center|topleft]]",
+    "maxsize points",
+    "colormode rgb|cmyk|gray",
+    "deterministic [1|0]",
+    "title string",
+    "creator string",
+    "user string",
+];
+
+// The usage lines for a command (some have more than one form).
+pub(crate) fn usage(command: &str) -> String {
+    USAGE
+        .iter()
+        .filter(|u| u.split(' ').next() == Some(command))
+        .cloned()
+        .collect::<Vec<&str>>()
+        .join(", or ")
+}
+
+// A token, with the column (in characters, from 1) and byte offset where
+// it starts.
+struct Token {
+    text: String,
+    column: usize,
+    start: usize,
+    end: usize,
+    quoted: bool,
+}
+
+// The arguments of a command, taken one at a time.
+pub(crate) struct Args<'a> {
+    command: String,
+    command_column: usize,
+    line: &'a str,
+    vars: &'a HashMap<String, f32>,
+    tokens: Vec<Token>,
+    next: usize,
+    end_column: usize,
+}
+
+impl<'a> Args<'a> {
+    // Splits a line into the command and its arguments.  Blank lines and
+    // lines starting with a hash mark give None.  Expressions in the
+    // arguments use the variables.
+    pub(crate) fn new(line: &'a str, vars: &'a HashMap<String, f32>) -> Result<Option<Args<'a>>, LineError> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return Ok(None);
+        }
+        let column = |byte: usize| line[..byte].chars().count() + 1;
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        loop {
+            let rest = &line[pos..];
+            let skipped = rest.len() - rest.trim_start().len();
+            pos += skipped;
+            if pos >= line.len() {
+                break;
+            }
+            let rest = &line[pos..];
+            if rest.starts_with('"') {
+                let (text, after) = quoted(rest).map_err(|message| LineError {
+                    column: column(pos),
+                    message,
+                })?;
+                let end = line.len() - after.len();
+                if !after.is_empty() && !after.starts_with(char::is_whitespace) {
+                    return Err(LineError {
+                        column: column(end),
+                        message: "expected a space after the closing quote".to_string(),
+                    });
+                }
+                tokens.push(Token {
+                    text,
+                    column: column(pos),
+                    start: pos,
+                    end,
+                    quoted: true,
+                });
+                pos = end;
+            } else {
+                // Parenthesized expressions can hold spaces
+                let mut depth = 0;
+                let mut len = rest.len();
+                for (i, c) in rest.char_indices() {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth = 0.max(depth - 1),
+                        c if c.is_whitespace() && depth == 0 => {
+                            len = i;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                tokens.push(Token {
+                    text: rest[..len].to_string(),
+                    column: column(pos),
+                    start: pos,
+                    end: pos + len,
+                    quoted: false,
+                });
+                pos += len;
+            }
+        }
+        let command = tokens.remove(0);
+        Ok(Some(Args {
+            command: command.text,
+            command_column: command.column,
+            line,
+            vars,
+            tokens,
+            next: 0,
+            end_column: line.trim_end().chars().count() + 1,
+        }))
+    }
+
+    pub(crate) fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub(crate) fn unknown_command(&self) -> LineError {
+        LineError {
+            column: self.command_column,
+            message: format!("unknown command '{}'", self.command),
+        }
+    }
+
+    // An error at the given column, with the usage of the command
+    fn error(&self, column: usize, message: String) -> LineError {
+        let usage = usage(&self.command);
+        let message = if usage.is_empty() {
+            format!("{}: {}", self.command, message)
+        } else {
+            format!("{}: {} (usage: {})", self.command, message, usage)
+        };
+        LineError { column, message }
+    }
+
+    // An error for the argument just taken
+    pub(crate) fn last_error(&self, message: String) -> LineError {
+        let column = self.tokens[self.next - 1].column;
+        self.error(column, message)
+    }
+
+    // An error for the argument just taken, which isn't one of the choices
+    pub(crate) fn bad_keyword(&self, text: &str, expected: &str) -> LineError {
+        self.last_error(format!("unknown {}; expected {}", text, expected))
+    }
+
+    fn take(&mut self, name: &str) -> Result<&Token, LineError> {
+        if self.next >= self.tokens.len() {
+            return Err(self.error(self.end_column, format!("missing {}", name)));
+        }
+        self.next += 1;
+        Ok(&self.tokens[self.next - 1])
+    }
+
+    // True if there are arguments left
+    pub(crate) fn has_more(&self) -> bool {
+        self.next < self.tokens.len()
+    }
+
+    // The next argument, without taking it
+    pub(crate) fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.next).map(|t| t.text.as_str())
+    }
+
+    // True if the next argument is a number (or an expression)
+    pub(crate) fn next_is_num(&self) -> bool {
+        self.has_more() && eval(&self.tokens[self.next].text, self.vars).is_ok()
+    }
+
+    pub(crate) fn num(&mut self, name: &str) -> Result<f32, LineError> {
+        let token = self.take(name)?;
+        let (text, column) = (token.text.clone(), token.column);
+        eval(&text, self.vars).map_err(|e| {
+            self.error(column, format!("expected a number for {}, found '{}': {}", name, text, e))
+        })
+    }
+
+    // An expression, as the rest of the line (so that it can have spaces)
+    pub(crate) fn expr(&mut self, name: &str) -> Result<f32, LineError> {
+        let token = self.take(name)?;
+        let (start, column) = (token.start, token.column);
+        self.next = self.tokens.len();
+        let text = self.line[start..].trim_end();
+        eval(text, self.vars).map_err(|e| self.error(column, format!("bad {}: {}", name, e)))
+    }
+
+    // An optional number, at the end of the arguments
+    pub(crate) fn opt_num(&mut self, name: &str) -> Result<Option<f32>, LineError> {
+        if self.has_more() {
+            Ok(Some(self.num(name)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // All of the remaining arguments, as numbers
+    pub(crate) fn nums(&mut self, name: &str) -> Result<Vec<f32>, LineError> {
+        let mut values = Vec::new();
+        while self.has_more() {
+            values.push(self.num(name)?);
+        }
+        Ok(values)
+    }
+
+    // A keyword, converted with FromStr (line caps, fill patterns, ...)
+    pub(crate) fn keyword<T: std::str::FromStr<Err = String>>(&mut self, name: &str) -> Result<T, LineError> {
+        let token = self.take(name)?;
+        let (text, column) = (token.text.clone(), token.column);
+        text.parse::<T>().map_err(|e| self.error(column, e))
+    }
+
+    // A single argument, as it is (a name, for example)
+    pub(crate) fn word(&mut self, name: &str) -> Result<String, LineError> {
+        Ok(self.take(name)?.text.clone())
+    }
+
+    // A single argument, quoted or not, with ${expression} replaced by the
+    // value (an object ID, for example)
+    pub(crate) fn item(&mut self, name: &str) -> Result<String, LineError> {
+        let token = self.take(name)?;
+        let (text, start, end, column, quoted) = (token.text.clone(), token.start, token.end, token.column, token.quoted);
+        let raw = &self.line[start..end];
+        if !raw.contains("${") {
+            return Ok(text);
+        }
+        let value = interpolate(raw, self.vars).map_err(|e| self.error(column, e))?;
+        if quoted {
+            self::quoted(&value).map(|(s, _)| s).map_err(|e| self.error(column, e))
+        } else {
+            Ok(value)
+        }
+    }
+
+    // A string -- a quoted argument, or the rest of the line as it is --
+    // with ${expression} replaced by the value.
+    pub(crate) fn string(&mut self, name: &str) -> Result<String, LineError> {
+        let token = self.take(name)?;
+        let (text, start, end, column, quoted) = (token.text.clone(), token.start, token.end, token.column, token.quoted);
+        if !quoted {
+            self.next = self.tokens.len();
+        }
+        let raw = if quoted {
+            &self.line[start..end]
+        } else {
+            self.line[start..].trim_end()
+        };
+        if !raw.contains("${") {
+            return Ok(if quoted { text } else { raw.to_string() });
+        }
+        let value = interpolate(raw, self.vars).map_err(|e| self.error(column, e))?;
+        if quoted {
+            self::quoted(&value).map(|(s, _)| s).map_err(|e| self.error(column, e))
+        } else {
+            Ok(value)
+        }
+    }
+
+    // A path, as the rest of the arguments -- an optional fill rule, then
+    // segments, each a letter and its numbers, as in SVG: M x y (move),
+    // L x y (line), C x1 y1 x2 y2 x3 y3 (cubic), Q x1 y1 x2 y2
+    // (quadratic), A x y r start end (arc), and Z (close).
+    pub(crate) fn path(&mut self) -> Result<PathBuilder, LineError> {
+        let mut path = PathBuilder::new();
+        if matches!(self.peek(), Some("nonzero") | Some("evenodd")) {
+            path.set_fill_rule(self.keyword("fill rule")?);
+        }
+        while self.has_more() {
+            let token = self.take("segment")?;
+            let (segment, column) = (token.text.clone(), token.column);
+            match segment.as_str() {
+                "M" => {
+                    let (x, y) = (self.num("x")?, self.num("y")?);
+                    path.move_to(x, y);
+                }
+                "L" => {
+                    let (x, y) = (self.num("x")?, self.num("y")?);
+                    path.line_to(x, y);
+                }
+                "C" => {
+                    let (x1, y1, x2, y2) = (self.num("x1")?, self.num("y1")?, self.num("x2")?, self.num("y2")?);
+                    let (x3, y3) = (self.num("x3")?, self.num("y3")?);
+                    path.curve_to(x1, y1, x2, y2, x3, y3);
+                }
+                "Q" => {
+                    let (x1, y1, x2, y2) = (self.num("x1")?, self.num("y1")?, self.num("x2")?, self.num("y2")?);
+                    path.quad_to(x1, y1, x2, y2);
+                }
+                "A" => {
+                    let (x, y, r) = (self.num("x")?, self.num("y")?, self.num("radius")?);
+                    let (start, end) = (self.num("start")?, self.num("end")?);
+                    path.arc(x, y, r, start, end);
+                }
+                "Z" => {
+                    path.close_path();
+                }
+                _ => {
+                    return Err(self.error(
+                        column,
+                        format!("unknown path segment '{}'; use M, L, C, Q, A, or Z", segment),
+                    ))
+                }
+            }
+        }
+        if path.is_empty() {
+            return Err(self.error(self.end_column, "missing path segments".to_string()));
+        }
+        Ok(path)
+    }
+
+    // Checks that all of the arguments were used
+    pub(crate) fn end(&self) -> Result<(), LineError> {
+        match self.tokens.get(self.next) {
+            Some(token) => Err(self.error(token.column, format!("unexpected argument '{}'", token.text))),
+            None => Ok(()),
+        }
+    }
+}
+
+// Replaces ${expression} in a string with the value.  A backslash keeps
+// the character after it as it is, so \${ is left alone (for quoted
+// strings, where the escape then gives a plain dollar sign).
+fn interpolate(raw: &str, vars: &HashMap<String, f32>) -> Result<String, String> {
+    let mut s = String::new();
+    let mut rest = raw;
+    while let Some(i) = rest.find(['\\', '$']) {
+        s.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with('\\') {
+            let len = rest[1..].chars().next().map_or(0, char::len_utf8);
+            s.push_str(&rest[..1 + len]);
+            rest = &rest[1 + len..];
+        } else if rest.starts_with("${") {
+            let close = rest.find('}').ok_or_else(|| "missing } after ${".to_string())?;
+            let value = eval(&rest[2..close], vars)?;
+            s.push_str(&value.to_string());
+            rest = &rest[close + 1..];
+        } else {
+            s.push('$');
+            rest = &rest[1..];
+        }
+    }
+    s.push_str(rest);
+    Ok(s)
+}
+
+// A string as a double-quoted argument, with escapes -- the inverse of
+// quoted.
+pub(crate) fn quote(s: &str) -> String {
+    let mut q = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => q.push_str("\\\""),
+            '\\' => q.push_str("\\\\"),
+            '$' => q.push_str("\\$"),
+            '\n' => q.push_str("\\n"),
+            '\t' => q.push_str("\\t"),
+            c if c.is_control() => q.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => q.push(c),
+        }
+    }
+    q.push('"');
+    q
+}
+
+// A name as a single argument -- as it is, unless it needs quotes (the
+// tokenizer groups parentheses, so those need quotes as well).
+pub(crate) fn word(s: &str) -> String {
+    if s.is_empty()
+        || s.starts_with('"')
+        || s.contains(|c: char| c.is_whitespace() || c.is_control() || matches!(c, '$' | '(' | ')'))
+    {
+        quote(s)
+    } else {
+        s.to_string()
+    }
+}
+
+// Parses a double-quoted string at the start of the input, returning the
+// string and the input that follows it.  Strings can use the escapes
+// \" \\ \$ \n \t and \u{XXXX}.
+fn quoted(input: &str) -> Result<(String, &str), String> {
+    let mut chars = input.char_indices().skip(1);
+    let unterminated = || "unterminated string".to_string();
+    let mut s = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((s, &input[i + 1..])),
+            '\\' => {
+                let (_, e) = chars.next().ok_or_else(unterminated)?;
+                match e {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    '"' | '\\' | '$' => s.push(e),
+                    'u' => {
+                        // \u{XXXX}, with one to six hex digits
+                        let bad = || "bad \\u escape; use \\u{XXXX}".to_string();
+                        if chars.next().map(|(_, c)| c) != Some('{') {
+                            return Err(bad());
+                        }
+                        let mut hex = String::new();
+                        loop {
+                            match chars.next() {
+                                Some((_, '}')) => break,
+                                Some((_, h)) => hex.push(h),
+                                None => return Err(bad()),
+                            }
+                        }
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(bad)?;
+                        s.push(c);
+                    }
+                    _ => return Err(format!("unknown escape \\{}", e)),
+                }
+            }
+            _ => s.push(c),
+        }
+    }
+    Err(unterminated())
+}
+
+// Evaluates an arithmetic expression -- numbers, variables, + - * / %,
+// parentheses, and the functions abs, sqrt, floor, ceil, round, min,
+// max, sin and cos (with angles in degrees, as everywhere else).
+pub(crate) fn eval(text: &str, vars: &HashMap<String, f32>) -> Result<f32, String> {
+    if let Ok(value) = text.parse::<f32>() {
+        return Ok(value);
+    }
+    let mut e = Expr {
+        chars: text.chars().collect(),
+        pos: 0,
+        depth: 0,
+        vars,
+    };
+    let value = e.sum()?;
+    match e.peek() {
+        Some(c) => Err(format!("unexpected '{}'", c)),
+        None => Ok(value),
+    }
+}
+
+// The deepest nesting of parentheses and signs in an expression, so that
+// a deeply nested one is an error rather than a stack overflow
+const MAX_NESTING: usize = 256;
+
+// A recursive descent parser for expressions, one level per precedence
+struct Expr<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize,
+    vars: &'a HashMap<String, f32>,
+}
+
+impl Expr<'_> {
+    // The next character that isn't a space
+    fn peek(&mut self) -> Option<char> {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    fn sum(&mut self) -> Result<f32, String> {
+        let mut value = self.product()?;
+        loop {
+            match self.peek() {
+                Some('+') => {
+                    self.pos += 1;
+                    value += self.product()?;
+                }
+                Some('-') => {
+                    self.pos += 1;
+                    value -= self.product()?;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<f32, String> {
+        let mut value = self.unary()?;
+        loop {
+            match self.peek() {
+                Some('*') => {
+                    self.pos += 1;
+                    value *= self.unary()?;
+                }
+                Some('/') => {
+                    self.pos += 1;
+                    value /= self.unary()?;
+                }
+                Some('%') => {
+                    self.pos += 1;
+                    value %= self.unary()?;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<f32, String> {
+        if self.depth >= MAX_NESTING {
+            return Err("expression nested too deeply".to_string());
+        }
+        self.depth += 1;
+        let value = self.signed();
+        self.depth -= 1;
+        value
+    }
+
+    fn signed(&mut self) -> Result<f32, String> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(-self.unary()?)
+            }
+            Some('+') => {
+                self.pos += 1;
+                self.unary()
+            }
+            _ => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<f32, String> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let value = self.sum()?;
+                self.expect(')')?;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos;
+                while self.pos < self.chars.len() {
+                    let c = self.chars[self.pos];
+                    let exponent_sign = (c == '+' || c == '-') && matches!(self.chars[self.pos - 1], 'e' | 'E');
+                    if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign) {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                number.parse::<f32>().map_err(|_| format!("bad number '{}'", number))
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let start = self.pos;
+                while self.pos < self.chars.len() && (self.chars[self.pos].is_alphanumeric() || self.chars[self.pos] == '_') {
+                    self.pos += 1;
+                }
+                let name: String = self.chars[start..self.pos].iter().collect();
+                if self.peek() == Some('(') {
+                    self.pos += 1;
+                    let mut args = vec![self.sum()?];
+                    while self.peek() == Some(',') {
+                        self.pos += 1;
+                        args.push(self.sum()?);
+                    }
+                    self.expect(')')?;
+                    return function(&name, &args);
+                }
+                self.vars
+                    .get(&name)
+                    .copied()
+                    .ok_or_else(|| format!("unknown variable '{}'", name))
+            }
+            Some(c) => Err(format!("unexpected '{}'", c)),
+            None => Err("missing a value".to_string()),
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("missing '{}'", c))
+        }
+    }
+}
+
+fn function(name: &str, args: &[f32]) -> Result<f32, String> {
+    let one = |f: fn(f32) -> f32| match args {
+        [x] => Ok(f(*x)),
+        _ => Err(format!("{} takes one argument", name)),
+    };
+    let two = |f: fn(f32, f32) -> f32| match args {
+        [x, y] => Ok(f(*x, *y)),
+        _ => Err(format!("{} takes two arguments", name)),
+    };
+    match name {
+        "abs" => one(f32::abs),
+        "sqrt" => one(f32::sqrt),
+        "floor" => one(f32::floor),
+        "ceil" => one(f32::ceil),
+        "round" => one(f32::round),
+        "sin" => one(|x| degrees(x, 0, f32::sin)),
+        "cos" => one(|x| degrees(x, 1, f32::cos)),
+        "min" => two(f32::min),
+        "max" => two(f32::max),
+        _ => Err(format!("unknown function '{}'", name)),
+    }
+}
+
+// Sine or cosine of an angle in degrees, exact at multiples of 90 so
+// that right angles stay square.  The quarter is where the cycle of
+// 0, 1, 0, -1 starts (0 for sine, 1 for cosine).
+fn degrees(x: f32, quarter: usize, f: fn(f32) -> f32) -> f32 {
+    if x % 90.0 == 0.0 {
+        let q = (x / 90.0).rem_euclid(4.0) as usize;
+        [0.0, 1.0, 0.0, -1.0][(q + quarter) % 4]
+    } else {
+        f(x.to_radians())
+    }
+}
+
+// A line of a command file, and where it came from -- loop and macro
+// bodies are kept as lines, and run later.
+#[derive(Clone)]
+pub(crate) struct Line {
+    pub file: String,
+    pub number: usize,
+    pub text: String,
+}
+
+// A macro, defined with "define name parameters", and called by name
+pub(crate) struct Macro {
+    pub params: Vec<String>,
+    pub body: Vec<Line>,
+}
+
+// What to do with a block, once its end is found
+pub(crate) enum BlockKind {
+    For { var: String, start: f32, end: f32, step: f32 },
+    Define { name: String, params: Vec<String> },
+}
+
+// What a line of a script does, beyond drawing
+pub(crate) enum Action {
+    Done,
+    Set(String, f32),
+    Block(BlockKind),
+    Include(std::fs::File, String),
+    Call(String, Vec<f32>),
+}
+
+// A for loop or macro definition being read, up to its end line
+pub(crate) struct Block {
+    pub kind: BlockKind,
+    pub header: Line,
+    pub body: Vec<Line>,
+    pub nesting: usize,
+}
+
+// The state of a script while it runs.
+pub(crate) struct Script {
+    pub mode: ParseMode,
+    pub errors: Vec<ParseError>,
+    pub vars: HashMap<String, f32>,
+    pub macros: HashMap<String, Macro>,
+    // Symbols being defined, with the line each started on
+    pub symbols: Vec<(String, Line, PSTool)>,
+    pub block: Option<Block>,
+    // Includes and macro calls in progress
+    pub depth: usize,
+}
+
+// Limits, against runaway scripts
+pub(crate) const MAX_DEPTH: usize = 64;
+pub(crate) const MAX_ITERATIONS: usize = 1_000_000;
+
+impl Script {
+    pub(crate) fn new(mode: ParseMode) -> Script {
+        Script {
+            mode,
+            errors: Vec::new(),
+            vars: HashMap::new(),
+            macros: HashMap::new(),
+            symbols: Vec::new(),
+            block: None,
+            depth: 0,
+        }
+    }
+
+    pub(crate) fn error(&mut self, line: &Line, e: LineError) {
+        self.errors.push(ParseError {
+            file: line.file.clone(),
+            line: line.number,
+            column: e.column,
+            message: e.message,
+        });
+    }
+
+    // Starts an include or a macro call, unless they are nested too
+    // deeply (most likely, one that includes or calls itself)
+    pub(crate) fn enter(&mut self) -> Result<(), LineError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(LineError {
+                column: 1,
+                message: "includes or macro calls nested too deeply".to_string(),
+            });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // In strict mode, everything stops at the first error
+    pub(crate) fn stopped(&self) -> bool {
+        self.mode == ParseMode::Strict && !self.errors.is_empty()
+    }
+}
+
+// The first word of a line, to find the ends of blocks without parsing
+pub(crate) fn first_word(text: &str) -> &str {
+    text.split_whitespace().next().unwrap_or("")
+}
+
+// Control commands and the drawing commands are reserved; macros can't
+// use their names.
+pub(crate) fn is_command(name: &str) -> bool {
+    !usage(name).is_empty()
+}
@@ -0,0 +1,280 @@
+use crate::bbox::BBox;
+
+/// Fill rules for paths with holes or self-intersections.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FillRule {
+    /// A point is inside if the path winds around it a nonzero number
+    /// of times (PostScript fill, the default).  Holes need to run in the
+    /// opposite direction from the outline.
+    NonZero,
+    /// A point is inside if a ray from it crosses the path an odd number
+    /// of times (PostScript eofill).  Holes can run in either direction.
+    EvenOdd,
+}
+
+impl std::str::FromStr for FillRule {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<FillRule, String> {
+        match s {
+            "nonzero" => Ok(FillRule::NonZero),
+            "evenodd" => Ok(FillRule::EvenOdd),
+            _ => Err(format!("unknown fill rule {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for FillRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            FillRule::NonZero => "nonzero",
+            FillRule::EvenOdd => "evenodd",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One step of a path.  Angles are in degrees, and arcs run
+/// counterclockwise (as with the PostScript arc operator).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PathSegment {
+    MoveTo(f32, f32),
+    LineTo(f32, f32),
+    /// Cubic Bezier, with two control points and the end point.
+    CurveTo(f32, f32, f32, f32, f32, f32),
+    /// Center, radius, start angle, end angle.  If the path has a current
+    /// point, a straight line joins it to the start of the arc.
+    Arc(f32, f32, f32, f32, f32),
+    ClosePath,
+}
+
+/// Builds a compound path -- any number of subpaths, made of lines,
+/// curves, and arcs -- which is then added to a PSTool with add_path,
+/// as a single event.  Subpaths inside other subpaths become holes
+/// (depending on the fill rule).
+/// ```
+/// use pstools::PSTool;
+/// use pstools::path::{FillRule, PathBuilder};
+/// let mut path = PathBuilder::new();
+/// path.move_to(0.0, 0.0).line_to(30.0, 0.0).line_to(30.0, 30.0).line_to(0.0, 30.0).close_path();
+/// path.move_to(10.0, 10.0).line_to(20.0, 10.0).line_to(20.0, 20.0).line_to(10.0, 20.0).close_path();
+/// path.set_fill_rule(FillRule::EvenOdd);
+/// let mut pst = PSTool::new();
+/// pst.add_path(&path, true, true);
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+pub struct PathBuilder {
+    // Private, so that every segment goes through the builder calls,
+    // which make sure a line or curve has a point to start from.
+    segments: Vec<PathSegment>,
+    pub fill_rule: FillRule,
+}
+
+impl Default for PathBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PathBuilder {
+    pub fn new() -> PathBuilder {
+        PathBuilder {
+            segments: Vec::new(),
+            fill_rule: FillRule::NonZero,
+        }
+    }
+    /// Starts a new subpath at (x, y).
+    pub fn move_to(&mut self, x: f32, y: f32) -> &mut PathBuilder {
+        self.segments.push(PathSegment::MoveTo(x, y));
+        self
+    }
+    /// Adds a straight line to (x, y).  With no current point, this
+    /// starts a subpath at (x, y) instead, as move_to does.
+    pub fn line_to(&mut self, x: f32, y: f32) -> &mut PathBuilder {
+        if !self.has_current_point() {
+            return self.move_to(x, y);
+        }
+        self.segments.push(PathSegment::LineTo(x, y));
+        self
+    }
+    /// Adds a cubic Bezier curve, with control points (x1, y1) and
+    /// (x2, y2), ending at (x3, y3).  With no current point, the curve
+    /// starts at the first control point.
+    pub fn curve_to(
+        &mut self,
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+        x3: f32,
+        y3: f32,
+    ) -> &mut PathBuilder {
+        if !self.has_current_point() {
+            self.move_to(x1, y1);
+        }
+        self.segments
+            .push(PathSegment::CurveTo(x1, y1, x2, y2, x3, y3));
+        self
+    }
+    /// Adds a quadratic Bezier curve from the current point, with
+    /// control point (x1, y1), ending at (x2, y2).  The curve is stored
+    /// as the equivalent cubic.
+    pub fn quad_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) -> &mut PathBuilder {
+        let (x0, y0) = self.current_point().unwrap_or((x1, y1));
+        let (c1x, c1y, c2x, c2y) = crate::quadratic_controls(x0, y0, x1, y1, x2, y2);
+        self.curve_to(c1x, c1y, c2x, c2y, x2, y2)
+    }
+    /// Adds a counterclockwise circular arc; if there is a current point,
+    /// it is joined to the start of the arc with a straight line.
+    pub fn arc(&mut self, x: f32, y: f32, r: f32, start: f32, end: f32) -> &mut PathBuilder {
+        self.segments.push(PathSegment::Arc(x, y, r, start, end));
+        self
+    }
+    /// Closes the current subpath, with a line back to its start.
+    pub fn close_path(&mut self) -> &mut PathBuilder {
+        self.segments.push(PathSegment::ClosePath);
+        self
+    }
+    /// Selects the fill rule, which decides which nested subpaths are holes.
+    pub fn set_fill_rule(&mut self, rule: FillRule) -> &mut PathBuilder {
+        self.fill_rule = rule;
+        self
+    }
+    /// The current point -- where the next segment starts -- if any.
+    pub fn current_point(&self) -> Option<(f32, f32)> {
+        let mut current = None;
+        let mut subpath_start = None;
+        for seg in &self.segments {
+            match *seg {
+                PathSegment::MoveTo(x, y) => {
+                    current = Some((x, y));
+                    subpath_start = current;
+                }
+                PathSegment::LineTo(x, y) | PathSegment::CurveTo(_, _, _, _, x, y) => {
+                    if current.is_none() {
+                        subpath_start = Some((x, y));
+                    }
+                    current = Some((x, y));
+                }
+                PathSegment::Arc(x, y, r, start, end) => {
+                    let (sa, ca) = start.to_radians().sin_cos();
+                    if current.is_none() {
+                        subpath_start = Some((x + r * ca, y + r * sa));
+                    }
+                    let (sa, ca) = end.to_radians().sin_cos();
+                    current = Some((x + r * ca, y + r * sa));
+                }
+                PathSegment::ClosePath => current = subpath_start,
+            }
+        }
+        current
+    }
+    // Whether there is a current point, without working out where it is:
+    // any segment but a close path leaves one.
+    fn has_current_point(&self) -> bool {
+        self.segments.iter().any(|seg| *seg != PathSegment::ClosePath)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+    /// The segments, in order.
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    /// Returns the bounding box of the path (not counting the line
+    /// width), using the true extents of curves and arcs.
+    pub fn bbox(&self) -> BBox {
+        let mut bbox = BBox::new();
+        let mut current = (0.0, 0.0);
+        let mut subpath_start = (0.0, 0.0);
+        for seg in &self.segments {
+            match *seg {
+                PathSegment::MoveTo(x, y) => {
+                    bbox.addpoint(x, y);
+                    current = (x, y);
+                    subpath_start = current;
+                }
+                PathSegment::LineTo(x, y) => {
+                    bbox.addpoint(x, y);
+                    current = (x, y);
+                }
+                PathSegment::CurveTo(x1, y1, x2, y2, x3, y3) => {
+                    bbox.addcubic(current.0, current.1, x1, y1, x2, y2, x3, y3);
+                    current = (x3, y3);
+                }
+                PathSegment::Arc(x, y, r, start, end) => {
+                    if !bbox.valid {
+                        let (sa, ca) = start.to_radians().sin_cos();
+                        subpath_start = (x + r * ca, y + r * sa);
+                    }
+                    bbox.addarc(x, y, r, start, end);
+                    let (sa, ca) = end.to_radians().sin_cos();
+                    current = (x + r * ca, y + r * sa);
+                }
+                PathSegment::ClosePath => current = subpath_start,
+            }
+        }
+        bbox
+    }
+
+    // PostScript path construction, with coordinates multiplied by scale
+    // (and passed through round, for deterministic output).
+    pub(crate) fn postscript(&self, scale: f32, round: fn(f32) -> f32) -> String {
+        let mut ps = vec!["newpath".to_string()];
+        for seg in &self.segments {
+            ps.push(match *seg {
+                PathSegment::MoveTo(x, y) => format!("{} {} moveto", round(scale * x), round(scale * y)),
+                PathSegment::LineTo(x, y) => format!("{} {} lineto", round(scale * x), round(scale * y)),
+                PathSegment::CurveTo(x1, y1, x2, y2, x3, y3) => format!(
+                    "{} {} {} {} {} {} curveto",
+                    round(scale * x1),
+                    round(scale * y1),
+                    round(scale * x2),
+                    round(scale * y2),
+                    round(scale * x3),
+                    round(scale * y3)
+                ),
+                PathSegment::Arc(x, y, r, start, end) => format!(
+                    "{} {} {} {} {} arc",
+                    round(scale * x),
+                    round(scale * y),
+                    round(scale * r),
+                    round(start),
+                    round(end)
+                ),
+                PathSegment::ClosePath => "closepath".to_string(),
+            });
+        }
+        ps.join(" ")
+    }
+
+    // SVG path data.  SVG arcs can't join from the current point the way
+    // PostScript does, so the current point is tracked here.
+    pub(crate) fn svg(&self, n: fn(f32) -> f32) -> String {
+        let mut d = Vec::new();
+        let mut current = false;
+        for seg in &self.segments {
+            match *seg {
+                PathSegment::MoveTo(x, y) => {
+                    d.push(format!("M {} {}", n(x), n(y)));
+                    current = true;
+                }
+                PathSegment::LineTo(x, y) => {
+                    d.push(format!("{} {} {}", if current { "L" } else { "M" }, n(x), n(y)));
+                    current = true;
+                }
+                PathSegment::CurveTo(x1, y1, x2, y2, x3, y3) => {
+                    d.push(format!("C {} {} {} {} {} {}", n(x1), n(y1), n(x2), n(y2), n(x3), n(y3)));
+                    current = true;
+                }
+                PathSegment::Arc(x, y, r, start, end) => {
+                    let arc = crate::svg::arc_path(x, y, r, start, end, n);
+                    d.push(format!("{} {}", if current { "L" } else { "M" }, arc));
+                    current = true;
+                }
+                PathSegment::ClosePath => d.push("Z".to_string()),
+            }
+        }
+        d.join(" ")
+    }
+}
@@ -12,6 +12,12 @@ impl fmt::Display for Point {
     }
 }
 
+impl Default for Point {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Point {
     pub fn new() -> Point {
         Point { x: 0.0, y: 0.0 }
@@ -0,0 +1,670 @@
+// SVG output for PSTool.  The events are walked the same way as in
+// generate, but written as SVG elements.  SVG has the origin at the top
+// left, so everything is wrapped in a group that flips the Y axis; text
+// is flipped back so that it reads the right way up.  The group also sets
+// the PostScript default miter limit (SVG defaults to 4).
+use crate::path::FillRule;
+use crate::{round_output, ColorMode, Fill, FillPattern, LineCap, LineJoin, PSTag, PSTool};
+use std::fs::File;
+use std::io::Result;
+use std::io::Write;
+
+// The parts of the graphic state that gsave/grestore cover.  The fill
+// state is not part of this -- it isn't part of the PostScript graphic
+// state either.
+#[derive(Clone)]
+struct SvgState {
+    color: String,
+    alpha: f32,
+    line_width: f32,
+    font: String,
+    font_scale: f32,
+    dash: String,
+    line_cap: LineCap,
+    line_join: LineJoin,
+    miter_limit: f32,
+    groups: usize,
+}
+
+impl SvgState {
+    // Paint attributes for an outline
+    fn stroke(&self) -> String {
+        format!("fill=\"none\" {}", self.stroke_attrs())
+    }
+
+    // Paint attributes for a shape that is both filled and outlined
+    fn fill_and_stroke(&self, paint: &str) -> String {
+        let mut attrs = format!("fill=\"{}\"", paint);
+        if self.alpha < 1.0 {
+            attrs.push_str(&format!(" fill-opacity=\"{}\"", self.alpha));
+        }
+        format!("{} {}", attrs, self.stroke_attrs())
+    }
+
+    fn stroke_attrs(&self) -> String {
+        let mut attrs = format!(
+            "stroke=\"{}\" stroke-width=\"{}\"",
+            self.color, self.line_width
+        );
+        if self.alpha < 1.0 {
+            attrs.push_str(&format!(" stroke-opacity=\"{}\"", self.alpha));
+        }
+        attrs.push_str(&self.dash);
+        match self.line_cap {
+            LineCap::Butt => {}
+            LineCap::Round => attrs.push_str(" stroke-linecap=\"round\""),
+            LineCap::Square => attrs.push_str(" stroke-linecap=\"square\""),
+        }
+        match self.line_join {
+            LineJoin::Miter => {}
+            LineJoin::Round => attrs.push_str(" stroke-linejoin=\"round\""),
+            LineJoin::Bevel => attrs.push_str(" stroke-linejoin=\"bevel\""),
+        }
+        if self.miter_limit != 10.0 {
+            attrs.push_str(&format!(" stroke-miterlimit=\"{}\"", self.miter_limit));
+        }
+        attrs
+    }
+
+    // Paint attributes for a filled shape (or text)
+    fn fill(&self) -> String {
+        self.fill_with(&self.color)
+    }
+
+    // Paint attributes for a shape filled with a color or a pattern
+    fn fill_with(&self, paint: &str) -> String {
+        let mut attrs = format!("fill=\"{}\" stroke=\"none\"", paint);
+        if self.alpha < 1.0 {
+            attrs.push_str(&format!(" fill-opacity=\"{}\"", self.alpha));
+        }
+        attrs
+    }
+}
+
+// Ids that must be unique across the whole file, including the symbol
+// definitions -- the pattern keys, and the number of clip paths.
+struct SvgOutput {
+    patterns: Vec<String>,
+    clips: usize,
+    // Rounding for the numbers written, in deterministic mode
+    round: fn(f32) -> f32,
+}
+
+// Returns the paint for a patterned fill, writing the pattern
+// definition the first time a pattern is used with a particular color.
+// SVG patterns carry their own color, unlike the uncolored PostScript
+// patterns, so each pattern/color combination needs a definition.
+fn pattern_paint(
+    f: &mut dyn Write,
+    out: &mut SvgOutput,
+    fill: &Fill,
+    color: &str,
+) -> Result<String> {
+    let defined = &mut out.patterns;
+    let key = format!(
+        "{:?} {} {} {}",
+        fill.pattern, fill.spacing, fill.angle, color
+    );
+    if let Some(i) = defined.iter().position(|k| *k == key) {
+        return Ok(format!("url(#pat{})", i));
+    }
+    let n = out.round;
+    let s = n(fill.spacing);
+    let h = n(s / 2.0);
+    let lw = n(s / 8.0);
+    let (step, content) = match fill.pattern {
+        FillPattern::Hatch => (
+            s,
+            format!(
+                "<line x1=\"0\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                h, s, h, color, lw
+            ),
+        ),
+        FillPattern::CrossHatch => (
+            s,
+            format!(
+                "<path d=\"M 0 {} H {} M {} 0 V {}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                h, s, h, s, color, lw
+            ),
+        ),
+        FillPattern::Dots => (
+            s,
+            format!(
+                "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"/>",
+                h,
+                h,
+                n(s / 5.0),
+                color
+            ),
+        ),
+        FillPattern::Checkerboard => (
+            2.0 * s,
+            format!(
+                "<path d=\"M 0 0 h {} v {} h -{} Z M {} {} h {} v {} h -{} Z\" fill=\"{}\"/>",
+                s, s, s, s, s, s, s, s, color
+            ),
+        ),
+        FillPattern::Solid => return Ok(color.to_string()),
+    };
+    let i = defined.len();
+    writeln!(
+        f,
+        "<defs><pattern id=\"pat{}\" patternUnits=\"userSpaceOnUse\" width=\"{}\" height=\"{}\" patternTransform=\"rotate({})\">{}</pattern></defs>",
+        i, n(step), n(step), n(fill.angle), content
+    )?;
+    defined.push(key);
+    Ok(format!("url(#pat{})", i))
+}
+
+// Path data for an arc, counterclockwise from start to end degrees (the
+// same as the PostScript arc operator), starting with the coordinates of
+// the first point.  The Y axis flip keeps counterclockwise the positive
+// sweep direction.  SVG can't draw a full circle in one arc, so longer
+// sweeps are split in half.
+pub(crate) fn arc_path(x: f32, y: f32, r: f32, start: f32, end: f32, n: fn(f32) -> f32) -> String {
+    let mut sweep = (end - start) % 360.0;
+    if sweep < 0.0 {
+        sweep += 360.0;
+    }
+    if sweep == 0.0 && end != start {
+        sweep = 360.0;
+    }
+    let point = |a: f32| {
+        let a = a.to_radians();
+        (n(x + r * a.cos()), n(y + r * a.sin()))
+    };
+    let (sx, sy) = point(start);
+    let mut d = format!("{} {}", sx, sy);
+    let steps = if sweep > 180.0 { 2 } else { 1 };
+    for i in 1..=steps {
+        let (px, py) = point(start + sweep * i as f32 / steps as f32);
+        d.push_str(&format!(" A {} {} 0 0 1 {} {}", n(r), n(r), px, py));
+    }
+    d
+}
+
+// Escapes the characters that can't appear in SVG text or attributes.
+pub(crate) fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// A property key as the rest of a data- attribute name: lower case
+// letters, digits, dashes, underscores and dots.
+fn data_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| match c {
+            'A'..='Z' => c.to_ascii_lowercase(),
+            'a'..='z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '-',
+        })
+        .collect();
+    if name.is_empty() {
+        "property".to_string()
+    } else {
+        name
+    }
+}
+
+// XML comments cannot contain a double dash.
+fn xml_comment(s: &str) -> String {
+    s.replace("--", "- -")
+}
+
+fn svg_color(r: f32, g: f32, b: f32, mode: ColorMode) -> String {
+    let (r, g, b) = match mode {
+        ColorMode::Gray => {
+            let v = crate::rgb_to_gray(r, g, b);
+            (v, v, v)
+        }
+        _ => (r, g, b),
+    };
+    let c = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", c(r), c(g), c(b))
+}
+
+// Maps a PostScript font name (Times-Roman, Helvetica-Bold, ...) to
+// SVG font attributes.
+fn svg_font(font: &str, scale: f32) -> String {
+    let mut parts = font.splitn(2, '-');
+    let family = parts.next().unwrap_or("Courier");
+    let style = parts.next().unwrap_or("");
+    let family = match family {
+        "Times" => "Times, serif".to_string(),
+        "Helvetica" => "Helvetica, Arial, sans-serif".to_string(),
+        "Courier" => "Courier, monospace".to_string(),
+        _ => xml_escape(family),
+    };
+    let mut attrs = format!("font-family=\"{}\" font-size=\"{}\"", family, scale);
+    if style.contains("Bold") {
+        attrs.push_str(" font-weight=\"bold\"");
+    }
+    if style.contains("Oblique") || style.contains("Italic") {
+        attrs.push_str(" font-style=\"italic\"");
+    }
+    attrs
+}
+
+impl PSTool {
+    /// Generates SVG output from the stored events, as an alternative to
+    /// PostScript.  Returns the number of events processed.  Colors are
+    /// written as RGB (or gray, in ColorMode::Gray); alpha becomes
+    /// fill-opacity and stroke-opacity.  Raw PostScript from add_postscript
+    /// can't be rendered, and is written as an SVG comment.  An error
+    /// creating or writing the file is returned.
+    pub fn generate_svg(&self, filepath: String) -> Result<usize> {
+        let mut f = File::create(filepath)?;
+
+        writeln!(&mut f, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        let n = self.svg_round();
+        let (llx, lly, urx, ury) = self.output_bounds();
+        let size = format!("width=\"{}pt\" height=\"{}pt\"", n(urx - llx), n(ury - lly));
+        self.write_svg(&mut f, &size)?;
+
+        Ok(self.events.len())
+    }
+
+    // In deterministic mode, numbers are rounded as in the PostScript, so
+    // that the output doesn't depend on the platform's sin and cos.
+    fn svg_round(&self) -> fn(f32) -> f32 {
+        if self.deterministic {
+            round_output
+        } else {
+            |v| v
+        }
+    }
+
+    // Writes the svg element, with the given attributes for its size.
+    // The HTML viewer embeds this as well.
+    pub(crate) fn write_svg(&self, f: &mut dyn Write, size: &str) -> Result<()> {
+        let n = self.svg_round();
+        let (llx, lly, urx, ury) = self.output_bounds();
+        let (llx, lly, urx, ury) = (n(llx), n(lly), n(urx), n(ury));
+        let dx = n(urx - llx);
+        let dy = n(ury - lly);
+
+        writeln!(
+            f,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" {} viewBox=\"{} {} {} {}\">",
+            size, llx, lly, dx, dy
+        )?;
+        writeln!(
+            f,
+            "<!-- Binghamton PSTools https://github.com/profmadden/pstools_r -->"
+        )?;
+        for s in &self.notes {
+            writeln!(f, "<!-- {} -->", xml_comment(s))?;
+        }
+        writeln!(
+            f,
+            "<g transform=\"matrix(1 0 0 -1 0 {})\" stroke-miterlimit=\"10\">",
+            n(lly + ury)
+        )?;
+
+        let state = SvgState {
+            color: "#000000".to_string(),
+            alpha: 1.0,
+            line_width: 1.0,
+            font: "Courier".to_string(),
+            font_scale: 12.0,
+            dash: String::new(),
+            line_cap: LineCap::Butt,
+            line_join: LineJoin::Miter,
+            miter_limit: 10.0,
+            groups: 0,
+        };
+        let mut out = SvgOutput {
+            patterns: Vec::new(),
+            clips: 0,
+            round: n,
+        };
+        self.write_svg_symbols(f, &mut out, state.clone(), "")?;
+        self.write_svg_events(f, &mut out, state, "")?;
+        writeln!(f, "</g>")?;
+        writeln!(f, "</svg>")
+    }
+
+    // Writes a definition for each symbol, after the definitions for any
+    // symbols nested inside it.  Symbols are drawn in currentColor until
+    // they set a color, so that they take the color of each use.
+    fn write_svg_symbols(
+        &self,
+        f: &mut dyn Write,
+        out: &mut SvgOutput,
+        state: SvgState,
+        prefix: &str,
+    ) -> Result<()> {
+        for (i, (name, symbol)) in self.symbols.iter().enumerate() {
+            let inner = format!("{}{}_", prefix, i);
+            let mut sym_state = state.clone();
+            sym_state.color = "currentColor".to_string();
+            sym_state.groups = 0;
+            symbol.write_svg_symbols(f, out, sym_state.clone(), &inner)?;
+            writeln!(f, "<!-- Symbol {} -->", xml_comment(name))?;
+            writeln!(f, "<defs><g id=\"sym{}{}\">", prefix, i)?;
+            symbol.write_svg_events(f, out, sym_state, &inner)?;
+            writeln!(f, "</g></defs>")?;
+        }
+        Ok(())
+    }
+
+    // Writes the SVG elements for the events, starting from the given
+    // state.  Symbols have their own PSTool, so this is also used for the
+    // symbol definitions; the prefix keeps their ids distinct.
+    fn write_svg_events(
+        &self,
+        f: &mut dyn Write,
+        out: &mut SvgOutput,
+        mut state: SvgState,
+        prefix: &str,
+    ) -> Result<()> {
+        let mut stack: Vec<SvgState> = Vec::new();
+        let mut fill_style = Fill::solid(false);
+        let n = out.round;
+
+        for e in &self.events {
+            unsafe {
+                let stroke = state.stroke();
+                let fill = state.fill();
+                let fillable = [PSTag::B, PSTag::R, PSTag::WG, PSTag::E, PSTag::RB];
+                let paint = if fill_style.fill && fillable.contains(&e.tag) {
+                    let p = pattern_paint(f, out, &fill_style, &state.color)?;
+                    state.fill_with(&p)
+                } else {
+                    stroke.clone()
+                };
+                if e.tag == PSTag::PA {
+                    let p = e.event.path;
+                    let path = &self.paths[p.path];
+                    let fill_paint = if p.fill {
+                        // The current fill pattern, if there is one
+                        let mut style = fill_style;
+                        style.fill = true;
+                        pattern_paint(f, out, &style, &state.color)?
+                    } else {
+                        "none".to_string()
+                    };
+                    let attrs = match (p.fill, p.stroke) {
+                        (true, true) => state.fill_and_stroke(&fill_paint),
+                        (true, false) => state.fill_with(&fill_paint),
+                        (false, true) => stroke.clone(),
+                        (false, false) => "fill=\"none\" stroke=\"none\"".to_string(),
+                    };
+                    let rule = match path.fill_rule {
+                        FillRule::NonZero => "",
+                        FillRule::EvenOdd => " fill-rule=\"evenodd\"",
+                    };
+                    writeln!(f, "<path d=\"{}\" {}{}/>", path.svg(n), attrs, rule)?;
+                }
+
+                if e.tag == PSTag::C {
+                    let c = e.event.color;
+                    state.color = svg_color(c.r, c.g, c.b, self.color_mode);
+                    state.alpha = c.a;
+                }
+                if e.tag == PSTag::B {
+                    let l = e.event.line;
+                    writeln!(
+                        f,
+                        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}/>",
+                        n(l.llx.min(l.urx)),
+                        n(l.lly.min(l.ury)),
+                        n((l.urx - l.llx).abs()),
+                        n((l.ury - l.lly).abs()),
+                        paint
+                    )?;
+                }
+                if e.tag == PSTag::L {
+                    let l = e.event.line;
+                    writeln!(
+                        f,
+                        "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" {}/>",
+                        n(l.llx), n(l.lly), n(l.urx), n(l.ury), stroke
+                    )?;
+                }
+                if e.tag == PSTag::W {
+                    state.line_width = n(e.event.line_width);
+                }
+                if e.tag == PSTag::D {
+                    let pattern = &self.dashes[e.event.dash.pattern];
+                    state.dash = if pattern.is_empty() {
+                        String::new()
+                    } else {
+                        let lengths: Vec<String> = pattern.iter().map(|d| n(*d).to_string()).collect();
+                        format!(
+                            " stroke-dasharray=\"{}\" stroke-dashoffset=\"{}\"",
+                            lengths.join(" "),
+                            n(e.event.dash.offset)
+                        )
+                    };
+                }
+                if e.tag == PSTag::LC {
+                    state.line_cap = e.event.line_cap;
+                }
+                if e.tag == PSTag::LJ {
+                    state.line_join = e.event.line_join;
+                }
+                if e.tag == PSTag::ML {
+                    state.miter_limit = e.event.miter_limit;
+                }
+                if e.tag == PSTag::R {
+                    let l = e.event.line;
+                    writeln!(
+                        f,
+                        "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" {}/>",
+                        n(l.llx), n(l.lly), n(l.urx), paint
+                    )?;
+                }
+                if e.tag == PSTag::A {
+                    let a = e.event.arc;
+                    writeln!(
+                        f,
+                        "<path d=\"M {}\" {}/>",
+                        arc_path(a.x, a.y, a.r, a.start, a.end, n),
+                        stroke
+                    )?;
+                }
+                if e.tag == PSTag::WG {
+                    let a = e.event.arc;
+                    writeln!(
+                        f,
+                        "<path d=\"M {} {} L {} Z\" {}/>",
+                        n(a.x),
+                        n(a.y),
+                        arc_path(a.x, a.y, a.r, a.start, a.end, n),
+                        paint
+                    )?;
+                }
+                if e.tag == PSTag::E {
+                    let el = e.event.ellipse;
+                    writeln!(
+                        f,
+                        "<ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" transform=\"rotate({} {} {})\" {}/>",
+                        n(el.x), n(el.y), n(el.rx), n(el.ry), n(el.angle), n(el.x), n(el.y), paint
+                    )?;
+                }
+                if e.tag == PSTag::RB {
+                    let (llx, lly, urx, ury, r) = e.event.rbox.normalized();
+                    writeln!(
+                        f,
+                        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{}\" {}/>",
+                        n(llx),
+                        n(lly),
+                        n(urx - llx),
+                        n(ury - lly),
+                        n(r),
+                        paint
+                    )?;
+                }
+                if e.tag == PSTag::V {
+                    let c = e.event.curve;
+                    let (close, paint) = if c.fillable && fill_style.fill {
+                        let p = pattern_paint(f, out, &fill_style, &state.color)?;
+                        (" Z", state.fill_with(&p))
+                    } else {
+                        ("", stroke.clone())
+                    };
+                    writeln!(
+                        f,
+                        "<path d=\"M {} {} C {} {} {} {} {} {}{}\" {}/>",
+                        n(c.x0), n(c.y0), n(c.x1), n(c.y1), n(c.x2), n(c.y2), n(c.x3), n(c.y3), close, paint
+                    )?;
+                }
+                if e.tag == PSTag::F {
+                    fill_style = e.event.fill;
+                }
+                if e.tag == PSTag::T || e.tag == PSTag::TC {
+                    let t = e.event.text;
+                    let mut transform = format!("translate({} {}) scale(1 -1)", n(t.x), n(t.y));
+                    if t.angle != 0.0 {
+                        transform.push_str(&format!(" rotate({})", n(-t.angle)));
+                    }
+                    let anchor = if e.tag == PSTag::TC {
+                        " text-anchor=\"middle\""
+                    } else {
+                        ""
+                    };
+                    writeln!(
+                        f,
+                        "<text transform=\"{}\" {} {}{}>{}</text>",
+                        transform,
+                        svg_font(&state.font, state.font_scale),
+                        fill,
+                        anchor,
+                        xml_escape(&self.te[t.text])
+                    )?;
+                }
+                if e.tag == PSTag::H {
+                    let h = e.event.head;
+                    writeln!(
+                        f,
+                        "<path d=\"M {} {} L {} {} L {} {}{}\" {}/>",
+                        n(h.lx),
+                        n(h.ly),
+                        n(h.tip_x),
+                        n(h.tip_y),
+                        n(h.rx),
+                        n(h.ry),
+                        if h.filled { " Z" } else { "" },
+                        if h.filled { &fill } else { &stroke }
+                    )?;
+                }
+                if e.tag == PSTag::N {
+                    writeln!(f, "<!-- {} -->", xml_comment(&self.te[e.event.text.text]))?;
+                }
+                if e.tag == PSTag::FN {
+                    state.font = self.te[e.event.font.font_name].clone();
+                    state.font_scale = n(e.event.font.scale);
+                }
+                if e.tag == PSTag::P {
+                    writeln!(
+                        f,
+                        "<!-- PostScript (not rendered): {} -->",
+                        xml_comment(&self.te[e.event.text.text])
+                    )?;
+                }
+                if e.tag == PSTag::GS {
+                    stack.push(state.clone());
+                    state.groups = 0;
+                }
+                if e.tag == PSTag::CS {
+                    let path = &self.paths[e.event.path.path];
+                    let rule = match path.fill_rule {
+                        FillRule::NonZero => "",
+                        FillRule::EvenOdd => " clip-rule=\"evenodd\"",
+                    };
+                    writeln!(
+                        f,
+                        "<clipPath id=\"clip{}\"><path d=\"{}\"{}/></clipPath>",
+                        out.clips,
+                        path.svg(n),
+                        rule
+                    )?;
+                    writeln!(f, "<g clip-path=\"url(#clip{})\">", out.clips)?;
+                    out.clips += 1;
+                    stack.push(state.clone());
+                    state.groups = 1;
+                }
+                if e.tag == PSTag::LB {
+                    writeln!(
+                        f,
+                        "<g data-layer=\"{}\">",
+                        xml_escape(&self.te[e.event.text.text])
+                    )?;
+                    stack.push(state.clone());
+                    state.groups = 1;
+                }
+                if e.tag == PSTag::OB {
+                    let (id, properties) = &self.objects[e.event.object];
+                    // IDs needn't be unique (several shapes can be on one
+                    // net), so they aren't used as the id attribute.
+                    let mut attributes = format!(" data-id=\"{}\"", xml_escape(id));
+                    let mut names = vec!["id".to_string()];
+                    let mut title = id.clone();
+                    for (key, value) in properties {
+                        // Attribute names can only be used once; every
+                        // property is still in the title.
+                        let name = data_name(key);
+                        if !names.contains(&name) {
+                            attributes += &format!(" data-{}=\"{}\"", name, xml_escape(value));
+                            names.push(name);
+                        }
+                        title += &format!("\n{}: {}", key, value);
+                    }
+                    writeln!(f, "<g{}><title>{}</title>", attributes, xml_escape(&title))?;
+                    stack.push(state.clone());
+                    state.groups = 1;
+                }
+                if e.tag == PSTag::GR || e.tag == PSTag::CR || e.tag == PSTag::LE || e.tag == PSTag::OE {
+                    if let Some(prior) = stack.pop() {
+                        for _ in 0..state.groups {
+                            writeln!(f, "</g>")?;
+                        }
+                        state = prior;
+                    } else {
+                        println!("PSTools: Unmatched graphic restore");
+                    }
+                }
+                if e.tag == PSTag::I {
+                    let inst = e.event.instance;
+                    let (a, b, c, d) = inst.orient.matrix();
+                    writeln!(
+                        f,
+                        "<use href=\"#sym{}{}\" transform=\"translate({} {}) matrix({} {} {} {} 0 0) scale({})\" color=\"{}\"/>",
+                        prefix, inst.symbol, n(inst.x), n(inst.y), a, b, c, d, n(inst.scale), state.color
+                    )?;
+                }
+                if e.tag == PSTag::S {
+                    writeln!(f, "<g transform=\"scale({})\">", n(e.event.scale.scale))?;
+                    state.groups += 1;
+                }
+                if e.tag == PSTag::X {
+                    writeln!(
+                        f,
+                        "<g transform=\"translate({} {})\">",
+                        n(e.event.translate.dx), n(e.event.translate.dy)
+                    )?;
+                    state.groups += 1;
+                }
+            }
+        }
+
+        // Close any transformations that were never restored
+        let open = state.groups + stack.iter().map(|s| s.groups).sum::<usize>();
+        for _ in 0..open {
+            writeln!(f, "</g>")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+// Tests for arrows: the shaft stops under a filled head, and never
+// sticks out of the back of it.
+use pstools::*;
+
+fn lines(pst: &PSTool) -> Vec<(f32, f32, f32, f32)> {
+    pst.events()
+        .filter_map(|(_, e)| match e {
+            Event::Line { x1, y1, x2, y2 } => Some((x1, y1, x2, y2)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn double_arrow_shaft() {
+    let mut pst = PSTool::new();
+    pst.set_arrow_head(ArrowHead::Filled, 6.0, 4.0);
+    pst.add_double_arrow(0.0, 0.0, 20.0, 0.0);
+    assert_eq!(lines(&pst), [(6.0, 0.0, 14.0, 0.0)]);
+
+    // Heads that meet, or overlap, leave no room for a shaft
+    for length in [12.0, 8.0, 1.0] {
+        let mut pst = PSTool::new();
+        pst.set_arrow_head(ArrowHead::Filled, 6.0, 4.0);
+        pst.add_double_arrow(0.0, 0.0, length, 0.0);
+        assert_eq!(lines(&pst), [], "length {}", length);
+        assert_eq!(pst.len(), 2);
+    }
+
+    // Open heads don't cover the shaft, so it runs tip to tip
+    let mut pst = PSTool::new();
+    pst.set_arrow_head(ArrowHead::Open, 6.0, 4.0);
+    pst.add_double_arrow(0.0, 0.0, 8.0, 0.0);
+    assert_eq!(lines(&pst), [(0.0, 0.0, 8.0, 0.0)]);
+}
+
+#[test]
+fn short_arrow() {
+    let mut pst = PSTool::new();
+    pst.set_arrow_head(ArrowHead::Filled, 6.0, 4.0);
+    pst.add_arrow(0.0, 0.0, 0.0, 4.0);
+    assert_eq!(lines(&pst), []);
+    pst.add_arrow(0.0, 0.0, 0.0, 10.0);
+    assert_eq!(lines(&pst), [(0.0, 0.0, 0.0, 4.0)]);
+}
+
+// The curve stops where it enters a filled head, and follows the same
+// path up to there.
+#[test]
+fn curved_arrow_under_head() {
+    let mut pst = PSTool::new();
+    pst.set_arrow_head(ArrowHead::Filled, 6.0, 4.0);
+    pst.add_curved_arrow(0.0, 0.0, 40.0, 0.0, 40.0, 40.0);
+    let curve = pst.events().find_map(|(_, e)| match e {
+        Event::Curve { x1, y1, x2, y2, x3, y3 } => Some((x1, y1, x2, y2, x3, y3)),
+        _ => None,
+    });
+    let (x1, y1, x2, y2, x3, y3) = curve.unwrap();
+    assert_eq!((x1, y1), (0.0, 0.0));
+    assert!(((x3 - 40.0).hypot(y3 - 40.0) - 6.0).abs() < 0.01);
+    // The new end is on the original curve, which has
+    // x(t) = 40(3t^2 - 2t^3) and y(t) = 40t^3.
+    let t = (y3 / 40.0).cbrt();
+    assert!((40.0 * (3.0 * t * t - 2.0 * t * t * t) - x3).abs() < 0.01);
+    // And it leaves in the same direction, towards (40, 0)
+    assert_eq!(y2, 0.0);
+    assert!(x2 > 0.0 && x2 < 40.0);
+
+    // Open heads leave the curve alone
+    let mut pst = PSTool::new();
+    pst.set_arrow_head(ArrowHead::Open, 6.0, 4.0);
+    pst.add_curved_arrow(0.0, 0.0, 40.0, 0.0, 40.0, 40.0);
+    assert_eq!(
+        pst.event(1),
+        Some(Event::Curve { x1: 0.0, y1: 0.0, x2: 40.0, y2: 0.0, x3: 40.0, y3: 40.0 })
+    );
+
+    // A curve that is all inside the head is left out
+    let mut pst = PSTool::new();
+    pst.set_arrow_head(ArrowHead::Filled, 6.0, 4.0);
+    pst.add_curved_arrow(0.0, 0.0, 2.0, 0.0, 2.0, 2.0);
+    assert_eq!(pst.len(), 1);
+}
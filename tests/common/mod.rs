@@ -0,0 +1,65 @@
+// Helpers shared by the integration tests.  Each test file is its own
+// crate, and uses only some of these.
+#![allow(dead_code)]
+use pstools::PSTool;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The text commands for the events, from write_commands.
+pub fn commands(pst: &PSTool) -> String {
+    let mut text = Vec::new();
+    pst.write_commands(&mut text).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+/// The JSON scene, from write_json.
+pub fn json(pst: &PSTool) -> String {
+    let mut text = Vec::new();
+    pst.write_json(&mut text).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+/// A new scratch directory, for the caller to remove.  Each has its own
+/// number, as tests run at the same time.
+pub fn scratch_dir() -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNT.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("pstools_test_{}_{}", std::process::id(), n));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A path in a directory that doesn't exist, so that creating (or
+/// opening) the file fails.
+pub fn unwritable(name: &str) -> String {
+    let path = std::env::temp_dir().join("pstools_no_such_dir").join(name);
+    path.to_string_lossy().to_string()
+}
+
+// Writes a file named name in a scratch directory, and returns what was
+// written; the directory is then removed.
+fn written<F: FnOnce(String) -> std::io::Result<usize>>(name: &str, write: F) -> String {
+    let dir = scratch_dir();
+    let path = dir.join(name);
+    write(path.to_string_lossy().to_string()).unwrap();
+    let output = fs::read_to_string(path).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+    output
+}
+
+/// The PostScript from generate, written as a file with the given name
+/// (which appears in the %%Title comment).
+pub fn generate(pst: &PSTool, name: &str) -> String {
+    written(name, |path| pst.generate(path))
+}
+
+/// The output of generate_svg.
+pub fn generate_svg(pst: &PSTool) -> String {
+    written("figure.svg", |path| pst.generate_svg(path))
+}
+
+/// The output of generate_html.
+pub fn generate_html(pst: &PSTool) -> String {
+    written("figure.html", |path| pst.generate_html(path))
+}
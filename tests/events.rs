@@ -0,0 +1,110 @@
+// Tests for event handles: they follow their event through removal,
+// truncation, and filtering, and are never reused.
+use pstools::*;
+
+fn circles(pst: &mut PSTool, n: usize) -> Vec<EventHandle> {
+    (0..n)
+        .map(|i| {
+            pst.add_circle(i as f32, 0.0, 1.0);
+            pst.last_handle().unwrap()
+        })
+        .collect()
+}
+
+fn circle(i: usize) -> Option<Event<'static>> {
+    Some(Event::Circle { x: i as f32, y: 0.0, radius: 1.0 })
+}
+
+// Removing an earlier event moves the index, not the handle.
+#[test]
+fn handle_survives_removal() {
+    let mut pst = PSTool::new();
+    let handles = circles(&mut pst, 4);
+    assert!(pst.remove_event(1));
+    assert_eq!(pst.index_of(handles[0]), Some(0));
+    assert_eq!(pst.index_of(handles[2]), Some(1));
+    assert_eq!(pst.index_of(handles[3]), Some(2));
+    assert_eq!(pst.event(pst.index_of(handles[2]).unwrap()), circle(2));
+    // Events added after the removal get handles of their own
+    pst.add_box(0.0, 0.0, 1.0, 1.0);
+    let last = pst.last_handle().unwrap();
+    assert!(!handles.contains(&last));
+    assert_eq!(pst.index_of(last), Some(3));
+    assert!(pst.remove_handle(handles[0]));
+    assert_eq!(pst.index_of(handles[3]), Some(1));
+    assert_eq!(pst.index_of(last), Some(2));
+}
+
+// A removed event's handle finds nothing, and can't be removed twice.
+#[test]
+fn removed_handle() {
+    let mut pst = PSTool::new();
+    let handles = circles(&mut pst, 3);
+    assert!(pst.remove_handle(handles[1]));
+    assert_eq!(pst.index_of(handles[1]), None);
+    assert!(!pst.remove_handle(handles[1]));
+    assert!(!pst.replace_handle(handles[1], Event::Circle { x: 0.0, y: 0.0, radius: 2.0 }));
+    assert_eq!(pst.len(), 2);
+}
+
+// Events added after a truncate don't take over the dropped handles.
+#[test]
+fn truncate_never_reuses_handles() {
+    let mut pst = PSTool::new();
+    let handles = circles(&mut pst, 4);
+    pst.truncate(2);
+    assert_eq!(pst.index_of(handles[2]), None);
+    assert_eq!(pst.index_of(handles[3]), None);
+    let again = circles(&mut pst, 3);
+    for h in &again {
+        assert!(!handles.contains(h));
+    }
+    assert_eq!(pst.index_of(handles[2]), None);
+    assert_eq!(pst.index_of(handles[3]), None);
+    assert_eq!(pst.index_of(handles[1]), Some(1));
+    assert_eq!(pst.index_of(again[0]), Some(2));
+    assert_eq!(pst.index_of(again[2]), Some(4));
+    // The same goes for handles that were never asked for
+    let mut pst = PSTool::new();
+    circles(&mut pst, 2);
+    pst.add_box(0.0, 0.0, 1.0, 1.0);
+    pst.truncate(2);
+    pst.add_box(0.0, 0.0, 2.0, 2.0);
+    let h = pst.last_handle().unwrap();
+    assert_eq!(pst.handle(2), Some(h));
+    assert_ne!(pst.index_of(h), None);
+    assert!(!circles(&mut pst, 1).contains(&h));
+}
+
+// A filtered copy keeps the handles of the events it keeps.
+#[test]
+fn filter_keeps_handles() {
+    let mut pst = PSTool::new();
+    let handles = circles(&mut pst, 4);
+    let odd = pst.filter(|e| matches!(e, Event::Circle { x, .. } if x as usize % 2 == 1));
+    assert_eq!(odd.len(), 2);
+    assert_eq!(odd.index_of(handles[0]), None);
+    assert_eq!(odd.index_of(handles[1]), Some(0));
+    assert_eq!(odd.index_of(handles[2]), None);
+    assert_eq!(odd.index_of(handles[3]), Some(1));
+    assert_eq!(odd.event(1), circle(3));
+    // The original is unchanged
+    assert_eq!(pst.len(), 4);
+    assert_eq!(pst.index_of(handles[2]), Some(2));
+}
+
+// Replacing with a placement of an undefined symbol fails, and leaves
+// the event as it was.
+#[test]
+fn replace_with_undefined_symbol() {
+    let mut pst = PSTool::new();
+    let handles = circles(&mut pst, 2);
+    let place = Event::Place { symbol: "nosuch", x: 0.0, y: 0.0, orientation: Orientation::N, scale: 1.0 };
+    assert!(!pst.replace_event(0, place));
+    assert!(!pst.replace_handle(handles[1], place));
+    assert_eq!(pst.len(), 2);
+    assert_eq!(pst.event(0), circle(0));
+    assert_eq!(pst.event(1), circle(1));
+    assert_eq!(pst.index_of(handles[0]), Some(0));
+    assert_eq!(pst.index_of(handles[1]), Some(1));
+}
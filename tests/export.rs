@@ -0,0 +1,48 @@
+// Tests for the SVG and HTML exporters.
+mod common;
+
+use common::{generate_html, unwritable};
+use pstools::*;
+
+// A file that can't be created is an error, not a count of zero events.
+#[test]
+fn svg_file_errors() {
+    let mut pst = PSTool::new();
+    pst.add_box(0.0, 0.0, 10.0, 10.0);
+    assert!(pst.generate_svg(unwritable("figure.svg")).is_err());
+}
+
+// The page embeds the SVG, with its layers and objects, and the script
+// for it, and loads nothing from anywhere else.
+#[test]
+fn html_is_self_contained() {
+    let mut pst = PSTool::new();
+    pst.begin_layer("metal1".to_string());
+    pst.begin_object("U1".to_string(), &[("cell", "NAND2")]);
+    pst.add_box(0.0, 0.0, 10.0, 5.0);
+    pst.end_object();
+    pst.end_layer();
+    let html = generate_html(&pst);
+
+    assert!(html.contains("<svg"));
+    assert!(html.contains("<script>"));
+    assert!(html.contains("<g data-layer=\"metal1\">"));
+    assert!(html.contains("<g data-id=\"U1\" data-cell=\"NAND2\"><title>U1\ncell: NAND2</title>"));
+    // The only URL outside comments is the SVG namespace, which is a name
+    let mut rest = html.as_str();
+    let mut text = String::new();
+    while let Some(start) = rest.find("<!--") {
+        text += &rest[..start];
+        rest = &rest[start + rest[start..].find("-->").unwrap() + 3..];
+    }
+    text += rest;
+    let text = text.replace("http://www.w3.org/2000/svg", "");
+    assert!(!text.contains("://"), "external URL in the page");
+    assert!(!text.contains("src=") && !text.contains("<link"));
+}
+
+#[test]
+fn html_file_errors() {
+    let pst = PSTool::new();
+    assert!(pst.generate_html(unwritable("figure.html")).is_err());
+}
@@ -0,0 +1,276 @@
+// Golden-file tests for deterministic PostScript output.  Each scene is
+// generated in deterministic mode and compared byte for byte with the
+// file in tests/golden.  After an intended change to the output, run
+// with PSTOOLS_UPDATE_GOLDEN=1 to rewrite the golden files, and review
+// the differences before committing them.
+mod common;
+
+use common::{generate, generate_svg};
+use pstools::path::{FillRule, PathBuilder};
+use pstools::*;
+use std::fs;
+use std::path::PathBuf;
+
+fn check_golden(pst: &PSTool, name: &str) {
+    let output = generate(pst, name);
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name);
+    if std::env::var("PSTOOLS_UPDATE_GOLDEN").is_ok() {
+        fs::write(&golden, &output).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|_| panic!("missing {}; run with PSTOOLS_UPDATE_GOLDEN=1", golden.display()));
+    assert!(output == expected, "{} differs from the golden file", name);
+}
+
+fn shapes() -> PSTool {
+    let mut pst = PSTool::new();
+    pst.set_deterministic(true);
+    pst.add_note("Basic shapes".to_string());
+    pst.set_color(0.5, 0.5, 0.5, 1.0);
+    pst.set_fill(true);
+    pst.add_box(10.0, 10.0, 60.0, 20.0);
+    pst.set_color(0.0, 0.0, 1.0, 1.0);
+    pst.set_fill(false);
+    pst.add_box(5.0, 15.0, 44.0, 56.0);
+    pst.add_line(5.0, 5.0, 77.0, 80.0);
+    pst.add_circle(100.0, 40.0, 15.0);
+    pst.set_color(0.6, 0.1, 0.6, 1.0);
+    pst.add_curve(2.0, 2.0, 40.0, 90.0, 80.0, 20.0);
+    pst.set_font(14.0, "Times-Roman".to_string());
+    pst.add_text(10.0, 30.0, "PSTools".to_string());
+    pst.add_text_rotated(120.0, 10.0, 30.0, "rotated".to_string());
+    pst.set_border(5.0);
+    pst
+}
+
+fn styles() -> PSTool {
+    let mut pst = PSTool::new();
+    pst.set_deterministic(true);
+    pst.set_color_mode(ColorMode::Cmyk);
+    pst.set_line_width(2.0);
+    pst.set_dash(vec![6.0, 3.0], 0.0);
+    pst.set_line_cap(LineCap::Round);
+    pst.set_line_join(LineJoin::Bevel);
+    pst.set_miter_limit(4.0);
+    pst.add_box(0.0, 0.0, 50.0, 50.0);
+    pst.set_dash(Vec::new(), 0.0);
+    pst.set_color(0.8, 0.2, 0.1, 0.5);
+    pst.set_fill_pattern(FillPattern::Hatch, 4.0, 45.0);
+    pst.add_box(25.0, 25.0, 75.0, 75.0);
+    pst.set_fill_pattern(FillPattern::Dots, 5.0, 0.0);
+    pst.add_circle(100.0, 50.0, 20.0);
+    pst.set_fill_pattern(FillPattern::Checkerboard, 3.0, 0.0);
+    pst.add_wedge(150.0, 50.0, 25.0, 30.0, 300.0);
+    pst.set_fill(false);
+    pst
+}
+
+fn geometry() -> PSTool {
+    let mut pst = PSTool::new();
+    pst.set_deterministic(true);
+    pst.set_arrow_head(ArrowHead::Filled, 8.0, 5.0);
+    pst.add_arrow(0.0, 0.0, 60.0, 35.0);
+    pst.set_arrow_head(ArrowHead::Open, 6.0, 4.0);
+    pst.add_double_arrow(0.0, 50.0, 60.0, 50.0);
+    pst.add_dimension(0.0, 70.0, 80.0, 70.0, 10.0, "80".to_string());
+    pst.add_arc(120.0, 20.0, 20.0, 10.0, 200.0);
+    pst.add_ellipse(120.0, 80.0, 30.0, 12.0, 30.0);
+    pst.add_rounded_box(160.0, 0.0, 220.0, 40.0, 8.0);
+    pst.add_cubic(160.0, 60.0, 170.0, 120.0, 210.0, 20.0, 220.0, 80.0);
+    pst.add_quadratic(160.0, 100.0, 190.0, 140.0, 220.0, 100.0);
+
+    let mut path = PathBuilder::new();
+    path.move_to(240.0, 0.0).line_to(300.0, 0.0).line_to(300.0, 60.0).line_to(240.0, 60.0).close_path();
+    path.move_to(255.0, 15.0).line_to(285.0, 15.0).line_to(285.0, 45.0).line_to(255.0, 45.0).close_path();
+    path.set_fill_rule(FillRule::EvenOdd);
+    pst.add_path(&path, true, true);
+
+    pst.push_clip_rect(240.0, 70.0, 280.0, 110.0);
+    pst.add_circle(280.0, 110.0, 30.0);
+    pst.pop_clip();
+    pst
+}
+
+// Angles that come out of trigonometry -- a diagonal dimension, rotated
+// text, and a scaled symbol -- so that every number in the output has to
+// be rounded to stay the same across platforms.
+fn angles() -> PSTool {
+    let mut pst = PSTool::new();
+    pst.set_deterministic(true);
+    pst.set_color(0.3, 0.7, 0.1, 1.0);
+    pst.set_line_width(0.3);
+    pst.set_miter_limit(2.7);
+    pst.add_dimension(0.0, 0.0, 30.0, 17.0, 5.0, "34.5".to_string());
+    pst.add_text_rotated(10.0, 40.0, 33.3, "tilted".to_string());
+    pst.add_arc(60.0, 20.0, 10.0, 12.3, 201.7);
+    pst.add_ellipse(60.0, 50.0, 9.0, 4.0, 17.1);
+    let mut dot = PSTool::new();
+    dot.add_circle(0.0, 0.0, 1.0);
+    pst.define_symbol("dot".to_string(), &dot);
+    pst.place_symbol("dot", 80.0, 10.0, Orientation::FE, 0.3);
+    pst.add_scale(0.7);
+    pst.add_box(0.0, 0.0, 10.0, 10.0);
+    pst
+}
+
+fn composed() -> PSTool {
+    let mut cell = PSTool::new();
+    cell.add_box(0.0, 0.0, 20.0, 10.0);
+    cell.add_line(0.0, 0.0, 5.0, 10.0);
+
+    let mut row = PSTool::new();
+    row.define_symbol("INV".to_string(), &cell);
+    for (i, orient) in ["N", "FN", "S", "FS", "E", "W", "FE", "FW"].iter().enumerate() {
+        row.place_symbol("INV", 25.0 * i as f32, 0.0, orient.parse().unwrap(), 1.0);
+    }
+
+    let mut chart = PSTool::new();
+    chart.chart(vec![1.0, 3.0, 2.0, 5.0, 4.0], 0.0, 0.0, 80.0, 60.0, 0.0, 6.0);
+
+    let mut pst = PSTool::compose_grid(&[&row, &chart, &cell], 2, 10.0);
+    pst.set_deterministic(true);
+    pst.embed(&cell, Transform::new(0.0, -40.0, 0.5));
+    pst.set_page(PageSetup {
+        size: PageSize::Letter,
+        orientation: PageOrientation::Landscape,
+        margin: 36.0,
+        center: true,
+    });
+    pst.set_title("Composed figure".to_string());
+    pst
+}
+
+#[test]
+fn golden_shapes() {
+    check_golden(&shapes(), "shapes.ps");
+}
+
+#[test]
+fn golden_styles() {
+    check_golden(&styles(), "styles.ps");
+}
+
+#[test]
+fn golden_geometry() {
+    check_golden(&geometry(), "geometry.ps");
+}
+
+#[test]
+fn golden_angles() {
+    check_golden(&angles(), "angles.ps");
+}
+
+#[test]
+fn golden_composed() {
+    check_golden(&composed(), "composed.ps");
+}
+
+// Identical events give identical bytes, wherever the file is written.
+#[test]
+fn deterministic_output_is_repeatable() {
+    let first = generate(&geometry(), "figure.ps");
+    let second = generate(&geometry(), "figure.ps");
+    assert!(first == second);
+    assert!(first.contains("%%CreationDate: 1970-01-01 00:00:00"));
+    assert!(!first.contains("%%For:"));
+}
+
+// The header says Clean7Bit, so text beyond ASCII is escaped everywhere,
+// including the comments.
+#[test]
+fn output_is_7_bit() {
+    let mut pst = PSTool::new();
+    pst.set_deterministic(true);
+    pst.set_title("caf\u{e9}".to_string());
+    pst.add_note("\u{263a} note".to_string());
+    pst.add_comment("line\nbreak \u{e9}".to_string());
+    pst.begin_layer("l\u{e9}".to_string());
+    pst.add_object("\u{263a}".to_string(), &[("k\u{e9}", "v\u{e9}")], |p| {
+        p.add_text(0.0, 0.0, "caf\u{e9} \u{263a}".to_string());
+    });
+    pst.end_layer();
+    let mut dot = PSTool::new();
+    dot.add_circle(0.0, 0.0, 1.0);
+    pst.define_symbol("a\nerasepage \u{e9}".to_string(), &dot);
+    pst.place_symbol("a\nerasepage \u{e9}", 0.0, 0.0, Orientation::N, 1.0);
+    let output = generate(&pst, "figure.ps");
+    assert!(output.is_ascii());
+    assert!(output.contains("%% Symbol a erasepage \\u{e9}\n"));
+    assert!(output.contains("%%Title: caf\\u{e9}\n"));
+    assert!(output.contains("%% \\u{263a} note\n"));
+    assert!(output.contains("%% line break \\u{e9}\n"));
+    assert!(output.contains("(caf\\351 ?)"));
+}
+
+// Deterministic SVG is rounded like the PostScript, so that the results
+// of sin and cos don't depend on the platform.
+#[test]
+fn deterministic_svg_is_rounded() {
+    let mut pst = geometry();
+    pst.add_arc(10.0, 10.0, 7.0, 10.0, 290.0);
+    pst.add_wedge(30.0, 10.0, 3.3, 33.0, 77.0);
+    let svg = generate_svg(&pst);
+    for number in svg.split(|c: char| !(c.is_ascii_digit() || c == '.')) {
+        if let Some((_, decimals)) = number.split_once('.') {
+            assert!(decimals.len() <= 3, "{} isn't rounded", number);
+        }
+    }
+}
+
+// A symbol embedded under a name already used here is renamed, so the
+// name still places the symbol defined here.
+#[test]
+fn embedded_symbols_are_renamed() {
+    let mut dot = PSTool::new();
+    dot.add_circle(0.0, 0.0, 1.0);
+    let mut square = PSTool::new();
+    square.add_box(0.0, 0.0, 2.0, 2.0);
+
+    let mut panel = PSTool::new();
+    panel.define_symbol("mark".to_string(), &square);
+    panel.place_symbol("mark", 0.0, 0.0, Orientation::N, 1.0);
+    let mut pst = PSTool::new();
+    pst.define_symbol("mark".to_string(), &dot);
+    pst.embed(&panel, Transform::new(10.0, 0.0, 1.0));
+    pst.embed(&panel, Transform::new(20.0, 0.0, 1.0));
+    pst.place_symbol("mark", 0.0, 0.0, Orientation::N, 1.0);
+
+    let mut text = Vec::new();
+    pst.write_commands(&mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.contains("symbol mark.2\nbox 0 0 2 2\nendsymbol\n"), "{}", text);
+    assert!(text.contains("symbol mark.3\n"), "{}", text);
+    assert!(text.ends_with("place mark 0 0 N 1\n"), "{}", text);
+}
+
+// The corner radius of a rounded box is limited to half the shorter side,
+// and square corners are drawn without arcto (which fails on a zero radius).
+#[test]
+fn rounded_box_radius() {
+    let mut pst = PSTool::new();
+    pst.set_deterministic(true);
+    pst.add_rounded_box(0.0, 0.0, 10.0, 4.0, 100.0);
+    pst.add_rounded_box(0.0, 10.0, 10.0, 14.0, -1.0);
+    let mut text = Vec::new();
+    pst.write_commands(&mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert_eq!(text, "deterministic\nroundbox 0 0 10 4 2\nroundbox 0 10 10 14 0\n");
+
+    let output = generate(&pst, "figure.ps");
+    assert_eq!(output.matches("arcto").count(), 4);
+    assert!(output.contains("lineto closepath stroke"), "{}", output);
+}
+
+// Colors are converted for the color mode when the output is written.
+#[test]
+fn color_modes() {
+    let mut pst = PSTool::new();
+    pst.set_deterministic(true);
+    pst.set_color(1.0, 0.5, 0.0, 1.0);
+    pst.add_box(0.0, 0.0, 10.0, 10.0);
+    pst.set_color_mode(ColorMode::Cmyk);
+    assert!(generate(&pst, "figure.ps").contains("\n0 0.5 1 0 setcmykcolor\n"));
+    pst.set_color_mode(ColorMode::Gray);
+    assert!(generate(&pst, "figure.ps").contains("\n0.593 setgray\n"));
+}
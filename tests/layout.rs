@@ -0,0 +1,168 @@
+// Tests for multi-panel figures: where the panels go, how each drawing
+// is scaled into its panel, and the captions.
+mod common;
+
+use common::generate;
+use pstools::bbox::BBox;
+use pstools::layout::SubfigureLayout;
+use pstools::*;
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 0.001
+}
+
+fn rect(b: &BBox) -> (f32, f32, f32, f32) {
+    (b.llx, b.lly, b.urx, b.ury)
+}
+
+// Two rows of two on a 400 by 300 page, with the default spacing of 10
+// and caption strips of 16: each cell is 200 by 150, less 5 all around,
+// with the caption strip at the bottom.
+#[test]
+fn panel_rectangles() {
+    let layout = SubfigureLayout::new(2, 2, 400.0, 300.0);
+    let panels: Vec<_> = layout.panels().iter().map(|(d, c)| (rect(d), rect(c))).collect();
+    assert_eq!(
+        panels,
+        [
+            ((5.0, 171.0, 195.0, 295.0), (5.0, 155.0, 195.0, 171.0)),
+            ((205.0, 171.0, 395.0, 295.0), (205.0, 155.0, 395.0, 171.0)),
+            ((5.0, 21.0, 195.0, 145.0), (5.0, 5.0, 195.0, 21.0)),
+            ((205.0, 21.0, 395.0, 145.0), (205.0, 5.0, 395.0, 21.0)),
+        ]
+    );
+
+    let mut layout = SubfigureLayout::new(1, 3, 300.0, 100.0);
+    layout.set_spacing(0.0).set_caption_font("Helvetica".to_string(), 8.0, 20.0);
+    let panels: Vec<_> = layout.panels().iter().map(|(d, c)| (rect(d), rect(c))).collect();
+    assert_eq!(
+        panels,
+        [
+            ((0.0, 20.0, 100.0, 100.0), (0.0, 0.0, 100.0, 20.0)),
+            ((100.0, 20.0, 200.0, 100.0), (100.0, 0.0, 200.0, 20.0)),
+            ((200.0, 20.0, 300.0, 100.0), (200.0, 0.0, 300.0, 20.0)),
+        ]
+    );
+}
+
+// Each drawing is scaled by the same factor in x and y, as large as fits
+// the panel, and centered in the other direction.
+#[test]
+fn compose_scales_to_fit() {
+    // Panels are 190 wide and 194 high (a 200 by 220 cell, less the
+    // spacing and the caption strip), starting at (5, 21) and (205, 21).
+    let mut square = PSTool::new();
+    square.add_box(0.0, 0.0, 100.0, 100.0);
+    let mut wide = PSTool::new();
+    wide.add_line(-100.0, 10.0, 300.0, 60.0);
+    let layout = SubfigureLayout::new(1, 2, 400.0, 220.0);
+    let figure = layout.compose(&[(&square, "early"), (&wide, "late")]);
+
+    let transforms: Vec<(f32, f32, f32)> = figure
+        .events()
+        .filter_map(|(_, e)| match e {
+            Event::Translate { dx, dy } => Some((dx, dy)),
+            _ => None,
+        })
+        .zip(figure.events().filter_map(|(_, e)| match e {
+            Event::Scale { factor } => Some(factor),
+            _ => None,
+        }))
+        .map(|((dx, dy), factor)| (dx, dy, factor))
+        .collect();
+    assert_eq!(transforms.len(), 2);
+
+    // The square is limited by the width, and centered in the height.
+    // Its bounding box includes half the line width on each side.
+    let (llx, lly, urx, ury) = square.bbox();
+    let (dx, dy, scale) = transforms[0];
+    assert!(close(scale, 190.0 / (urx - llx)));
+    assert!(close(dx + scale * llx, 5.0));
+    assert!(close(dy + scale * lly, 21.0 + (194.0 - scale * (ury - lly)) / 2.0));
+
+    // The line is about 400 by 50, so the width limits it again; its
+    // lower left corner lands on the left edge of the second panel.
+    let (llx, lly, urx, ury) = wide.bbox();
+    let (dx, dy, scale) = transforms[1];
+    assert!(close(scale, 190.0 / (urx - llx)));
+    assert!(close(dx + scale * llx, 205.0));
+    assert!(close(dy + scale * lly, 21.0 + (194.0 - scale * (ury - lly)) / 2.0));
+
+    // A tall drawing is limited by the height, and centered across
+    let mut tall = PSTool::new();
+    tall.add_box(0.0, 0.0, 10.0, 200.0);
+    let figure = layout.compose(&[(&tall, "")]);
+    let (llx, lly, urx, ury) = tall.bbox();
+    let (dx, dy) = figure
+        .events()
+        .find_map(|(_, e)| match e {
+            Event::Translate { dx, dy } => Some((dx, dy)),
+            _ => None,
+        })
+        .unwrap();
+    let scale = figure
+        .events()
+        .find_map(|(_, e)| match e {
+            Event::Scale { factor } => Some(factor),
+            _ => None,
+        })
+        .unwrap();
+    assert!(close(scale, 194.0 / (ury - lly)));
+    assert!(close(dy + scale * lly, 21.0));
+    assert!(close(dx + scale * llx, 5.0 + (190.0 - scale * (urx - llx)) / 2.0));
+}
+
+// Captions are labeled (a), (b), and so on, centered in the strip under
+// each panel, and the figure's output bounds are the whole page.
+#[test]
+fn compose_captions_and_bounds() {
+    let mut square = PSTool::new();
+    square.add_box(0.0, 0.0, 100.0, 100.0);
+    let layout = SubfigureLayout::new(1, 2, 400.0, 220.0);
+    let figure = layout.compose(&[(&square, "early"), (&square, "late")]);
+    let captions: Vec<(f32, f32, String)> = figure
+        .events()
+        .filter_map(|(_, e)| match e {
+            Event::Text { x, y, text, centered: true, .. } => Some((x, y, text.to_string())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(captions.len(), 2);
+    // The strips run from 5 to 21; the baseline is a third of the 10
+    // point font below the middle.
+    let baseline = 13.0 - 10.0 / 3.0;
+    assert_eq!(captions[0].2, "(a) early");
+    assert!(close(captions[0].0, 100.0) && close(captions[0].1, baseline));
+    assert_eq!(captions[1].2, "(b) late");
+    assert!(close(captions[1].0, 300.0) && close(captions[1].1, baseline));
+    let output = generate(&figure, "figure.ps");
+    assert!(output.contains("%%BoundingBox: 0 0 400 220\n"));
+
+    // Without labels, the caption is used as it is, and an empty one is
+    // left out
+    let mut layout = SubfigureLayout::new(1, 2, 400.0, 220.0);
+    layout.set_labels(false);
+    let figure = layout.compose(&[(&square, "early"), (&square, "")]);
+    let captions: Vec<&str> = figure
+        .events()
+        .filter_map(|(_, e)| match e {
+            Event::Text { text, .. } => Some(text),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(captions, ["early"]);
+
+    // Labels past z carry on with aa, ab, ...
+    let layout = SubfigureLayout::new(1, 28, 2800.0, 100.0);
+    let panels: Vec<(&PSTool, &str)> = (0..28).map(|_| (&square, "x")).collect();
+    let figure = layout.compose(&panels);
+    let last: Vec<String> = figure
+        .events()
+        .filter_map(|(_, e)| match e {
+            Event::Text { text, .. } => Some(text.to_string()),
+            _ => None,
+        })
+        .skip(25)
+        .collect();
+    assert_eq!(last, ["(z) x", "(aa) x", "(ab) x"]);
+}
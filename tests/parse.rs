@@ -0,0 +1,45 @@
+// Tests for the errors from reading text commands: the file, line, and
+// column of each, the usage, and strict and lenient parsing.
+mod common;
+
+use common::unwritable;
+use pstools::*;
+
+#[test]
+fn parse_errors() {
+    let error = |text: &str| {
+        let mut pst = PSTool::new();
+        let errors = pst.parse_reader(text.as_bytes(), "figure.txt", ParseMode::Strict).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        errors[0].clone()
+    };
+    let e = error("color 1 0 0\n\nbox 0 0 1 x\n");
+    assert_eq!((e.file.as_str(), e.line, e.column), ("figure.txt", 3, 11));
+    assert_eq!(
+        e.message,
+        "box: expected a number for ury, found 'x': unknown variable 'x' (usage: box llx lly urx ury)"
+    );
+    let e = error("circle 1 2\n");
+    assert_eq!((e.line, e.column), (1, 11));
+    assert_eq!(e.to_string(), "figure.txt:1:11: circle: missing radius (usage: circle x y radius)");
+    let e = error("# comment\n  boks 0 0 1 1\n");
+    assert_eq!(e.to_string(), "figure.txt:2:3: unknown command 'boks'");
+    let e = error("text 0 0 \"abc\n");
+    assert_eq!(e.to_string(), "figure.txt:1:10: unterminated string");
+
+    // Lenient parsing skips the bad lines, and reports all of them
+    let mut pst = PSTool::new();
+    let errors = pst.parse_str("box 0 0 1\ncircle 0 0 1\nlinecap flat\n", ParseMode::Lenient).unwrap_err();
+    let lines: Vec<(usize, usize)> = errors.iter().map(|e| (e.line, e.column)).collect();
+    assert_eq!(lines, [(1, 10), (3, 9)]);
+    assert_eq!(errors[1].message, "linecap: unknown line cap flat (usage: linecap butt|round|square)");
+    assert_eq!(pst.len(), 1);
+
+    // parse returns the errors too, including a file that isn't there
+    let missing = unwritable("figure.txt");
+    let errors = pst.parse(missing.clone()).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].file.as_str(), errors[0].line), (missing.as_str(), 0));
+    assert!(errors[0].message.starts_with("can't open file: "));
+    assert_eq!(pst.len(), 1);
+}
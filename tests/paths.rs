@@ -0,0 +1,23 @@
+// Tests for paths built with PathBuilder, or read from text commands
+// and JSON.
+mod common;
+
+use common::commands;
+use pstools::path::{PathBuilder, PathSegment};
+use pstools::*;
+
+// A path has to start somewhere: a first line starts the subpath at its
+// end point, and a first curve at its first control point.
+#[test]
+fn path_start() {
+    let mut pst = PSTool::new();
+    pst.parse_str("path stroke L 1 2 L 3 4\npath stroke Z C 1 2 3 4 5 6\n", ParseMode::Strict).unwrap();
+    pst.parse_json(r#"{"events": [{"type": "path", "segments": [["L", 1, 2], ["L", 3, 4]]}]}"#).unwrap();
+    assert_eq!(
+        commands(&pst),
+        "path stroke M 1 2 L 3 4\npath stroke Z M 1 2 C 1 2 3 4 5 6\npath stroke M 1 2 L 3 4\n"
+    );
+    let mut path = PathBuilder::new();
+    path.line_to(1.0, 2.0);
+    assert_eq!(path.segments(), [PathSegment::MoveTo(1.0, 2.0)]);
+}
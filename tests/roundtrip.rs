@@ -0,0 +1,433 @@
+// Round-trip tests for write_commands and write_json.  Random scenes are
+// built through the drawing API, written as text commands or JSON, and
+// read back; the result must write the same again, and generate the same
+// PostScript.
+// The scenes come from a small seeded generator, so failures repeat; the
+// seed is in the assertion message.
+mod common;
+
+use common::{commands, generate, json};
+use pstools::path::{FillRule, PathBuilder};
+use pstools::*;
+
+// xorshift64 -- enough randomness for building scenes
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+    // Coordinates, with fractions that don't print exactly in decimal
+    fn coord(&mut self) -> f32 {
+        (self.below(20000) as f32 - 5000.0) / 7.0
+    }
+    fn unit(&mut self) -> f32 {
+        self.below(1001) as f32 / 1000.0
+    }
+    fn size(&mut self) -> f32 {
+        1.0 + self.below(500) as f32 / 3.0
+    }
+    fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+        choices[self.below(choices.len() as u64) as usize]
+    }
+    // Strings with the characters that need care -- quotes, backslashes,
+    // newlines, hash marks, dollar signs, spaces at the ends, and text
+    // beyond ASCII.
+    fn string(&mut self) -> String {
+        let pieces = [
+            "plain", " ", "\"quoted\"", "back\\slash", "new\nline", "tab\there", "# not a comment",
+            "caf\u{e9}", "\u{263a}", "(parens)", "a(b", ")", "", "${x}", "$5",
+        ];
+        (0..self.below(4)).map(|_| self.pick(&pieces)).collect()
+    }
+}
+
+fn random_path(rng: &mut Rng) -> PathBuilder {
+    let mut path = PathBuilder::new();
+    path.move_to(rng.coord(), rng.coord());
+    for _ in 0..1 + rng.below(5) {
+        match rng.below(5) {
+            0 => path.line_to(rng.coord(), rng.coord()),
+            1 => path.curve_to(rng.coord(), rng.coord(), rng.coord(), rng.coord(), rng.coord(), rng.coord()),
+            2 => path.quad_to(rng.coord(), rng.coord(), rng.coord(), rng.coord()),
+            3 => path.arc(rng.coord(), rng.coord(), rng.size(), rng.coord(), rng.coord()),
+            _ => path.close_path().move_to(rng.coord(), rng.coord()),
+        };
+    }
+    if rng.below(2) == 0 {
+        path.set_fill_rule(FillRule::EvenOdd);
+    }
+    path
+}
+
+// Adds a random sequence of operations, keeping gsave/grestore, the
+// clipping regions, and the layers and objects balanced.
+fn random_events(pst: &mut PSTool, rng: &mut Rng, count: u64, symbols: &[&str]) {
+    let mut saved = 0;
+    let mut clips = 0;
+    // Open layers and objects, true for an object
+    let mut groups = Vec::new();
+    for _ in 0..count {
+        match rng.below(36) {
+            0 => pst.add_box(rng.coord(), rng.coord(), rng.coord(), rng.coord()),
+            1 => pst.add_line(rng.coord(), rng.coord(), rng.coord(), rng.coord()),
+            2 => pst.add_circle(rng.coord(), rng.coord(), rng.size()),
+            3 => pst.add_arc(rng.coord(), rng.coord(), rng.size(), rng.coord(), rng.coord()),
+            4 => pst.add_wedge(rng.coord(), rng.coord(), rng.size(), rng.coord(), rng.coord()),
+            5 => pst.add_ellipse(rng.coord(), rng.coord(), rng.size(), rng.size(), rng.coord()),
+            6 => pst.add_rounded_box(rng.coord(), rng.coord(), rng.coord(), rng.coord(), rng.size()),
+            7 => pst.add_curve(rng.coord(), rng.coord(), rng.coord(), rng.coord(), rng.coord(), rng.coord()),
+            8 => pst.add_cubic(
+                rng.coord(),
+                rng.coord(),
+                rng.coord(),
+                rng.coord(),
+                rng.coord(),
+                rng.coord(),
+                rng.coord(),
+                rng.coord(),
+            ),
+            9 => pst.add_quadratic(rng.coord(), rng.coord(), rng.coord(), rng.coord(), rng.coord(), rng.coord()),
+            10 => {
+                let path = random_path(rng);
+                pst.add_path(&path, rng.below(2) == 0, rng.below(2) == 0);
+            }
+            11 => {
+                let head = rng.pick(&["none", "open", "filled"]).parse::<ArrowHead>().unwrap();
+                pst.set_arrow_head(head, rng.size(), rng.size());
+                pst.add_arrow(rng.coord(), rng.coord(), rng.coord(), rng.coord());
+            }
+            12 => pst.add_double_arrow(rng.coord(), rng.coord(), rng.coord(), rng.coord()),
+            13 => pst.add_curved_arrow(rng.coord(), rng.coord(), rng.coord(), rng.coord(), rng.coord(), rng.coord()),
+            14 => {
+                let label = rng.string();
+                pst.add_dimension(rng.coord(), rng.coord(), rng.coord(), rng.coord(), rng.coord() / 10.0, label);
+            }
+            15 => {
+                let data = (0..rng.below(8)).map(|_| rng.coord()).collect();
+                pst.chart(data, 0.0, 0.0, rng.coord(), rng.coord(), rng.coord(), rng.coord());
+            }
+            16 => {
+                let a = if rng.below(2) == 0 { 1.0 } else { rng.unit() };
+                pst.set_color(rng.unit(), rng.unit(), rng.unit(), a);
+            }
+            17 => pst.set_fill(rng.below(2) == 0),
+            18 => {
+                let pattern = rng.pick(&["solid", "hatch", "crosshatch", "dots", "checker"]);
+                pst.set_fill_pattern(pattern.parse().unwrap(), rng.size(), rng.coord());
+            }
+            19 => pst.set_line_width(rng.size() / 10.0),
+            20 => {
+                let dashes = (0..rng.below(4)).map(|_| rng.size()).collect();
+                pst.set_dash(dashes, rng.size());
+            }
+            21 => {
+                pst.set_line_cap(rng.pick(&["butt", "round", "square"]).parse().unwrap());
+                pst.set_line_join(rng.pick(&["miter", "round", "bevel"]).parse().unwrap());
+                pst.set_miter_limit(rng.size());
+            }
+            22 => pst.set_font(rng.size() / 10.0, rng.pick(&["Times-Roman", "Helvetica", "Odd Font"]).to_string()),
+            23 => pst.add_text(rng.coord(), rng.coord(), rng.string()),
+            24 => pst.add_text_rotated(rng.coord(), rng.coord(), rng.coord(), rng.string()),
+            25 => pst.add_text_centered(rng.coord(), rng.coord(), rng.string()),
+            26 => {
+                pst.set_text_ln(rng.coord(), rng.coord());
+                pst.add_text_ln(rng.string());
+                pst.add_text_ln(rng.string());
+            }
+            27 => pst.add_comment(rng.string()),
+            28 => pst.add_postscript(rng.string()),
+            29 => pst.add_note(rng.string()),
+            30 => {
+                if saved > 0 && rng.below(2) == 0 {
+                    pst.add_grestore();
+                    saved -= 1;
+                } else {
+                    pst.add_gsave();
+                    pst.add_scale(rng.size() / 100.0);
+                    pst.add_translate(rng.coord(), rng.coord());
+                    saved += 1;
+                }
+            }
+            31 => {
+                if clips > 0 && rng.below(2) == 0 {
+                    pst.pop_clip();
+                    clips -= 1;
+                } else if rng.below(2) == 0 {
+                    pst.push_clip_rect(rng.coord(), rng.coord(), rng.coord(), rng.coord());
+                    clips += 1;
+                } else {
+                    pst.push_clip_path(&random_path(rng));
+                    clips += 1;
+                }
+            }
+            32 | 33 => {
+                match groups.last() {
+                    Some(&object) if rng.below(2) == 0 => {
+                        if object {
+                            pst.end_object();
+                        } else {
+                            pst.end_layer();
+                        }
+                        groups.pop();
+                    }
+                    _ if rng.below(2) == 0 => {
+                        pst.begin_layer(rng.string());
+                        groups.push(false);
+                    }
+                    _ => {
+                        let properties: Vec<(String, String)> =
+                            (0..rng.below(3)).map(|_| (rng.string(), rng.string())).collect();
+                        let properties: Vec<(&str, &str)> =
+                            properties.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+                        pst.begin_object(rng.string(), &properties);
+                        groups.push(true);
+                    }
+                }
+            }
+            _ => {
+                if !symbols.is_empty() {
+                    let name = rng.pick(symbols);
+                    let orient = rng.pick(&["N", "S", "E", "W", "FN", "FS", "FE", "FW"]).parse().unwrap();
+                    pst.place_symbol(name, rng.coord(), rng.coord(), orient, rng.size() / 50.0);
+                }
+            }
+        }
+    }
+    for _ in 0..clips {
+        pst.pop_clip();
+    }
+    for _ in 0..saved {
+        pst.add_grestore();
+    }
+    while let Some(object) = groups.pop() {
+        if object {
+            pst.end_object();
+        } else {
+            pst.end_layer();
+        }
+    }
+}
+
+fn random_scene(seed: u64) -> PSTool {
+    let mut rng = Rng(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1);
+    let mut pst = PSTool::new();
+    pst.set_deterministic(true);
+    pst.set_title(rng.string());
+    if rng.below(2) == 0 {
+        pst.set_for(rng.string());
+    }
+    match rng.below(4) {
+        0 => {
+            let mut page = PageSetup::new(rng.pick(&["letter", "a4", "a3", "500.5x700"]).parse().unwrap());
+            page.orientation = rng.pick(&["portrait", "landscape"]).parse().unwrap();
+            page.margin = rng.size() / 10.0;
+            page.center = rng.below(2) == 0;
+            pst.set_page(page);
+        }
+        1 => pst.set_max_size(rng.size()),
+        2 => pst.set_bounds(rng.coord(), rng.coord(), rng.coord(), rng.coord()),
+        _ => pst.set_border(rng.size() / 10.0),
+    }
+    pst.set_color_mode(rng.pick(&["rgb", "cmyk", "gray"]).parse().unwrap());
+
+    // A symbol holding another, one with an awkward name, and a
+    // redefinition that only the later placements see.
+    let mut via = PSTool::new();
+    random_events(&mut via, &mut rng, 3, &[]);
+    let mut cell = PSTool::new();
+    cell.define_symbol("via".to_string(), &via);
+    random_events(&mut cell, &mut rng, 6, &["via"]);
+    pst.define_symbol("cell".to_string(), &cell);
+    pst.define_symbol("odd name".to_string(), &via);
+    random_events(&mut pst, &mut rng, 30, &["cell", "odd name"]);
+    let mut other = PSTool::new();
+    random_events(&mut other, &mut rng, 4, &[]);
+    pst.define_symbol("cell".to_string(), &other);
+    random_events(&mut pst, &mut rng, 30, &["cell", "odd name"]);
+    pst
+}
+
+// Compares two texts word by word, allowing numbers to differ slightly;
+// symbol placements are written relative to the corner of the symbol,
+// which can move the last bit of a coordinate.
+fn assert_close(a: &str, b: &str, what: &str, seed: u64) {
+    let (wa, wb): (Vec<&str>, Vec<&str>) = (a.split_whitespace().collect(), b.split_whitespace().collect());
+    assert_eq!(wa.len(), wb.len(), "seed {}: {} differ in length", seed, what);
+    for (x, y) in wa.iter().zip(wb.iter()) {
+        if x == y {
+            continue;
+        }
+        match (x.parse::<f32>(), y.parse::<f32>()) {
+            (Ok(x), Ok(y)) if (x - y).abs() <= 1e-3 * (1.0 + x.abs()) => {}
+            _ => panic!("seed {}: {} differ at '{}' and '{}'", seed, what, x, y),
+        }
+    }
+}
+
+#[test]
+fn roundtrip() {
+    for seed in 1..=200 {
+        let original = random_scene(seed);
+        let text = commands(&original);
+        let mut parsed = PSTool::new();
+        if let Err(errors) = parsed.parse_str(&text, ParseMode::Strict) {
+            panic!("seed {}: {}\n{}", seed, errors[0], text);
+        }
+        assert_close(&text, &commands(&parsed), "commands", seed);
+        assert_close(&format!("{:?}", original.bbox()), &format!("{:?}", parsed.bbox()), "bounding boxes", seed);
+        assert_close(&generate(&original, "a.ps"), &generate(&parsed, "b.ps"), "PostScript", seed);
+    }
+}
+
+#[test]
+fn json_roundtrip() {
+    for seed in 1..=200 {
+        let original = random_scene(seed);
+        let text = json(&original);
+        let mut parsed = PSTool::new();
+        if let Err(error) = parsed.parse_json(&text) {
+            panic!("seed {}: {}\n{}", seed, error, text);
+        }
+        assert_close(&text, &json(&parsed), "JSON", seed);
+        assert_close(&format!("{:?}", original.bbox()), &format!("{:?}", parsed.bbox()), "bounding boxes", seed);
+        assert_close(&generate(&original, "c.ps"), &generate(&parsed, "d.ps"), "PostScript", seed);
+    }
+}
+
+#[test]
+fn json_matches_api() {
+    // A scene written by hand in JSON draws the same as the API calls.
+    let scene = r#"{
+      "format": "pstools-scene",
+      "version": 1,
+      "settings": {"title": "cells", "deterministic": true, "border": 5},
+      "notes": ["iteration 10"],
+      "symbols": [{"name": "via", "scene": {"events": [{"type": "box", "llx": 0, "lly": 0, "urx": 4, "ury": 4}]}}],
+      "events": [
+        {"type": "layer", "name": "placement", "events": [
+          {"type": "color", "r": 0.2, "g": 0.4, "b": 0.6, "a": 0.5},
+          {"type": "fill", "fill": true, "pattern": "hatch", "spacing": 4, "angle": 45},
+          {"type": "box", "llx": 0, "lly": 0, "urx": 40, "ury": 20},
+          {"type": "fill", "fill": false},
+          {"type": "place", "symbol": "via", "x": 10, "y": 10, "orientation": "E", "scale": 2}
+        ]},
+        {"type": "dash", "pattern": [3, 1], "offset": 0},
+        {"type": "path", "fill": true, "stroke": true, "fill_rule": "evenodd",
+         "segments": [["M", 0, 0], ["L", 30, 0], ["Q", 40, 15, 30, 30], ["Z"]]},
+        {"type": "arrow_style", "head": "open", "length": 8, "width": 5},
+        {"type": "arrow", "x1": 0, "y1": 50, "x2": 40, "y2": 50},
+        {"type": "font", "size": 10, "name": "Helvetica"},
+        {"type": "text", "x": 20, "y": 60, "text": "caf\u00e9 \"A\"", "align": "center"}
+      ]
+    }"#;
+    let mut parsed = PSTool::new();
+    assert_eq!(parsed.parse_json(scene), Ok(13));
+
+    let mut pst = PSTool::new();
+    pst.set_title("cells".to_string());
+    pst.set_deterministic(true);
+    pst.set_border(5.0);
+    pst.add_note("iteration 10".to_string());
+    let mut via = PSTool::new();
+    via.add_box(0.0, 0.0, 4.0, 4.0);
+    pst.define_symbol("via".to_string(), &via);
+    pst.begin_layer("placement".to_string());
+    pst.set_color(0.2, 0.4, 0.6, 0.5);
+    pst.set_fill_pattern(FillPattern::Hatch, 4.0, 45.0);
+    pst.add_box(0.0, 0.0, 40.0, 20.0);
+    pst.set_fill(false);
+    pst.place_symbol("via", 10.0, 10.0, Orientation::E, 2.0);
+    pst.end_layer();
+    pst.set_dash(vec![3.0, 1.0], 0.0);
+    let mut path = PathBuilder::new();
+    path.set_fill_rule(FillRule::EvenOdd);
+    path.move_to(0.0, 0.0).line_to(30.0, 0.0).quad_to(40.0, 15.0, 30.0, 30.0).close_path();
+    pst.add_path(&path, true, true);
+    pst.set_arrow_head(ArrowHead::Open, 8.0, 5.0);
+    pst.add_arrow(0.0, 50.0, 40.0, 50.0);
+    pst.set_font(10.0, "Helvetica".to_string());
+    pst.add_text_centered(20.0, 60.0, "caf\u{e9} \"A\"".to_string());
+
+    assert_eq!(commands(&parsed), commands(&pst));
+    assert_eq!(generate(&parsed, "e.ps"), generate(&pst, "f.ps"));
+}
+
+#[test]
+fn json_errors() {
+    let mut pst = PSTool::new();
+    let error = pst.parse_json("{\"events\": [\n  {\"type\": \"box\", \"llx\": 1,}\n]}").unwrap_err();
+    assert_eq!(error.to_string(), "line 2, column 28: expected a field name, found '}'");
+    let error = pst
+        .parse_json(r#"{"events": [{"type": "circle", "x": 1, "y": 2}]}"#)
+        .unwrap_err();
+    assert_eq!(error.to_string(), "scene.events[0]: missing radius");
+    let error = pst
+        .parse_json(r#"{"events": [{"type": "layer", "name": "a", "events": [{"type": "line_cap", "cap": "flat"}]}]}"#)
+        .unwrap_err();
+    assert_eq!(error.to_string(), "scene.events[0].events[0].cap: unknown line cap flat");
+}
+
+// A layer or object left open is read back open, and one with an error
+// is ended, so that the events before the error stay balanced.
+#[test]
+fn json_open_groups() {
+    let mut pst = PSTool::new();
+    pst.begin_layer("cells".to_string());
+    pst.add_box(0.0, 0.0, 1.0, 1.0);
+    pst.begin_object("u1".to_string(), &[]);
+    pst.add_line(0.0, 0.0, 1.0, 1.0);
+    let text = json(&pst);
+    assert!(text.contains("\"open\": true"), "{}", text);
+    let mut parsed = PSTool::new();
+    parsed.parse_json(&text).unwrap();
+    assert_eq!(commands(&parsed), commands(&pst));
+    assert_eq!(json(&parsed), text);
+
+    let mut pst = PSTool::new();
+    let scene = r#"{"events": [{"type": "layer", "name": "a", "events": [
+        {"type": "object", "id": "u1", "events": [{"type": "box", "llx": 0, "lly": 0, "urx": 1, "ury": 1}, {"type": "circle"}]}]}]}"#;
+    assert!(pst.parse_json(scene).is_err());
+    assert_eq!(commands(&pst), "layer \"a\"\nobject u1\nbox 0 0 1 1\nendobject\nendlayer\n");
+}
+
+// Names are written as single words when they can be; a parenthesis
+// would group with the rest of the line, so those are quoted.
+#[test]
+fn names_with_parentheses() {
+    let mut cell = PSTool::new();
+    cell.add_box(0.0, 0.0, 2.0, 1.0);
+    let mut pst = PSTool::new();
+    pst.define_symbol("a(b".to_string(), &cell);
+    pst.place_symbol("a(b", 5.0, 5.0, Orientation::N, 2.0);
+    pst.add_object("x)y".to_string(), &[], |p| p.add_circle(0.0, 0.0, 1.0));
+    let text = commands(&pst);
+    assert!(text.contains("place \"a(b\" 5 5 N 2\n"), "{}", text);
+    let mut parsed = PSTool::new();
+    assert!(parsed.parse_str(&text, ParseMode::Strict).is_ok(), "{}", text);
+    assert_eq!(commands(&parsed), text);
+
+    // The end of the line isn't part of the last word
+    let errors = parsed.parse_str("place a(b 5 5 N 2\n", ParseMode::Strict).unwrap_err();
+    assert!(!errors[0].message.contains('\n'), "{}", errors[0]);
+}
+
+#[test]
+fn hand_edits() {
+    // The written commands are meant to be edited; comments and blank
+    // lines can be added, and the result still parses.
+    let mut pst = PSTool::new();
+    pst.set_font(12.0, "Helvetica".to_string());
+    pst.add_text(10.0, 20.0, "say \"hi\"".to_string());
+    let text = commands(&pst);
+    assert_eq!(text, "font 12 \"Helvetica\"\ntext 10 20 \"say \\\"hi\\\"\"\n");
+    let edited = format!("# edited by hand\n\n{}box 0 0 5 5\n", text);
+    let mut parsed = PSTool::new();
+    assert_eq!(parsed.parse_str(&edited, ParseMode::Strict), Ok(3));
+}
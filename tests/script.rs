@@ -0,0 +1,144 @@
+// Tests for the scripting side of the text format: variables, ${}
+// interpolation, expressions, for loops, macros, and include.  Scripts
+// are compared with the same figure drawn through the API, by way of
+// write_commands.
+mod common;
+
+use common::{commands, scratch_dir};
+use pstools::*;
+use std::fs;
+
+fn run(script: &str) -> PSTool {
+    let mut pst = PSTool::new();
+    if let Err(errors) = pst.parse_str(script, ParseMode::Strict) {
+        panic!("{}\n{}", errors[0], script);
+    }
+    pst
+}
+
+fn error(script: &str) -> String {
+    let mut pst = PSTool::new();
+    match pst.parse_str(script, ParseMode::Strict) {
+        Ok(_) => panic!("no error for\n{}", script),
+        Err(errors) => errors[0].to_string(),
+    }
+}
+
+#[test]
+fn for_grid() {
+    let pst = run("set size 4\n\
+                   for i 0 2\n\
+                   for j 0 1\n\
+                   box i*10 j*10 i*10+size j*10+size\n\
+                   end\n\
+                   end\n");
+    let mut expected = PSTool::new();
+    for i in 0..=2 {
+        for j in 0..=1 {
+            let (x, y) = (i as f32 * 10.0, j as f32 * 10.0);
+            expected.add_box(x, y, x + 4.0, y + 4.0);
+        }
+    }
+    assert_eq!(commands(&pst), commands(&expected));
+
+    // Steps can go down, and the end is included
+    let pst = run("for x 1 0 -0.5\nline x 0 x 1\nend\n");
+    let mut expected = PSTool::new();
+    for x in [1.0, 0.5, 0.0] {
+        expected.add_line(x, 0.0, x, 1.0);
+    }
+    assert_eq!(commands(&pst), commands(&expected));
+
+    // The loop variable is restored afterwards
+    let pst = run("set i 7\nfor i 0 1\nend\ncircle 0 0 i\n");
+    let mut expected = PSTool::new();
+    expected.add_circle(0.0, 0.0, 7.0);
+    assert_eq!(commands(&pst), commands(&expected));
+}
+
+#[test]
+fn macro_parameters() {
+    let pst = run("set w 7\n\
+                   define cell x w\n\
+                   box x 0 x+w w\n\
+                   end\n\
+                   cell 10 3\n\
+                   cell 20 max(1,2)\n\
+                   box 0 0 w w\n");
+    let mut expected = PSTool::new();
+    expected.add_box(10.0, 0.0, 13.0, 3.0);
+    expected.add_box(20.0, 0.0, 22.0, 2.0);
+    expected.add_box(0.0, 0.0, 7.0, 7.0);
+    assert_eq!(commands(&pst), commands(&expected));
+
+    // A parameter that wasn't a variable before the call isn't one after
+    assert!(error("define cell x\nbox x 0 1 1\nend\ncell 1\nbox x 0 1 1\n").contains("unknown variable 'x'"));
+    assert!(error("define cell x\nend\ncell\n").contains("missing x"));
+    assert!(error("define box\nend\n").contains("box is a command"));
+}
+
+#[test]
+fn interpolation() {
+    let pst = run("set x 2.5\n\
+                   comment x is ${x}, twice is ${x*2}\n\
+                   comment \"x is \\${x}\"\n\
+                   text 0 0 \"[${x+1}]\"\n");
+    let mut expected = PSTool::new();
+    expected.add_comment("x is 2.5, twice is 5".to_string());
+    expected.add_comment("x is ${x}".to_string());
+    expected.add_text(0.0, 0.0, "[3.5]".to_string());
+    assert_eq!(commands(&pst), commands(&expected));
+
+    assert!(error("comment ${y}\n").contains("unknown variable 'y'"));
+    assert!(error("comment ${1\n").contains("missing }"));
+}
+
+#[test]
+fn expressions() {
+    let pst = run("set a 3\nset b (a + 1) * 2 - -1\ncircle a%2 b/3 sqrt(16)+abs(-1)\n");
+    let mut expected = PSTool::new();
+    expected.add_circle(1.0, 3.0, 5.0);
+    assert_eq!(commands(&pst), commands(&expected));
+
+    assert!(error("set a 1 +\n").contains("missing a value"));
+    assert!(error("box 0 0 (1 1\n").contains("missing ')'"));
+    let deep = format!("set a {}1{}\n", "(".repeat(5000), ")".repeat(5000));
+    assert!(error(&deep).contains("nested too deeply"));
+    let signs = format!("set a {}1\n", "-".repeat(5000));
+    assert!(error(&signs).contains("nested too deeply"));
+}
+
+#[test]
+fn limits_and_blocks() {
+    assert!(error("for i 0 10\nbox 0 0 1 1\n").contains("for has no end"));
+    assert!(error("define m\nbox 0 0 1 1\n").contains("define has no end"));
+    assert!(error("end\n").contains("no for or define to end"));
+    assert!(error("for i 0 1 0\nend\n").contains("the step can't be zero"));
+    assert!(error("for i 0 2000000\nend\n").contains("too many iterations"));
+    assert!(error("define m\nm\nend\nm\n").contains("nested too deeply"));
+}
+
+#[test]
+fn include() {
+    let dir = scratch_dir();
+    fs::write(dir.join("cell.txt"), "box 0 0 w w\n").unwrap();
+    fs::write(dir.join("main.txt"), "set w 3\ninclude cell.txt\n").unwrap();
+    fs::write(dir.join("self.txt"), "include self.txt\n").unwrap();
+
+    let mut pst = PSTool::new();
+    let result = pst.parse_with(&dir.join("main.txt").to_string_lossy(), ParseMode::Strict);
+    let mut self_pst = PSTool::new();
+    let self_result = self_pst.parse_with(&dir.join("self.txt").to_string_lossy(), ParseMode::Strict);
+    let missing = error("include no_such_file.txt\n");
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(result.is_ok());
+    let mut expected = PSTool::new();
+    expected.add_box(0.0, 0.0, 3.0, 3.0);
+    assert_eq!(commands(&pst), commands(&expected));
+
+    let errors = self_result.unwrap_err();
+    assert!(errors[0].message.contains("nested too deeply"), "{}", errors[0]);
+    assert!(errors[0].file.ends_with("self.txt"));
+    assert!(missing.contains("can't open"));
+}
@@ -0,0 +1,67 @@
+// Tests for line and fill styles: the values that are refused, and how
+// patterns are scaled.
+mod common;
+
+use common::{generate, generate_svg};
+use pstools::*;
+
+// A pattern needs a spacing of more than zero, from any source.
+#[test]
+fn fill_spacing() {
+    let mut pst = PSTool::new();
+    let errors = pst.parse_str("fill hatch 0\nfill dots -2 45\n", ParseMode::Lenient).unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].message, "fill: the spacing must be more than zero (usage: fill [1|0], or fill pattern spacing [angle])");
+    let error = pst
+        .parse_json(r#"{"events": [{"type": "fill", "pattern": "hatch", "spacing": 0}]}"#)
+        .unwrap_err();
+    assert_eq!(error.to_string(), "scene.events[0].spacing: the spacing must be more than zero");
+    pst.set_fill_pattern(FillPattern::Hatch, 0.0, 0.0);
+    assert!(pst.is_empty());
+}
+
+// Dash lengths can't be negative, or all zero.
+#[test]
+fn dash_lengths() {
+    let mut pst = PSTool::new();
+    let errors = pst.parse_str("dash 0 3 -1\ndash 0 0 0\ndash 0\ndash 1 0 2\n", ParseMode::Lenient).unwrap_err();
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+        messages,
+        [
+            "<string>:1:10: dash: dash lengths can't be negative (usage: dash offset [d1 d2 ...])",
+            "<string>:2:10: dash: dash lengths can't all be zero (usage: dash offset [d1 d2 ...])",
+        ]
+    );
+    assert_eq!(pst.len(), 2);
+    let error = pst.parse_json(r#"{"events": [{"type": "dash", "pattern": [-1]}]}"#).unwrap_err();
+    assert_eq!(error.to_string(), "scene.events[0].pattern: dash lengths can't be negative");
+    pst.set_dash(vec![0.0], 0.0);
+    assert_eq!(pst.len(), 2);
+}
+
+// A pattern is scaled along with the shape it fills, in both outputs.
+// The PostScript pattern is made at the fill, under the scaled CTM, and
+// the SVG pattern is in the user space of the scaled group.
+#[test]
+fn patterns_follow_scale() {
+    let mut pst = PSTool::new();
+    pst.set_deterministic(true);
+    pst.add_gsave();
+    pst.add_scale(2.0);
+    pst.set_fill_pattern(FillPattern::Hatch, 4.0, 30.0);
+    pst.add_box(0.0, 0.0, 10.0, 10.0);
+    pst.add_grestore();
+    let output = generate(&pst, "figure.ps");
+    assert!(output.contains("/pat0 {<< /PatternType 1 "));
+    assert!(output.contains("/BBox [0 0 4 4] "));
+    assert!(output.contains(" 30 matrix rotate makepattern} def\n"));
+    let scale = output.find("2 2 scale\n").unwrap();
+    let fill = output.find("pat0 setcolor 0 0 10 10 bf").unwrap();
+    assert!(scale < fill);
+
+    let svg = generate_svg(&pst);
+    let group = svg.find("<g transform=\"scale(2)\">").unwrap();
+    let pattern = svg.find("<pattern id=\"pat0\" patternUnits=\"userSpaceOnUse\" width=\"4\" height=\"4\"").unwrap();
+    assert!(group < pattern);
+}
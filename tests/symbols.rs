@@ -0,0 +1,16 @@
+// Tests for symbols: definition, placement, and embedding.
+use pstools::*;
+
+// An unknown name places nothing, and says so.
+#[test]
+fn unknown_symbol() {
+    let mut cell = PSTool::new();
+    cell.add_box(0.0, 0.0, 4.0, 2.0);
+    let mut pst = PSTool::new();
+    pst.define_symbol("cell".to_string(), &cell);
+    assert!(pst.place_symbol("cell", 10.0, 10.0, Orientation::N, 1.0));
+    assert!(!pst.place_symbol("nand", 10.0, 10.0, Orientation::N, 1.0));
+    assert_eq!(pst.len(), 1);
+    // The corner goes at (x, y), from the extents found at definition
+    assert_eq!(pst.bbox(), (10.0, 10.0, 15.0, 13.0));
+}